@@ -0,0 +1,187 @@
+use super::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Caches the answers of the inner resolver for as long as their TTL allows.
+///
+/// Names that do not exist are remembered for the `negative_ttl`,
+/// failed lookups are not cached at all.
+#[derive(Debug)]
+pub struct CachedResolver<R> {
+    inner: R,
+    capacity: usize,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    entries: Mutex<HashMap<(String, DnsRecordType), CacheEntry>>,
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    expires: Instant,
+    records: Option<Vec<DnsRecord>>,
+}
+
+impl<R: DnsResolver> CachedResolver<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            capacity: 10_000,
+            max_ttl: Duration::from_secs(24 * 3600),
+            negative_ttl: Duration::from_secs(60),
+            entries: Mutex::default(),
+        }
+    }
+    /// How many answers to keep at most
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+    /// Do not keep answers longer than this, whatever their TTL
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+    /// How long to remember that a record does not exist
+    pub fn with_negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+    /// Forget all the answers
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, DnsRecordType), CacheEntry>> {
+        // the map is consistent even if another thread panicked
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn cached(&self, key: &(String, DnsRecordType)) -> Option<DnsResult> {
+        let now = Instant::now();
+        let entry = self.lock().get(key).cloned()?;
+        let ttl = entry.expires.checked_duration_since(now)?;
+        Some(match entry.records {
+            Some(records) => Ok(DnsAnswer { records, ttl }),
+            None => Err(DnsError::NotFound),
+        })
+    }
+    fn store(&self, key: (String, DnsRecordType), result: &DnsResult) {
+        let (ttl, records) = match result {
+            Ok(answer) => (answer.ttl.min(self.max_ttl), Some(answer.records.clone())),
+            Err(DnsError::NotFound) => (self.negative_ttl, None),
+            Err(DnsError::Failed(_)) => return,
+        };
+        if ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.lock();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.expires > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            // make room by dropping the answer that would expire first
+            let first = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone());
+            if let Some(first) = first {
+                entries.remove(&first);
+            }
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                expires: now + ttl,
+                records,
+            },
+        );
+    }
+}
+
+impl<R: DnsResolver> DnsResolver for CachedResolver<R> {
+    fn query<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+        record_type: DnsRecordType,
+    ) -> S2Fut<'f, DnsResult>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        Box::pin(async move {
+            let key = (normalize(name), record_type);
+            if let Some(result) = self.cached(&key) {
+                return result;
+            }
+            let result = self.inner.query(key.0.as_str(), record_type).await;
+            self.store(key, &result);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Default)]
+    struct Counting {
+        inner: StaticResolver,
+        queries: AtomicUsize,
+    }
+
+    impl DnsResolver for Counting {
+        fn query<'a, 'n, 'f>(
+            &'a self,
+            name: &'n str,
+            record_type: DnsRecordType,
+        ) -> S2Fut<'f, DnsResult>
+        where
+            'a: 'f,
+            'n: 'f,
+        {
+            self.queries.fetch_add(1, Ordering::Relaxed);
+            self.inner.query(name, record_type)
+        }
+    }
+
+    fn counting() -> Counting {
+        Counting {
+            inner: StaticResolver::default()
+                .with_txt("example.org", "v=spf1 -all")
+                .with_failure("broken.example.org", DnsRecordType::Txt),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn answers_are_cached() {
+        async_std::task::block_on(async move {
+            let sut = CachedResolver::new(counting());
+            for _ in 0..3 {
+                assert_eq!(
+                    sut.lookup_txt("Example.org.").await,
+                    Ok(vec!["v=spf1 -all".to_owned()])
+                );
+                assert_eq!(
+                    sut.lookup_txt("nowhere.example.org").await,
+                    Err(DnsError::NotFound)
+                );
+            }
+            assert_eq!(sut.inner.queries.load(Ordering::Relaxed), 2);
+        })
+    }
+
+    #[test]
+    fn expired_and_failed_answers_are_queried_again() {
+        async_std::task::block_on(async move {
+            let sut = CachedResolver::new(counting()).with_max_ttl(Duration::ZERO);
+            sut.lookup_txt("example.org").await.expect("txt");
+            sut.lookup_txt("example.org").await.expect("txt");
+            assert!(sut.lookup_txt("broken.example.org").await.is_err());
+            assert!(sut.lookup_txt("broken.example.org").await.is_err());
+            assert_eq!(sut.inner.queries.load(Ordering::Relaxed), 4);
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use super::*;
+use std::collections::HashMap;
+
+/// DNS records given in memory, mainly for tests
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    records: HashMap<(String, DnsRecordType), Vec<DnsRecord>>,
+    failures: Vec<(String, DnsRecordType)>,
+}
+
+impl StaticResolver {
+    /// How long the static answers may be cached
+    pub const TTL: Duration = Duration::from_secs(3600);
+
+    /// Add a record of the name
+    pub fn with_record(mut self, name: &str, record: DnsRecord) -> Self {
+        self.records
+            .entry((normalize(name), record.record_type()))
+            .or_default()
+            .push(record);
+        self
+    }
+    /// Add an IP address of the name as an A or AAAA record
+    pub fn with_ip(self, name: &str, ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => self.with_record(name, DnsRecord::A(ip)),
+            IpAddr::V6(ip) => self.with_record(name, DnsRecord::Aaaa(ip)),
+        }
+    }
+    /// Add a mail exchanger of the name
+    pub fn with_mx(self, name: &str, preference: u16, exchange: &str) -> Self {
+        self.with_record(
+            name,
+            DnsRecord::Mx(MxRecord {
+                preference,
+                exchange: normalize(exchange),
+            }),
+        )
+    }
+    /// Add a text record of the name
+    pub fn with_txt(self, name: &str, text: impl Into<String>) -> Self {
+        self.with_record(name, DnsRecord::Txt(text.into()))
+    }
+    /// Add a name of the IP to the reverse zone
+    pub fn with_ptr(self, ip: IpAddr, name: &str) -> Self {
+        self.with_record(reverse_name(ip).as_str(), DnsRecord::Ptr(normalize(name)))
+    }
+    /// Fail the lookups of the records of the name as if the DNS was not reachable
+    pub fn with_failure(mut self, name: &str, record_type: DnsRecordType) -> Self {
+        self.failures.push((normalize(name), record_type));
+        self
+    }
+}
+
+impl DnsResolver for StaticResolver {
+    fn query<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+        record_type: DnsRecordType,
+    ) -> S2Fut<'f, DnsResult>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        let key = (normalize(name), record_type);
+        let result = if self.failures.contains(&key) {
+            Err(DnsError::Failed(format!(
+                "{} {:?} lookup failed",
+                key.0, key.1
+            )))
+        } else {
+            match self.records.get(&key) {
+                Some(records) => Ok(DnsAnswer {
+                    records: records.clone(),
+                    ttl: Self::TTL,
+                }),
+                None => Err(DnsError::NotFound),
+            }
+        };
+        Box::pin(ready(result))
+    }
+}
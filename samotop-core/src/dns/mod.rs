@@ -0,0 +1,334 @@
+//! DNS lookups shared by the checks, such as SPF, FCrDNS or DKIM.
+//!
+//! The checks take any `DnsResolver`. Without one, they use the `default_resolver()`
+//! which is created once and caches the answers for all sessions.
+//! `StaticResolver` serves records from memory so that tests run offline.
+
+mod cache;
+mod fixed;
+#[cfg(feature = "dns")]
+mod trust;
+
+pub use self::cache::*;
+pub use self::fixed::*;
+#[cfg(feature = "dns")]
+pub use self::trust::*;
+
+use crate::common::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+/// The result of a DNS query
+pub type DnsResult = std::result::Result<DnsAnswer, DnsError>;
+
+/// Resolves DNS records
+pub trait DnsResolver: fmt::Debug + Send + Sync {
+    /// Query the records of the given type for the name
+    fn query<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+        record_type: DnsRecordType,
+    ) -> S2Fut<'f, DnsResult>
+    where
+        'a: 'f,
+        'n: 'f;
+
+    /// IPv4 addresses of the name
+    fn lookup_a<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+    ) -> S2Fut<'f, std::result::Result<Vec<Ipv4Addr>, DnsError>>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        Box::pin(async move {
+            let answer = self.query(name, DnsRecordType::A).await?;
+            Ok(answer
+                .records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::A(ip) => Some(ip),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+
+    /// IPv6 addresses of the name
+    fn lookup_aaaa<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+    ) -> S2Fut<'f, std::result::Result<Vec<Ipv6Addr>, DnsError>>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        Box::pin(async move {
+            let answer = self.query(name, DnsRecordType::Aaaa).await?;
+            Ok(answer
+                .records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::Aaaa(ip) => Some(ip),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+
+    /// Mail exchangers of the name, the most preferred first.
+    /// A null MX (RFC 7505) has an empty exchange.
+    fn lookup_mx<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+    ) -> S2Fut<'f, std::result::Result<Vec<MxRecord>, DnsError>>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        Box::pin(async move {
+            let answer = self.query(name, DnsRecordType::Mx).await?;
+            let mut mxs = answer
+                .records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::Mx(mx) => Some(mx),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            mxs.sort_by_key(|mx| mx.preference);
+            Ok(mxs)
+        })
+    }
+
+    /// Text records of the name
+    fn lookup_txt<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+    ) -> S2Fut<'f, std::result::Result<Vec<String>, DnsError>>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        Box::pin(async move {
+            let answer = self.query(name, DnsRecordType::Txt).await?;
+            Ok(answer
+                .records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::Txt(text) => Some(text),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+
+    /// Names of the IP address from the reverse zone
+    fn lookup_ptr<'a, 'f>(
+        &'a self,
+        ip: IpAddr,
+    ) -> S2Fut<'f, std::result::Result<Vec<String>, DnsError>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let name = reverse_name(ip);
+            let answer = self.query(name.as_str(), DnsRecordType::Ptr).await?;
+            Ok(answer
+                .records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::Ptr(name) => Some(name),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+
+    /// DANE TLSA records (RFC 6698) of the name, such as `_25._tcp.mx.example.org`
+    fn lookup_tlsa<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+    ) -> S2Fut<'f, std::result::Result<Vec<TlsaRecord>, DnsError>>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        Box::pin(async move {
+            let answer = self.query(name, DnsRecordType::Tlsa).await?;
+            Ok(answer
+                .records
+                .into_iter()
+                .filter_map(|record| match record {
+                    DnsRecord::Tlsa(tlsa) => Some(tlsa),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+}
+
+impl<T: DnsResolver + ?Sized> DnsResolver for Arc<T> {
+    fn query<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+        record_type: DnsRecordType,
+    ) -> S2Fut<'f, DnsResult>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        T::query(self.as_ref(), name, record_type)
+    }
+}
+
+impl<T: DnsResolver + ?Sized> DnsResolver for Box<T> {
+    fn query<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+        record_type: DnsRecordType,
+    ) -> S2Fut<'f, DnsResult>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        T::query(self.as_ref(), name, record_type)
+    }
+}
+
+/// The record types the checks need
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DnsRecordType {
+    A,
+    Aaaa,
+    Mx,
+    Txt,
+    Ptr,
+    Tlsa,
+}
+
+/// A DNS record, names are without the trailing dot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecord {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Mx(MxRecord),
+    /// The character strings of the record joined together
+    Txt(String),
+    Ptr(String),
+    Tlsa(TlsaRecord),
+}
+
+impl DnsRecord {
+    pub fn record_type(&self) -> DnsRecordType {
+        match self {
+            DnsRecord::A(_) => DnsRecordType::A,
+            DnsRecord::Aaaa(_) => DnsRecordType::Aaaa,
+            DnsRecord::Mx(_) => DnsRecordType::Mx,
+            DnsRecord::Txt(_) => DnsRecordType::Txt,
+            DnsRecord::Ptr(_) => DnsRecordType::Ptr,
+            DnsRecord::Tlsa(_) => DnsRecordType::Tlsa,
+        }
+    }
+}
+
+/// A mail exchanger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxRecord {
+    pub preference: u16,
+    pub exchange: String,
+}
+
+/// A DANE TLSA record (RFC 6698)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsaRecord {
+    /// Certificate usage, 3 is DANE-EE
+    pub usage: u8,
+    /// 0 for the full certificate, 1 for the public key
+    pub selector: u8,
+    /// 0 for the raw data, 1 for SHA-256, 2 for SHA-512
+    pub matching: u8,
+    pub data: Vec<u8>,
+}
+
+/// The records found and how long they may be cached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsAnswer {
+    pub records: Vec<DnsRecord>,
+    pub ttl: Duration,
+}
+
+/// Failure to resolve a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsError {
+    /// There is no such record - permanent failure
+    NotFound,
+    /// The lookup failed - temporary failure
+    Failed(String),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::NotFound => f.write_str("no records found"),
+            DnsError::Failed(e) => write!(f, "lookup failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+/// The name to look up the PTR records of the IP, such as `1.2.0.192.in-addr.arpa`
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(format!("{:x}.{:x}.", byte & 0xf, byte >> 4).as_str());
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Names are compared without case and without the trailing dot
+pub(crate) fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            reverse_name("192.0.2.1".parse().expect("ip")),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::1".parse().expect("ip")),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn mx_are_sorted_by_preference() {
+        let sut = StaticResolver::default()
+            .with_mx("example.org", 20, "backup.example.org")
+            .with_mx("example.org", 10, "mx.example.org");
+        let mxs = async_std::task::block_on(sut.lookup_mx("example.org.")).expect("mx");
+        assert_eq!(
+            mxs.iter()
+                .map(|mx| mx.exchange.as_str())
+                .collect::<Vec<_>>(),
+            vec!["mx.example.org", "backup.example.org"]
+        );
+    }
+}
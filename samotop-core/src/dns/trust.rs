@@ -0,0 +1,155 @@
+use super::*;
+use async_std::future::timeout;
+use async_std_resolver::{
+    proto::{rr::RData, rr::RecordType, xfer::DnsRequestOptions},
+    resolver_from_system_conf, AsyncStdResolver, ResolveError,
+};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use trust_dns_resolver::error::ResolveErrorKind;
+
+/// The resolver shared by all the checks that were not given one.
+///
+/// It uses the system resolver configuration and caches the answers.
+pub fn default_resolver() -> Arc<dyn DnsResolver> {
+    static DEFAULT: OnceLock<Arc<dyn DnsResolver>> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| Arc::new(CachedResolver::new(TrustDnsResolver::default())))
+        .clone()
+}
+
+/// Resolves with trust-dns, by default using the system resolver configuration
+#[derive(Clone)]
+pub struct TrustDnsResolver {
+    // created on first use as the system configuration is read asynchronously
+    inner: Arc<Mutex<Option<AsyncStdResolver>>>,
+    timeout: Duration,
+}
+
+impl Default for TrustDnsResolver {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl From<AsyncStdResolver> for TrustDnsResolver {
+    fn from(resolver: AsyncStdResolver) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Some(resolver))),
+            ..Default::default()
+        }
+    }
+}
+
+impl TrustDnsResolver {
+    /// How long to wait for the DNS
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    async fn resolver(&self) -> std::result::Result<AsyncStdResolver, ResolveError> {
+        if let Some(resolver) = self.lock().as_ref() {
+            return Ok(resolver.clone());
+        }
+        let resolver = resolver_from_system_conf().await?;
+        Ok(self.lock().get_or_insert(resolver).clone())
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<AsyncStdResolver>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    async fn resolve(self, name: String, record_type: DnsRecordType) -> DnsResult {
+        let resolver = self.resolver().await.map_err(failed)?;
+        let query_type = match record_type {
+            DnsRecordType::A => RecordType::A,
+            DnsRecordType::Aaaa => RecordType::AAAA,
+            DnsRecordType::Mx => RecordType::MX,
+            DnsRecordType::Txt => RecordType::TXT,
+            DnsRecordType::Ptr => RecordType::PTR,
+            DnsRecordType::Tlsa => RecordType::TLSA,
+        };
+        // fully qualified so that the search domains do not apply
+        let fqdn = format!("{}.", name);
+        let lookup = resolver.lookup(fqdn, query_type, DnsRequestOptions::default());
+        let lookup = match timeout(self.timeout, lookup).await {
+            Ok(Ok(lookup)) => lookup,
+            Ok(Err(e)) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => return Err(DnsError::NotFound),
+                _ => return Err(failed(e)),
+            },
+            Err(_) => {
+                return Err(DnsError::Failed(format!(
+                    "{} {:?} lookup timed out",
+                    name, record_type
+                )))
+            }
+        };
+        let records = lookup
+            .iter()
+            .filter_map(|data| match data {
+                RData::A(ip) => Some(DnsRecord::A(*ip)),
+                RData::AAAA(ip) => Some(DnsRecord::Aaaa(*ip)),
+                RData::MX(mx) => Some(DnsRecord::Mx(MxRecord {
+                    preference: mx.preference(),
+                    exchange: normalize(mx.exchange().to_ascii().as_str()),
+                })),
+                RData::TXT(txt) => Some(DnsRecord::Txt(
+                    txt.txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect(),
+                )),
+                RData::PTR(name) => Some(DnsRecord::Ptr(normalize(name.to_ascii().as_str()))),
+                RData::TLSA(tlsa) => Some(DnsRecord::Tlsa(TlsaRecord {
+                    usage: tlsa.cert_usage().into(),
+                    selector: tlsa.selector().into(),
+                    matching: tlsa.matching().into(),
+                    data: tlsa.cert_data().to_vec(),
+                })),
+                // such as the CNAME records on the way
+                _ => None,
+            })
+            .filter(|record| record.record_type() == record_type)
+            .collect::<Vec<_>>();
+        if records.is_empty() {
+            return Err(DnsError::NotFound);
+        }
+        Ok(DnsAnswer {
+            records,
+            ttl: lookup
+                .valid_until()
+                .saturating_duration_since(Instant::now()),
+        })
+    }
+}
+
+fn failed(e: ResolveError) -> DnsError {
+    DnsError::Failed(e.to_string())
+}
+
+impl fmt::Debug for TrustDnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrustDnsResolver")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl DnsResolver for TrustDnsResolver {
+    fn query<'a, 'n, 'f>(
+        &'a self,
+        name: &'n str,
+        record_type: DnsRecordType,
+    ) -> S2Fut<'f, DnsResult>
+    where
+        'a: 'f,
+        'n: 'f,
+    {
+        // the resolver futures are not Sync, running them in a task of their own
+        Box::pin(async_std::task::spawn(
+            self.clone().resolve(normalize(name), record_type),
+        ))
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// Details of a negotiated TLS session as reported by the TLS provider
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// Negotiated protocol version, such as "TLSv1_3", if the provider can tell
+    pub protocol: Option<String>,
+    /// Negotiated cipher suite, such as "TLS13_AES_256_GCM_SHA384", if the provider can tell
+    pub cipher_suite: Option<String>,
+    /// Server name indication (SNI) sent by the client
+    pub server_name: Option<String>,
+    /// Certificate chain presented by the peer, end entity first.
+    /// Only verified certificates are reported, so if it is not empty,
+    /// the peer has been authenticated by the TLS provider.
+    pub peer_certificates: Vec<PeerCertificate>,
+    /// The provider validated the peer certificate chain, and for a server also its name.
+    /// An encrypted session alone is not enough for REQUIRETLS (RFC 8689).
+    pub peer_verified: bool,
+}
+
+/// Peer certificate as presented in the TLS handshake
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct PeerCertificate {
+    /// Subject distinguished name, such as "CN=client.example.org, O=Example"
+    pub subject: Option<String>,
+    /// Lowercase hex encoded SHA-256 digest of the DER encoded certificate
+    pub fingerprint: String,
+    /// DER encoded certificate
+    pub der: Vec<u8>,
+}
+
+#[cfg(feature = "x509")]
+impl PeerCertificate {
+    /// Describe a DER encoded certificate
+    pub fn from_der(der: Vec<u8>) -> Self {
+        use sha2::Digest;
+        let fingerprint = sha2::Sha256::digest(der.as_slice())
+            .iter()
+            .fold(String::new(), |s, b| s + format!("{:02x}", b).as_str());
+        let subject = x509_parser::parse_x509_certificate(der.as_slice())
+            .map(|(_, cert)| cert.subject().to_string())
+            .ok();
+        PeerCertificate {
+            subject,
+            fingerprint,
+            der,
+        }
+    }
+}
+
+impl TlsInfo {
+    /// The end entity certificate of the peer, if any was presented and verified
+    pub fn peer_certificate(&self) -> Option<&PeerCertificate> {
+        self.peer_certificates.first()
+    }
+}
+
+impl fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} with {}",
+            self.protocol.as_deref().unwrap_or("TLS"),
+            self.cipher_suite.as_deref().unwrap_or("unknown cipher")
+        )?;
+        if let Some(ref name) = self.server_name {
+            write!(f, " for {}", name)?;
+        }
+        if let Some(cert) = self.peer_certificate() {
+            write!(
+                f,
+                " authenticated as {} ({})",
+                cert.subject.as_deref().unwrap_or("unknown subject"),
+                cert.fingerprint
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn display_tls_info() {
+        let sut = TlsInfo {
+            protocol: Some("TLSv1_3".into()),
+            cipher_suite: Some("TLS13_AES_256_GCM_SHA384".into()),
+            server_name: Some("mx.example.org".into()),
+            peer_certificates: vec![PeerCertificate {
+                subject: Some("CN=client".into()),
+                fingerprint: "abcd".into(),
+                der: vec![],
+            }],
+            peer_verified: true,
+        };
+        insta::assert_display_snapshot!(sut, @"TLSv1_3 with TLS13_AES_256_GCM_SHA384 for mx.example.org authenticated as CN=client (abcd)");
+    }
+
+    #[cfg(feature = "x509")]
+    #[test]
+    pub fn fingerprint_of_invalid_certificate() {
+        let sut = PeerCertificate::from_der(b"abc".to_vec());
+        assert_eq!(sut.subject, None);
+        assert_eq!(
+            sut.fingerprint,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,9 +1,11 @@
+mod info;
 mod notls;
 mod stream;
 mod traits;
 
 use core::panic;
 
+pub use info::*;
 pub use notls::*;
 pub use stream::*;
 pub use traits::*;
@@ -55,4 +57,8 @@ impl MayBeTls for Dummy {
     fn is_encrypted(&self) -> bool {
         false
     }
+
+    fn tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
 }
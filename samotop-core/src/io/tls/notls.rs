@@ -1,6 +1,6 @@
 use super::{TlsProvider, TlsUpgrade};
 use crate::common::*;
-use crate::io::tls::Io;
+use crate::io::tls::{Io, TlsInfo};
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct NoTls;
@@ -19,7 +19,7 @@ impl TlsUpgrade for Impossible {
         &self,
         _stream: Box<dyn Io>,
         _name: String,
-    ) -> S3Fut<std::io::Result<Box<dyn Io>>> {
+    ) -> S3Fut<std::io::Result<(Box<dyn Io>, TlsInfo)>> {
         unreachable!()
         //Ok(Box::pin(ready(stream)))
     }
@@ -1,10 +1,11 @@
-use super::{Io, MayBeTls, TlsUpgrade};
+use super::{Io, MayBeTls, TlsInfo, TlsUpgrade};
 use crate::common::*;
 use core::panic;
 use std::fmt;
 
 pub struct TlsCapable {
     state: State,
+    info: Option<TlsInfo>,
 }
 
 enum State {
@@ -14,7 +15,7 @@ enum State {
     /// Plain TCP stream with name and potential TLS upgrade
     Enabled(Box<dyn Io>, Box<dyn TlsUpgrade>, String),
     /// Pending TLS handshake
-    Handshake(S3Fut<std::io::Result<Box<dyn Io>>>),
+    Handshake(S3Fut<std::io::Result<(Box<dyn Io>, TlsInfo)>>),
     /// TLS failed or in transition state
     Failed,
 }
@@ -57,6 +58,9 @@ impl MayBeTls for TlsCapable {
             State::Failed => false,
         }
     }
+    fn tls_info(&self) -> Option<TlsInfo> {
+        self.info.clone()
+    }
 
     fn enable_encryption(&mut self, upgrade: Box<dyn super::TlsUpgrade>, name: String) {
         self.state = match std::mem::replace(&mut self.state, State::Failed) {
@@ -71,16 +75,19 @@ impl TlsCapable {
     pub fn plaintext(io: Box<dyn Io>) -> Self {
         TlsCapable {
             state: State::Done(io, false),
+            info: None,
         }
     }
     pub fn encrypted(io: Box<dyn Io>) -> Self {
         TlsCapable {
             state: State::Done(io, true),
+            info: Some(TlsInfo::default()),
         }
     }
     pub fn enabled(io: Box<dyn Io>, upgrade: Box<dyn TlsUpgrade>, peer_name: String) -> Self {
         TlsCapable {
             state: State::Enabled(io, upgrade, peer_name),
+            info: None,
         }
     }
     fn poll_tls(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
@@ -93,9 +100,10 @@ impl TlsCapable {
                         trace!("TLS is not ready yet");
                         Poll::Pending
                     }
-                    Poll::Ready(encrypted) => {
-                        trace!("TLS is on!");
+                    Poll::Ready((encrypted, info)) => {
+                        trace!("TLS is on! {}", info);
                         this.state = State::Done(encrypted, true);
+                        this.info = Some(info);
                         Poll::Ready(Ok(()))
                     }
                 }
@@ -16,6 +16,9 @@ pub trait MayBeTls: Unpin + io::Read + io::Write + Sync + Send {
     fn can_encrypt(&self) -> bool;
     /// Returns true if the stream is already encrypted.
     fn is_encrypted(&self) -> bool;
+    /// Details of the negotiated TLS session once the handshake is done.
+    /// Returns None until the stream is encrypted.
+    fn tls_info(&self) -> Option<super::TlsInfo>;
 }
 
 impl<TLSIO, T: DerefMut<Target = TLSIO>> MayBeTls for T
@@ -32,6 +35,9 @@ where
     fn is_encrypted(&self) -> bool {
         TLSIO::is_encrypted(T::deref(self))
     }
+    fn tls_info(&self) -> Option<super::TlsInfo> {
+        TLSIO::tls_info(T::deref(self))
+    }
 
     fn enable_encryption(&mut self, upgrade: Box<dyn super::TlsUpgrade>, name: String) {
         TLSIO::enable_encryption(T::deref_mut(self), upgrade, name)
@@ -46,11 +52,13 @@ pub trait TlsProvider: std::fmt::Debug {
 }
 
 pub trait TlsUpgrade: Sync + Send {
+    /// Performs the TLS handshake on the given stream.
+    /// Resolves to the encrypted stream and the negotiated session details.
     fn upgrade_to_tls(
         &self,
         stream: Box<dyn Io>,
         name: String,
-    ) -> S3Fut<std::io::Result<Box<dyn Io>>>;
+    ) -> S3Fut<std::io::Result<(Box<dyn Io>, super::TlsInfo)>>;
 }
 
 impl<S: TlsProvider + ?Sized, T: Deref<Target = S>> TlsProvider for T
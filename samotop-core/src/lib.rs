@@ -3,6 +3,7 @@
 #[macro_use]
 extern crate log;
 
+pub mod dns;
 pub mod io;
 pub mod mail;
 pub mod smtp;
@@ -0,0 +1,709 @@
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{command::SmtpHelo, SmtpPath, SmtpReply, SmtpSession},
+};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// Whitelist and blacklist of clients, HELO names, senders and recipients.
+///
+/// Rules are loaded from files, one rule per line: `<subject> <pattern> <action> [text]`.
+/// Empty lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// # subject  pattern            action    text
+/// client     192.0.2.0/24       skip
+/// client     198.51.100.7       reject    You have been blocked
+/// helo       *.spam.example     reject
+/// sender     <>                 accept
+/// sender     *.spam.example     tempfail  Come back later
+/// sender     bob@example.org    reject    Bob is not welcome
+/// recipient  postmaster@example.org accept
+/// recipient  *                  reject    No such user
+/// ```
+///
+/// * Subjects are checked when they appear in the session: `client` (CIDR or IP) when the
+///   connection opens, `helo` on HELO/EHLO/LHLO, `sender` on MAIL and `recipient` on RCPT.
+/// * Patterns for `helo`, `sender` and `recipient` are either an address `user@domain`,
+///   a domain `example.org`, a wildcard for its subdomains `*.example.org`,
+///   `*` for anything or `<>` for the null sender. Matching is case insensitive.
+/// * Rules are evaluated in the order they were loaded and the first matching rule wins.
+/// * `accept` whitelists the subject and passes it without further access rules.
+///   The check guards, such as SPF, callouts or `RelayPolicy`, are skipped for the rest
+///   of the session after a `client` or `helo` rule, for the rest of the mail transaction
+///   after a `sender` rule and for the recipient after a `recipient` rule.
+/// * `reject` and `tempfail` refuse the subject with a permanent or temporary failure,
+///   using the optional text in the reply.
+/// * `skip` skips the remaining checks, the access rules as well as the check guards -
+///   for the whole session after `client` and `helo` rules, such as for trusted networks,
+///   or for the current mail transaction after `sender` and `recipient` rules.
+///
+/// Guards that adjust the mail, such as `Aliases`, still run for whitelisted subjects.
+/// The access list is added as the first guard so that it takes precedence over other guards.
+/// Changed files are reloaded when a new session opens or on demand with `reload()`.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    state: Arc<RwLock<AccessState>>,
+}
+
+#[derive(Debug, Default)]
+struct AccessState {
+    sources: Vec<AccessSource>,
+}
+
+#[derive(Debug)]
+enum AccessSource {
+    File {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+        rules: Vec<AccessRule>,
+    },
+    Text(Vec<AccessRule>),
+}
+
+impl AccessSource {
+    fn rules(&self) -> &[AccessRule] {
+        match self {
+            AccessSource::File { rules, .. } => rules.as_slice(),
+            AccessSource::Text(rules) => rules.as_slice(),
+        }
+    }
+}
+
+/// One access rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessRule {
+    pub subject: AccessSubject,
+    pub action: AccessAction,
+}
+
+/// What the access rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessSubject {
+    /// The client IP is in the network
+    Client(IpAddr, u8),
+    /// The HELO host matches the pattern
+    Helo(String),
+    /// The MAIL sender matches the pattern
+    Sender(String),
+    /// The RCPT recipient matches the pattern
+    Recipient(String),
+}
+
+/// What to do if the access rule matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessAction {
+    /// Pass the subject without further access rules
+    Accept,
+    /// Refuse permanently with an optional reply text
+    Reject(Option<String>),
+    /// Refuse temporarily with an optional reply text
+    TempFail(Option<String>),
+    /// Pass the session without further access rules
+    Skip,
+}
+
+/// Tag left on the session or the transaction once a `skip` rule matched
+pub const ACCESS_SKIP_TAG: &str = "access-skip";
+
+impl AccessList {
+    /// Load rules from the given file. The file will be reloaded when it changes.
+    pub fn with_file(self, path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let (modified, rules) = Self::load(path.as_path())?;
+        self.state
+            .write()
+            .expect("access list lock")
+            .sources
+            .push(AccessSource::File {
+                path,
+                modified,
+                rules,
+            });
+        Ok(self)
+    }
+    /// Add rules given in the file format
+    pub fn with_rules(self, text: &str) -> std::io::Result<Self> {
+        let rules = parse_rules(text)?;
+        self.state
+            .write()
+            .expect("access list lock")
+            .sources
+            .push(AccessSource::Text(rules));
+        Ok(self)
+    }
+    /// Reload all the files now. On failure, the current rules are kept.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let paths = self.paths();
+        let mut loaded = vec![];
+        for path in paths {
+            loaded.push(Self::load(path.as_path())?);
+        }
+        let mut state = self.state.write().expect("access list lock");
+        let files = state.sources.iter_mut().filter_map(|source| match source {
+            AccessSource::File {
+                modified, rules, ..
+            } => Some((modified, rules)),
+            AccessSource::Text(_) => None,
+        });
+        for ((modified, rules), (new_modified, new_rules)) in files.zip(loaded) {
+            *modified = new_modified;
+            *rules = new_rules;
+        }
+        Ok(())
+    }
+    /// Reload all the files if any of them changed since the last load
+    pub fn reload_if_changed(&self) {
+        let changed = self
+            .state
+            .read()
+            .expect("access list lock")
+            .sources
+            .iter()
+            .any(|source| match source {
+                AccessSource::File { path, modified, .. } => Self::modified(path) != *modified,
+                AccessSource::Text(_) => false,
+            });
+        if changed {
+            match self.reload() {
+                Ok(()) => info!("Access list reloaded"),
+                Err(e) => error!("Access list reload failed, keeping the old rules: {}", e),
+            }
+        }
+    }
+    /// The action of the first rule matching the subject
+    pub fn decide(&self, matches: impl Fn(&AccessSubject) -> bool) -> Option<AccessAction> {
+        let state = self.state.read().expect("access list lock");
+        state
+            .sources
+            .iter()
+            .flat_map(AccessSource::rules)
+            .find(|rule| matches(&rule.subject))
+            .map(|rule| rule.action.clone())
+    }
+    fn decide_for(
+        &self,
+        session: &mut SmtpSession,
+        matches: impl Fn(&AccessSubject) -> bool,
+    ) -> Option<AccessAction> {
+        let skipped = |tags: &[String]| tags.iter().any(|tag| tag == ACCESS_SKIP_TAG);
+        if skipped(&session.tags) || skipped(&session.transaction.tags) {
+            return None;
+        }
+        let action = self.decide(matches);
+        if action == Some(AccessAction::Skip) {
+            // sender and recipient rules only skip the current mail transaction
+            if session.transaction.mail.is_some() {
+                session.transaction.tags.push(ACCESS_SKIP_TAG.to_owned());
+            } else {
+                session.tags.push(ACCESS_SKIP_TAG.to_owned());
+            }
+            whitelist(session);
+        }
+        action
+    }
+    fn paths(&self) -> Vec<PathBuf> {
+        let state = self.state.read().expect("access list lock");
+        state
+            .sources
+            .iter()
+            .filter_map(|source| match source {
+                AccessSource::File { path, .. } => Some(path.clone()),
+                AccessSource::Text(_) => None,
+            })
+            .collect()
+    }
+    fn load(path: &Path) -> std::io::Result<(Option<SystemTime>, Vec<AccessRule>)> {
+        let modified = Self::modified(path);
+        let text = std::fs::read_to_string(path)?;
+        let rules = parse_rules(text.as_str())
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        Ok((modified, rules))
+    }
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
+
+impl AccessSubject {
+    /// Does the rule apply to the client IP?
+    pub fn matches_client(&self, ip: IpAddr) -> bool {
+        match *self {
+            AccessSubject::Client(net, prefix) => in_network(ip, net, prefix),
+            _ => false,
+        }
+    }
+    /// Does the rule apply to the HELO host?
+    pub fn matches_helo(&self, host: &str) -> bool {
+        match self {
+            AccessSubject::Helo(pattern) => matches_domain(pattern, host),
+            _ => false,
+        }
+    }
+    /// Does the rule apply to the sender?
+    pub fn matches_sender(&self, path: &SmtpPath) -> bool {
+        match self {
+            AccessSubject::Sender(pattern) => matches_path(pattern, path),
+            _ => false,
+        }
+    }
+    /// Does the rule apply to the recipient?
+    pub fn matches_recipient(&self, path: &SmtpPath) -> bool {
+        match self {
+            AccessSubject::Recipient(pattern) => matches_path(pattern, path),
+            _ => false,
+        }
+    }
+}
+
+fn parse_rules(text: &str) -> std::io::Result<Vec<AccessRule>> {
+    let invalid = |line: usize, msg: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+    let mut rules = vec![];
+    for (num, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let (subject, pattern, action) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(subject), Some(pattern), Some(action)) => (subject, pattern, action),
+            _ => return Err(invalid(num, "expected <subject> <pattern> <action>".into())),
+        };
+        let text = parts.collect::<Vec<_>>().join(" ");
+        let text = if text.is_empty() { None } else { Some(text) };
+        let pattern_lc = pattern.to_ascii_lowercase();
+        let subject = match subject.to_ascii_lowercase().as_str() {
+            "client" => {
+                let (net, prefix) = parse_network(pattern)
+                    .ok_or_else(|| invalid(num, format!("invalid network {:?}", pattern)))?;
+                AccessSubject::Client(net, prefix)
+            }
+            "helo" => AccessSubject::Helo(pattern_lc),
+            "sender" => AccessSubject::Sender(pattern_lc),
+            "recipient" => AccessSubject::Recipient(pattern_lc),
+            other => return Err(invalid(num, format!("unknown subject {:?}", other))),
+        };
+        let action = match action.to_ascii_lowercase().as_str() {
+            "accept" => AccessAction::Accept,
+            "reject" => AccessAction::Reject(text),
+            "tempfail" => AccessAction::TempFail(text),
+            "skip" => AccessAction::Skip,
+            other => return Err(invalid(num, format!("unknown action {:?}", other))),
+        };
+        rules.push(AccessRule { subject, action });
+    }
+    Ok(rules)
+}
+
+fn parse_network(pattern: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix) = match pattern.split_once('/') {
+        Some((ip, prefix)) => (ip.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (pattern.parse::<IpAddr>().ok()?, None),
+    };
+    let max = match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    match prefix {
+        Some(prefix) if prefix > max => None,
+        prefix => Some((ip, prefix.unwrap_or(max))),
+    }
+}
+
+pub(crate) fn in_network(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    };
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn matches_domain(pattern: &str, domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        _ if pattern == "*" => true,
+        Some(parent) => domain.ends_with(format!(".{}", parent).as_str()),
+        None => domain == pattern,
+    }
+}
+
+fn matches_path(pattern: &str, path: &SmtpPath) -> bool {
+    match path {
+        SmtpPath::Null => pattern == "<>" || pattern == "*",
+        SmtpPath::Postmaster => pattern == "postmaster" || pattern == "*",
+        SmtpPath::Mailbox { name, host, .. } => {
+            if pattern.contains('@') {
+                let address = format!("{}@{}", name, host.domain()).to_ascii_lowercase();
+                address == pattern
+            } else {
+                pattern != "<>" && matches_domain(pattern, host.domain().as_str())
+            }
+        }
+    }
+}
+
+/// Skip the check guards for the rest of the mail transaction or the session
+fn whitelist(session: &mut SmtpSession) {
+    if session.transaction.mail.is_some() {
+        session.transaction.whitelisted = true;
+    } else {
+        session.whitelisted = true;
+    }
+}
+
+pub(crate) fn peer_ip(session: &SmtpSession) -> Option<IpAddr> {
+    let peer = session.connection.peer_addr.as_str();
+    match peer.parse::<SocketAddr>() {
+        Ok(addr) => Some(addr.ip()),
+        Err(_) => peer.parse::<IpAddr>().ok(),
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for AccessList {
+    fn setup(self, config: &mut T) {
+        config.add_first_guard(self)
+    }
+}
+
+impl MailGuard for AccessList {
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.reload_if_changed();
+        let action = match peer_ip(session) {
+            Some(ip) => self.decide_for(session, |s| s.matches_client(ip)),
+            None => None,
+        };
+        let result = match action {
+            None | Some(AccessAction::Skip) => OpenSessionResult::Accepted,
+            Some(AccessAction::Accept) => {
+                whitelist(session);
+                OpenSessionResult::Accepted
+            }
+            Some(AccessAction::Reject(text)) => OpenSessionResult::Failed(
+                text.map(|text| OpenSessionFailure::Custom(SmtpReply::Custom(554, text)))
+                    .unwrap_or(OpenSessionFailure::NoService),
+                format!("Client {} is blacklisted", session.connection.peer_addr),
+            ),
+            Some(AccessAction::TempFail(text)) => OpenSessionResult::Failed(
+                text.map(|text| OpenSessionFailure::Custom(SmtpReply::Custom(421, text)))
+                    .unwrap_or(OpenSessionFailure::FailedTemporarily),
+                format!("Client {} is greylisted", session.connection.peer_addr),
+            ),
+        };
+        Box::pin(ready(result))
+    }
+
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        let host = helo.host.to_string();
+        let result = match self.decide_for(session, |s| s.matches_helo(host.as_str())) {
+            None | Some(AccessAction::Skip) => CheckHeloResult::Accepted,
+            Some(AccessAction::Accept) => {
+                whitelist(session);
+                CheckHeloResult::Accepted
+            }
+            Some(AccessAction::Reject(text)) => CheckHeloResult::Failed(
+                text.map(|text| CheckHeloFailure::Custom(SmtpReply::Custom(550, text)))
+                    .unwrap_or(CheckHeloFailure::Rejected),
+                format!("HELO {} is blacklisted", host),
+            ),
+            Some(AccessAction::TempFail(text)) => CheckHeloResult::Failed(
+                text.map(|text| CheckHeloFailure::Custom(SmtpReply::Custom(450, text)))
+                    .unwrap_or(CheckHeloFailure::FailedTemporarily),
+                format!("HELO {} is greylisted", host),
+            ),
+        };
+        Box::pin(ready(result))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let sender = match session.transaction.mail.as_ref() {
+            Some(mail) => mail.sender().clone(),
+            None => return Box::pin(ready(StartMailResult::Accepted)),
+        };
+        let result = match self.decide_for(session, |s| s.matches_sender(&sender)) {
+            None | Some(AccessAction::Skip) => StartMailResult::Accepted,
+            Some(AccessAction::Accept) => {
+                whitelist(session);
+                StartMailResult::Accepted
+            }
+            Some(AccessAction::Reject(text)) => StartMailResult::Failed(
+                text.map(|text| StartMailFailure::Custom(SmtpReply::Custom(550, text)))
+                    .unwrap_or(StartMailFailure::Rejected),
+                format!("Sender {} is blacklisted", sender),
+            ),
+            Some(AccessAction::TempFail(text)) => StartMailResult::Failed(
+                text.map(|text| StartMailFailure::Custom(SmtpReply::Custom(450, text)))
+                    .unwrap_or(StartMailFailure::FailedTemporarily),
+                format!("Sender {} is greylisted", sender),
+            ),
+        };
+        Box::pin(ready(result))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = match self.decide_for(session, |s| s.matches_recipient(&rcpt.address)) {
+            None | Some(AccessAction::Skip) => AddRecipientResult::Inconclusive(rcpt),
+            Some(AccessAction::Accept) => {
+                let mut rcpt = rcpt;
+                rcpt.whitelisted = true;
+                AddRecipientResult::Inconclusive(rcpt)
+            }
+            Some(AccessAction::Reject(text)) => AddRecipientResult::Failed(
+                text.map(|text| AddRecipientFailure::Custom(SmtpReply::Custom(550, text)))
+                    .unwrap_or(AddRecipientFailure::RejectedPermanently),
+                format!("Recipient {} is blacklisted", rcpt.address),
+            ),
+            Some(AccessAction::TempFail(text)) => AddRecipientResult::Failed(
+                text.map(|text| AddRecipientFailure::Custom(SmtpReply::Custom(450, text)))
+                    .unwrap_or(AddRecipientFailure::RejectedTemporarily),
+                format!("Recipient {} is greylisted", rcpt.address),
+            ),
+        };
+        Box::pin(ready(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Dummy, Identify};
+    use crate::smtp::{command::SmtpMail, SmtpHost};
+
+    const RULES: &str = "
+        # trusted network
+        client    10.0.0.0/8          skip
+        client    192.0.2.66          reject   You are blocked
+        helo      *.spam.example      reject
+        sender    *.spam.example      tempfail Come back later
+        sender    bob@example.org     reject
+        recipient postmaster@example.org accept
+        recipient example.org         reject   No such user
+    ";
+
+    fn path(name: &str, domain: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        }
+    }
+
+    fn session(peer: &str) -> SmtpSession {
+        let mut session = SmtpSession::default();
+        session.connection.peer_addr = peer.to_owned();
+        session
+    }
+
+    fn start_mail(
+        sut: &AccessList,
+        session: &mut SmtpSession,
+        sender: SmtpPath,
+    ) -> StartMailResult {
+        session.transaction.mail = Some(SmtpMail::Mail(sender, vec![]));
+        async_std::task::block_on(sut.start_mail(session))
+    }
+
+    #[test]
+    fn blocked_client_is_refused_with_text() {
+        let sut = AccessList::default().with_rules(RULES).expect("rules");
+        let mut session = session("192.0.2.66:2525");
+        let res = async_std::task::block_on(sut.open_session(&mut session));
+        assert_eq!(
+            res,
+            OpenSessionResult::Failed(
+                OpenSessionFailure::Custom(SmtpReply::Custom(554, "You are blocked".to_owned())),
+                "Client 192.0.2.66:2525 is blacklisted".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn sender_rules_match_address_and_subdomain() {
+        let sut = AccessList::default().with_rules(RULES).expect("rules");
+        let mut session = session("192.0.2.1:2525");
+        assert!(matches!(
+            start_mail(&sut, &mut session, path("Bob", "Example.org")),
+            StartMailResult::Failed(StartMailFailure::Rejected, _)
+        ));
+        assert!(matches!(
+            start_mail(&sut, &mut session, path("x", "mx.spam.example")),
+            StartMailResult::Failed(StartMailFailure::Custom(_), _)
+        ));
+        assert_eq!(
+            start_mail(&sut, &mut session, path("x", "spam.example")),
+            StartMailResult::Accepted
+        );
+    }
+
+    #[test]
+    fn first_matching_recipient_rule_wins() {
+        let sut = AccessList::default().with_rules(RULES).expect("rules");
+        let mut session = session("192.0.2.1:2525");
+        let res = async_std::task::block_on(sut.add_recipient(
+            &mut session,
+            Recipient::new(path("postmaster", "example.org")),
+        ));
+        assert!(matches!(res, AddRecipientResult::Inconclusive(rcpt) if rcpt.whitelisted));
+        assert!(session.transaction.rcpts.is_empty());
+        assert!(!session.transaction.whitelisted);
+        let res = async_std::task::block_on(
+            sut.add_recipient(&mut session, Recipient::new(path("joe", "example.org"))),
+        );
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+    }
+
+    #[test]
+    fn skip_passes_the_session() {
+        let sut = AccessList::default().with_rules(RULES).expect("rules");
+        let mut session = session("10.1.2.3:2525");
+        let res = async_std::task::block_on(sut.open_session(&mut session));
+        assert_eq!(res, OpenSessionResult::Accepted);
+        assert_eq!(
+            start_mail(&sut, &mut session, path("bob", "example.org")),
+            StartMailResult::Accepted
+        );
+    }
+
+    #[test]
+    fn sender_skip_passes_only_the_transaction() {
+        let sut = AccessList::default()
+            .with_rules("sender alice@example.org skip\nrecipient * reject")
+            .expect("rules");
+        let mut session = session("192.0.2.1:2525");
+        start_mail(&sut, &mut session, path("alice", "example.org"));
+        let res = async_std::task::block_on(
+            sut.add_recipient(&mut session, Recipient::new(path("joe", "example.org"))),
+        );
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+
+        session.transaction.reset();
+        start_mail(&sut, &mut session, path("mallory", "example.org"));
+        let res = async_std::task::block_on(
+            sut.add_recipient(&mut session, Recipient::new(path("joe", "example.org"))),
+        );
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+    }
+
+    #[test]
+    fn whitelisted_client_passes_the_check_guards() {
+        async_std::task::block_on(async move {
+            let sut = AccessList::default()
+                .with_rules("client 10.0.0.0/8 accept")
+                .expect("rules");
+            let blocker = TestGuard::default()
+                .open_session(OpenSessionResult::Failed(
+                    OpenSessionFailure::NoService,
+                    "blocked".to_owned(),
+                ))
+                .checking();
+            let service = (Builder + sut + blocker).build_with_driver(Dummy);
+
+            let mut trusted = session("10.1.2.3:2525");
+            assert_eq!(
+                service.open_session(&mut trusted).await,
+                OpenSessionResult::Accepted
+            );
+            assert!(trusted.whitelisted);
+
+            let mut stranger = session("192.0.2.1:2525");
+            assert!(matches!(
+                service.open_session(&mut stranger).await,
+                OpenSessionResult::Failed(OpenSessionFailure::NoService, _)
+            ));
+        })
+    }
+
+    #[test]
+    fn whitelisted_recipient_passes_the_relay_policy() {
+        async_std::task::block_on(async move {
+            let sut = AccessList::default()
+                .with_rules("recipient friend@example.net accept")
+                .expect("rules");
+            let relay = RelayPolicy::default().with_local_domain("example.org");
+            let service = (Builder + sut + relay).build_with_driver(Dummy);
+            let mut session = session("192.0.2.1:2525");
+            session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
+
+            let res = service
+                .add_recipient(&mut session, Recipient::new(path("friend", "example.net")))
+                .await;
+            assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+            let res = service
+                .add_recipient(&mut session, Recipient::new(path("other", "example.net")))
+                .await;
+            assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+        })
+    }
+
+    #[test]
+    fn invalid_rule_is_reported() {
+        let err = AccessList::default()
+            .with_rules("client 10.0.0.0/33 skip")
+            .expect_err("invalid");
+        assert_eq!(err.to_string(), "line 1: invalid network \"10.0.0.0/33\"");
+    }
+
+    #[test]
+    fn changed_file_is_reloaded() {
+        let path = std::env::temp_dir().join(format!("samotop-access-{}", Identify::now()));
+        std::fs::write(&path, "sender <> reject\n").expect("write");
+        let sut = AccessList::default().with_file(&path).expect("load");
+        let mut session = session("192.0.2.1:2525");
+        assert!(matches!(
+            start_mail(&sut, &mut session, SmtpPath::Null),
+            StartMailResult::Failed(_, _)
+        ));
+
+        std::fs::write(&path, "sender <> accept\n").expect("write");
+        sut.reload().expect("reload");
+        assert_eq!(
+            start_mail(&sut, &mut session, SmtpPath::Null),
+            StartMailResult::Accepted
+        );
+        std::fs::remove_file(&path).expect("cleanup");
+    }
+}
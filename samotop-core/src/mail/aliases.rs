@@ -0,0 +1,725 @@
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{SmtpHost, SmtpPath, SmtpReply, SmtpSession},
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Alias and virtual domain maps expanding recipients to other addresses.
+///
+/// Maps are loaded from files or text in one of two formats:
+///
+/// ```text
+/// # /etc/aliases - name: target, target...
+/// postmaster: root
+/// team:       alice, bob@example.net,
+///             carol
+///
+/// # postfix virtual - key target, target...
+/// example.org           virtual-domain
+/// info@example.org      alice@example.org, bob@example.org
+/// @example.com          catchall@example.org
+/// @old.example.org      @example.org
+/// ```
+///
+/// * Keys are a local part `name`, an address `name@domain` or a catch-all `@domain`.
+///   For an address, the exact key is tried first, then the local part if the domain is local,
+///   then the catch-all. Matching is case insensitive, the first map that matches wins.
+/// * A local part key applies to the domains given with `with_local_domain()` and to the domains
+///   declared in virtual maps by a key without a local part, or to any domain if none are known.
+/// * Targets without a domain stay in the domain of the recipient. A catch-all target `@domain`
+///   keeps the local part. Pipes, files and `:include:` are not supported and are skipped.
+/// * Targets are resolved recursively. A target equal to the alias itself is delivered as is,
+///   any other loop fails the recipient with `554 5.4.6`.
+/// * One recipient may expand to many. The first one is passed on to the following guards,
+///   the others follow in `Recipient::expansion` and each of them is passed on to the same
+///   following guards as a recipient of its own. If any of them is refused, the whole
+///   recipient is refused. The guards before `Aliases` only check the alias itself.
+///
+/// Changed files are reloaded when a new session opens, at most once per `check_interval`,
+/// or on demand with `reload()`. A file that fails to load is only tried again once it changes.
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    state: Arc<RwLock<AliasState>>,
+}
+
+#[derive(Debug)]
+struct AliasState {
+    local_domains: Vec<String>,
+    sources: Vec<AliasSource>,
+    check_interval: Duration,
+    checked: Option<Instant>,
+}
+
+impl Default for AliasState {
+    fn default() -> Self {
+        Self {
+            local_domains: vec![],
+            sources: vec![],
+            check_interval: Duration::from_secs(10),
+            checked: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum AliasSource {
+    File {
+        path: PathBuf,
+        format: AliasFormat,
+        modified: Option<SystemTime>,
+        map: AliasMap,
+    },
+    Text(AliasMap),
+}
+
+impl AliasSource {
+    fn map(&self) -> &AliasMap {
+        match self {
+            AliasSource::File { map, .. } => map,
+            AliasSource::Text(map) => map,
+        }
+    }
+}
+
+/// The format of an alias map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasFormat {
+    /// sendmail `/etc/aliases` - `name: target, target`
+    Aliases,
+    /// postfix `virtual` - `key target, target`
+    Virtual,
+}
+
+#[derive(Debug, Default)]
+struct AliasMap {
+    domains: Vec<String>,
+    entries: HashMap<String, Vec<String>>,
+}
+
+/// Why a recipient could not be expanded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AliasError {
+    /// The alias refers back to itself through the given address
+    Loop(String),
+    /// The aliases are nested deeper than allowed
+    TooDeep(String),
+}
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AliasError::Loop(address) => write!(f, "alias loop through {}", address),
+            AliasError::TooDeep(address) => write!(f, "aliases nested too deep at {}", address),
+        }
+    }
+}
+
+impl std::error::Error for AliasError {}
+
+/// How deep aliases may be nested
+const MAX_DEPTH: usize = 20;
+
+impl Aliases {
+    /// Load a map from the given file. The file will be reloaded when it changes.
+    pub fn with_file(self, path: impl Into<PathBuf>, format: AliasFormat) -> std::io::Result<Self> {
+        let path = path.into();
+        let (modified, map) = Self::load(path.as_path(), format)?;
+        self.state
+            .write()
+            .expect("aliases lock")
+            .sources
+            .push(AliasSource::File {
+                path,
+                format,
+                modified,
+                map,
+            });
+        Ok(self)
+    }
+    /// Add a map given in the file format
+    pub fn with_map(self, text: &str, format: AliasFormat) -> std::io::Result<Self> {
+        let map = parse_map(text, format)?;
+        self.state
+            .write()
+            .expect("aliases lock")
+            .sources
+            .push(AliasSource::Text(map));
+        Ok(self)
+    }
+    /// Local part keys apply to this domain
+    pub fn with_local_domain(self, domain: impl Into<String>) -> Self {
+        self.state
+            .write()
+            .expect("aliases lock")
+            .local_domains
+            .push(domain.into().to_ascii_lowercase());
+        self
+    }
+    /// How often to look for changed files when sessions open
+    pub fn with_check_interval(self, interval: Duration) -> Self {
+        self.state.write().expect("aliases lock").check_interval = interval;
+        self
+    }
+    /// Reload all the files now. On failure, the current maps are kept.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let files = self.files();
+        let mut loaded = vec![];
+        for (path, format) in files {
+            loaded.push(Self::load(path.as_path(), format)?);
+        }
+        let mut state = self.state.write().expect("aliases lock");
+        let files = state.sources.iter_mut().filter_map(|source| match source {
+            AliasSource::File { modified, map, .. } => Some((modified, map)),
+            AliasSource::Text(_) => None,
+        });
+        for ((modified, map), (new_modified, new_map)) in files.zip(loaded) {
+            *modified = new_modified;
+            *map = new_map;
+        }
+        Ok(())
+    }
+    /// Reload all the files if any of them changed since the last load
+    pub fn reload_if_changed(&self) {
+        let changed = self
+            .state
+            .read()
+            .expect("aliases lock")
+            .sources
+            .iter()
+            .any(|source| match source {
+                AliasSource::File { path, modified, .. } => Self::modified(path) != *modified,
+                AliasSource::Text(_) => false,
+            });
+        if changed {
+            match self.reload() {
+                Ok(()) => info!("Aliases reloaded"),
+                Err(e) => {
+                    error!("Aliases reload failed, keeping the old maps: {}", e);
+                    // do not try again until the files change
+                    let mut state = self.state.write().expect("aliases lock");
+                    for source in state.sources.iter_mut() {
+                        if let AliasSource::File { path, modified, .. } = source {
+                            *modified = Self::modified(path);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Is it time to look for changed files again?
+    fn is_check_due(&self) -> bool {
+        let mut state = self.state.write().expect("aliases lock");
+        let now = Instant::now();
+        match state.checked {
+            Some(checked) if now.duration_since(checked) < state.check_interval => false,
+            _ => {
+                state.checked = Some(now);
+                true
+            }
+        }
+    }
+    /// The final addresses of the recipient `name@domain`.
+    /// An address that is not an alias resolves to itself.
+    pub fn resolve(&self, address: &str) -> std::result::Result<Vec<String>, AliasError> {
+        let state = self.state.read().expect("aliases lock");
+        let mut resolved = vec![];
+        state.expand(address, &mut vec![], &mut resolved)?;
+        Ok(resolved)
+    }
+    fn files(&self) -> Vec<(PathBuf, AliasFormat)> {
+        let state = self.state.read().expect("aliases lock");
+        state
+            .sources
+            .iter()
+            .filter_map(|source| match source {
+                AliasSource::File { path, format, .. } => Some((path.clone(), *format)),
+                AliasSource::Text(_) => None,
+            })
+            .collect()
+    }
+    fn load(path: &Path, format: AliasFormat) -> std::io::Result<(Option<SystemTime>, AliasMap)> {
+        let modified = Self::modified(path);
+        let text = std::fs::read_to_string(path)?;
+        let map = parse_map(text.as_str(), format)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        Ok((modified, map))
+    }
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
+
+impl AliasState {
+    fn is_local(&self, domain: &str) -> bool {
+        let mut domains = self
+            .local_domains
+            .iter()
+            .chain(self.sources.iter().flat_map(|s| s.map().domains.iter()))
+            .peekable();
+        domains.peek().is_none() || domains.any(|local| local == domain)
+    }
+    fn lookup(&self, local: &str, domain: &str) -> Option<Vec<String>> {
+        let address = format!("{}@{}", local, domain);
+        let maps = || self.sources.iter().map(AliasSource::map);
+        if let Some(targets) = maps().find_map(|map| map.entries.get(&address)) {
+            return Some(targets.clone());
+        }
+        if self.is_local(domain) {
+            if let Some(targets) = maps().find_map(|map| map.entries.get(local)) {
+                return Some(targets.clone());
+            }
+        }
+        let catch_all = format!("@{}", domain);
+        maps()
+            .find_map(|map| map.entries.get(&catch_all))
+            .map(|targets| {
+                targets
+                    .iter()
+                    .map(|target| {
+                        if target.starts_with('@') {
+                            format!("{}{}", local, target)
+                        } else {
+                            target.clone()
+                        }
+                    })
+                    .collect()
+            })
+    }
+    fn expand(
+        &self,
+        address: &str,
+        stack: &mut Vec<String>,
+        resolved: &mut Vec<String>,
+    ) -> std::result::Result<(), AliasError> {
+        let (local, domain) = split_address(address);
+        let key = format!("{}@{}", local, domain).to_ascii_lowercase();
+        let targets = match self.lookup(&local.to_ascii_lowercase(), &domain.to_ascii_lowercase()) {
+            None => {
+                if !resolved.iter().any(|r| r.eq_ignore_ascii_case(address)) {
+                    resolved.push(address.to_owned());
+                }
+                return Ok(());
+            }
+            Some(targets) => targets,
+        };
+        if stack.contains(&key) {
+            return Err(AliasError::Loop(address.to_owned()));
+        }
+        if stack.len() >= MAX_DEPTH {
+            return Err(AliasError::TooDeep(address.to_owned()));
+        }
+        stack.push(key);
+        for target in targets {
+            let target = if target.contains('@') {
+                target
+            } else {
+                format!("{}@{}", target, domain)
+            };
+            if target.eq_ignore_ascii_case(address) {
+                // the alias keeps a copy for itself
+                if !resolved.iter().any(|r| r.eq_ignore_ascii_case(&target)) {
+                    resolved.push(target);
+                }
+            } else {
+                self.expand(target.as_str(), stack, resolved)?;
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+fn split_address(address: &str) -> (&str, &str) {
+    match address.rfind('@') {
+        Some(at) => (&address[..at], &address[at + 1..]),
+        None => (address, ""),
+    }
+}
+
+fn parse_map(text: &str, format: AliasFormat) -> std::io::Result<AliasMap> {
+    let invalid = |line: usize, msg: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+    // join the continuation lines starting with a white space
+    let mut entries: Vec<(usize, String)> = vec![];
+    for (num, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l)) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        match entries.last_mut() {
+            Some((_, entry)) if line.starts_with(|c: char| c.is_whitespace()) => {
+                entry.push(' ');
+                entry.push_str(trimmed);
+            }
+            _ => entries.push((num, trimmed.to_owned())),
+        }
+    }
+
+    let mut map = AliasMap::default();
+    for (num, entry) in entries {
+        let (key, targets) = match format {
+            AliasFormat::Aliases => entry
+                .split_once(':')
+                .ok_or_else(|| invalid(num, "expected <name>: <targets>".into()))?,
+            AliasFormat::Virtual => entry
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(num, "expected <key> <targets>".into()))?,
+        };
+        let key = key.trim().to_ascii_lowercase();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(invalid(num, format!("invalid key {:?}", key)));
+        }
+        if format == AliasFormat::Virtual && !key.contains('@') && key.contains('.') {
+            // a virtual alias domain, the value does not matter
+            map.domains.push(key);
+            continue;
+        }
+        let separator = |c: char| match format {
+            AliasFormat::Aliases => c == ',',
+            AliasFormat::Virtual => c == ',' || c.is_whitespace(),
+        };
+        let targets = targets
+            .split(separator)
+            .map(str::trim)
+            .map(|target| target.trim_start_matches('<').trim_end_matches('>'))
+            .filter(|target| !target.is_empty())
+            .filter(|target| {
+                let supported = !target.starts_with(['|', '/', '"'].as_ref())
+                    && !target.starts_with(":include:");
+                if !supported {
+                    warn!("Skipping unsupported alias target {} of {}", target, key);
+                }
+                supported
+            })
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        if targets.is_empty() {
+            return Err(invalid(num, format!("no targets for {:?}", key)));
+        }
+        map.entries.entry(key).or_insert(targets);
+    }
+    Ok(map)
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for Aliases {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for Aliases {
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if self.is_check_due() {
+            self.reload_if_changed();
+        }
+        Box::pin(ready(OpenSessionResult::Accepted))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        mut rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let address = match &rcpt.address {
+            SmtpPath::Mailbox {
+                name,
+                host: SmtpHost::Domain(domain),
+                ..
+            } => format!("{}@{}", name, domain),
+            _ => return Box::pin(ready(AddRecipientResult::Inconclusive(rcpt))),
+        };
+        let resolved = match self.resolve(address.as_str()) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                return Box::pin(ready(AddRecipientResult::Failed(
+                    AddRecipientFailure::Custom(SmtpReply::Custom(
+                        554,
+                        "5.4.6 Alias loop, cannot deliver".to_owned(),
+                    )),
+                    format!("Recipient {} failed: {}", rcpt.address, e),
+                )))
+            }
+        };
+        if resolved.len() == 1 && resolved[0] == address {
+            return Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)));
+        }
+        debug!("Recipient {} expanded to {:?}", rcpt.address, resolved);
+
+        let rcpts = &session.transaction.rcpts;
+        let mut resolved = resolved
+            .into_iter()
+            .map(|address| {
+                let (name, domain) = split_address(address.as_str());
+                SmtpPath::Mailbox {
+                    name: name.to_owned(),
+                    host: SmtpHost::Domain(domain.to_owned()),
+                    relays: vec![],
+                }
+            })
+            .filter(|path| !rcpts.iter().any(|r| &r.address == path))
+            .collect::<Vec<_>>()
+            .into_iter();
+        let first = match resolved.next() {
+            Some(first) => first,
+            // all of them are recipients already
+            None => return Box::pin(ready(AddRecipientResult::Accepted)),
+        };
+        rcpt.expansion.extend(resolved.map(Recipient::new));
+        rcpt.address = first;
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Identify;
+
+    const ALIASES: &str = "\
+# local aliases
+postmaster: root
+team:       alice, bob@example.net,
+            carol
+root:       admin
+ping: pong
+pong: ping
+alice: alice, \"|/usr/bin/vacation alice\"
+";
+
+    const VIRTUAL: &str = "\
+example.org           virtual-domain
+info@example.org      alice@example.org, bob@example.org
+@example.com          catchall@example.org
+@old.example.org      @example.org
+";
+
+    fn sut() -> Aliases {
+        Aliases::default()
+            .with_map(VIRTUAL, AliasFormat::Virtual)
+            .expect("virtual")
+            .with_map(ALIASES, AliasFormat::Aliases)
+            .expect("aliases")
+    }
+
+    fn path(name: &str, domain: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        }
+    }
+
+    #[test]
+    fn aliases_are_resolved_recursively() {
+        let sut = sut();
+        assert_eq!(
+            sut.resolve("Postmaster@example.org"),
+            Ok(vec!["admin@example.org".to_owned()])
+        );
+        assert_eq!(
+            sut.resolve("team@example.org"),
+            Ok(vec![
+                "alice@example.org".to_owned(),
+                "bob@example.net".to_owned(),
+                "carol@example.org".to_owned(),
+            ])
+        );
+        assert_eq!(
+            sut.resolve("nobody@example.net"),
+            Ok(vec!["nobody@example.net".to_owned()])
+        );
+    }
+
+    #[test]
+    fn catch_all_applies_to_unknown_users() {
+        let sut = sut();
+        assert_eq!(
+            sut.resolve("info@example.org"),
+            Ok(vec![
+                "alice@example.org".to_owned(),
+                "bob@example.org".to_owned(),
+            ])
+        );
+        assert_eq!(
+            sut.resolve("whoever@example.com"),
+            Ok(vec!["catchall@example.org".to_owned()])
+        );
+        assert_eq!(
+            sut.resolve("whoever@old.example.org"),
+            Ok(vec!["whoever@example.org".to_owned()])
+        );
+    }
+
+    #[test]
+    fn loops_are_detected() {
+        let sut = sut();
+        assert_eq!(
+            sut.resolve("ping@example.org"),
+            Err(AliasError::Loop("ping@example.org".to_owned()))
+        );
+        let mut session = SmtpSession::default();
+        let res = async_std::task::block_on(
+            sut.add_recipient(&mut session, Recipient::new(path("pong", "example.org"))),
+        );
+        assert!(
+            matches!(
+                res,
+                AddRecipientResult::Failed(AddRecipientFailure::Custom(_), _)
+            ),
+            "{:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn recipient_fans_out() {
+        let sut = sut();
+        let mut session = SmtpSession::default();
+        session
+            .transaction
+            .rcpts
+            .push(Recipient::new(path("carol", "example.org")));
+        let res = async_std::task::block_on(
+            sut.add_recipient(&mut session, Recipient::new(path("team", "example.org"))),
+        );
+        match res {
+            AddRecipientResult::Inconclusive(rcpt) => {
+                assert_eq!(rcpt.address, path("alice", "example.org"));
+                let expansion = rcpt
+                    .expansion
+                    .iter()
+                    .map(|r| r.address.address())
+                    .collect::<Vec<_>>();
+                assert_eq!(expansion, vec!["bob@example.net"]);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        // the RCPT command adds them after all the guards checked them
+        assert_eq!(session.transaction.rcpts.len(), 1);
+
+        session
+            .transaction
+            .rcpts
+            .push(Recipient::new(path("alice", "example.org")));
+        session
+            .transaction
+            .rcpts
+            .push(Recipient::new(path("bob", "example.net")));
+        let res = async_std::task::block_on(
+            sut.add_recipient(&mut session, Recipient::new(path("team", "example.org"))),
+        );
+        assert!(matches!(res, AddRecipientResult::Accepted), "{:?}", res);
+    }
+
+    #[test]
+    fn local_part_keys_apply_to_local_domains() {
+        let sut = Aliases::default()
+            .with_local_domain("example.org")
+            .with_map("root: admin", AliasFormat::Aliases)
+            .expect("aliases");
+        assert_eq!(
+            sut.resolve("root@example.org"),
+            Ok(vec!["admin@example.org".to_owned()])
+        );
+        assert_eq!(
+            sut.resolve("root@example.com"),
+            Ok(vec!["root@example.com".to_owned()])
+        );
+    }
+
+    #[test]
+    fn invalid_entry_is_reported() {
+        let err = Aliases::default()
+            .with_map("root admin", AliasFormat::Aliases)
+            .expect_err("invalid");
+        assert_eq!(err.to_string(), "line 1: expected <name>: <targets>");
+    }
+
+    #[test]
+    fn changed_file_is_reloaded() {
+        let path = std::env::temp_dir().join(format!("samotop-aliases-{}", Identify::now()));
+        std::fs::write(&path, "root: admin\n").expect("write");
+        let sut = Aliases::default()
+            .with_file(&path, AliasFormat::Aliases)
+            .expect("load");
+        assert_eq!(
+            sut.resolve("root@example.org"),
+            Ok(vec!["admin@example.org".to_owned()])
+        );
+
+        std::fs::write(&path, "root: admin, backup@example.net\n").expect("write");
+        sut.reload().expect("reload");
+        std::fs::remove_file(&path).ok();
+        assert_eq!(
+            sut.resolve("root@example.org"),
+            Ok(vec![
+                "admin@example.org".to_owned(),
+                "backup@example.net".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn broken_file_is_not_read_again_until_changed() {
+        let path = std::env::temp_dir().join(format!("samotop-aliases-{}", Identify::now()));
+        std::fs::write(&path, "root: admin\n").expect("write");
+        let sut = Aliases::default()
+            .with_file(&path, AliasFormat::Aliases)
+            .expect("load");
+
+        std::fs::write(&path, "root admin\n").expect("write");
+        let broken = Aliases::modified(&path);
+        {
+            let mut state = sut.state.write().expect("aliases lock");
+            if let AliasSource::File { modified, .. } = &mut state.sources[0] {
+                *modified = None;
+            }
+        }
+        sut.reload_if_changed();
+        std::fs::remove_file(&path).ok();
+        let state = sut.state.read().expect("aliases lock");
+        assert!(matches!(
+            &state.sources[0],
+            AliasSource::File { modified, .. } if *modified == broken
+        ));
+        drop(state);
+        assert_eq!(
+            sut.resolve("root@example.org"),
+            Ok(vec!["admin@example.org".to_owned()])
+        );
+    }
+
+    #[test]
+    fn files_are_checked_once_per_interval() {
+        let sut = Aliases::default().with_check_interval(Duration::from_secs(60));
+        assert!(sut.is_check_due());
+        assert!(!sut.is_check_due());
+    }
+}
@@ -0,0 +1,442 @@
+use crate::{
+    common::*,
+    mail::{AcceptsDispatch, DispatchResult, MailDataSink, MailDispatch, MailSetup},
+    smtp::SmtpSession,
+};
+use std::net::SocketAddr;
+
+/// Adds the `Authentication-Results` (RFC 8601) and `Received-SPF` (RFC 7208) headers to the mail.
+///
+/// Content checks run on the held mail before it is dispatched, so the results are final
+/// when the mail body opens and the headers are prepended right away.
+/// Incoming headers of the same kind that claim to come from our authserv-id are removed.
+/// For that, each incoming header field is held back until it is complete, up to `HEADER_LIMIT`
+/// bytes, the body is passed on as it comes.
+///
+/// Set it up after the dispatch that delivers the mail.
+#[derive(Debug, Clone, Default)]
+pub struct AuthResultsHeader {
+    authserv_id: Option<String>,
+}
+
+impl AuthResultsHeader {
+    /// The most of a single incoming header field held back to remove forged results.
+    /// A larger field is passed on as it comes, unless it is a result header, then it is removed.
+    pub const HEADER_LIMIT: usize = 64 * 1024;
+    /// Identify the results with the given authserv-id rather than the service name
+    pub fn with_authserv_id(mut self, authserv_id: impl Into<String>) -> Self {
+        self.authserv_id = Some(authserv_id.into());
+        self
+    }
+}
+
+impl<T: AcceptsDispatch> MailSetup<T> for AuthResultsHeader {
+    fn setup(self, config: &mut T) {
+        config.add_last_dispatch(self)
+    }
+}
+
+impl MailDispatch for AuthResultsHeader {
+    fn open_mail_body<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, DispatchResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if let Some(inner) = session.transaction.sink.take() {
+            let authserv_id = self
+                .authserv_id
+                .clone()
+                .unwrap_or_else(|| session.service_name.clone());
+            session.transaction.sink = Some(Box::pin(AuthResultsSink {
+                inner,
+                pending: result_headers(authserv_id.as_str(), session).into_bytes(),
+                authserv_id,
+                field: vec![],
+                incoming: Incoming::Field,
+                line_end: false,
+                written: 0,
+            }));
+        }
+        Box::pin(ready(Ok(())))
+    }
+}
+
+/// Compose the result headers of the session and of the mail transaction
+fn result_headers(id: &str, session: &SmtpSession) -> String {
+    let mut results = session.auth_results.clone();
+    results.extend(session.transaction.auth_results.to_vec());
+    let peer = session.connection.peer_addr.as_str();
+    let client_ip = match peer.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => peer.to_owned(),
+    };
+    let helo = session.peer_name.clone().unwrap_or_default();
+    let envelope_from = session
+        .transaction
+        .mail
+        .as_ref()
+        .map(|mail| mail.sender().address())
+        .unwrap_or_default();
+
+    let mut headers = format!("Authentication-Results: {}", id);
+    if results.is_empty() {
+        headers.push_str("; none");
+    }
+    for result in results.iter() {
+        headers.push_str(";\r\n\t");
+        headers.push_str(single_line(result.to_string()).as_str());
+    }
+    headers.push_str("\r\n");
+
+    for spf in results.iter().filter(|result| result.is_method("spf")) {
+        let comment = match spf.reason {
+            Some(ref reason) => reason.clone(),
+            None => format!(
+                "SPF {} for {}",
+                spf.result,
+                spf.property("smtp.mailfrom").unwrap_or_default()
+            ),
+        };
+        let mut header = format!(
+            "Received-SPF: {} ({}: {}) receiver={};",
+            spf.result, id, comment, id
+        );
+        if !client_ip.is_empty() {
+            header.push_str(format!(" client-ip={};", client_ip).as_str());
+        }
+        header.push_str(format!(" envelope-from=\"{}\";", envelope_from).as_str());
+        if !helo.is_empty() {
+            header.push_str(format!(" helo={};", helo).as_str());
+        }
+        headers.push_str(single_line(header).as_str());
+        headers.push_str("\r\n");
+    }
+    headers
+}
+
+/// Writes our headers and the incoming mail without forged results to the inner sink
+struct AuthResultsSink {
+    inner: Pin<Box<dyn MailDataSink>>,
+    authserv_id: String,
+    /// our result headers, then the checked incoming data, waiting to be written
+    pending: Vec<u8>,
+    /// the incoming header field received so far
+    field: Vec<u8>,
+    incoming: Incoming,
+    /// the last byte ended a line, the field may still be folded
+    line_end: bool,
+    written: usize,
+}
+
+/// What the incoming data is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Incoming {
+    /// a header field held back until it is complete
+    Field,
+    /// a header field too large to hold, passed on as it comes
+    LargeField,
+    /// a result header too large to hold, removed
+    LargeResult,
+    /// the mail body after the header section
+    Body,
+}
+
+impl AuthResultsSink {
+    /// Check the incoming data, the checked part is queued in `pending`
+    fn receive(&mut self, buf: &[u8]) {
+        for (pos, b) in buf.iter().enumerate() {
+            if self.line_end && *b != b' ' && *b != b'\t' {
+                self.end_field();
+            }
+            self.line_end = *b == b'\n';
+            match self.incoming {
+                Incoming::Field => {
+                    self.field.push(*b);
+                    if self.field == b"\r\n" || self.field == b"\n" {
+                        // the empty line ends the header section
+                        self.pending.append(&mut self.field);
+                        self.pending.extend_from_slice(&buf[pos + 1..]);
+                        self.incoming = Incoming::Body;
+                        return;
+                    }
+                    if self.field.len() > AuthResultsHeader::HEADER_LIMIT {
+                        self.incoming = if is_result_field(&self.field) {
+                            warn!("Removing an oversized result header");
+                            self.field.clear();
+                            Incoming::LargeResult
+                        } else {
+                            self.pending.append(&mut self.field);
+                            Incoming::LargeField
+                        };
+                    }
+                }
+                Incoming::LargeField => self.pending.push(*b),
+                Incoming::LargeResult => {}
+                Incoming::Body => unreachable!("body is passed on as it comes"),
+            }
+        }
+    }
+    /// The incoming header field is complete, pass it on unless it is forged
+    fn end_field(&mut self) {
+        let field = std::mem::take(&mut self.field);
+        if is_forged(self.authserv_id.as_str(), &field) {
+            debug!(
+                "Removing forged header {:?}",
+                String::from_utf8_lossy(&field).trim()
+            );
+        } else {
+            self.pending.extend(field);
+        }
+        self.incoming = Incoming::Field;
+        self.line_end = false;
+    }
+    /// Write out the pending data
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.written < self.pending.len() {
+            let len = ready!(self
+                .inner
+                .as_mut()
+                .poll_write(cx, &self.pending[self.written..]))?;
+            if len == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.written += len;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl io::Write for AuthResultsSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        if this.incoming == Incoming::Body {
+            return this.inner.as_mut().poll_write(cx, buf);
+        }
+        this.receive(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        // an incomplete field is held back until it is complete
+        ready!(this.poll_drain(cx))?;
+        this.inner.as_mut().poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if this.incoming != Incoming::Body {
+            this.end_field();
+            this.incoming = Incoming::Body;
+        }
+        ready!(this.poll_drain(cx))?;
+        this.inner.as_mut().poll_close(cx)
+    }
+}
+
+/// Header values must not break the header
+fn single_line(value: String) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// Is the field one of the result headers we add?
+fn is_result_field(field: &[u8]) -> bool {
+    let field = String::from_utf8_lossy(field);
+    match field.split_once(':') {
+        Some((name, _)) => {
+            let name = name.trim();
+            name.eq_ignore_ascii_case("authentication-results")
+                || name.eq_ignore_ascii_case("received-spf")
+        }
+        None => false,
+    }
+}
+
+fn is_forged(authserv_id: &str, field: &[u8]) -> bool {
+    let field = String::from_utf8_lossy(field);
+    let (name, value) = match field.split_once(':') {
+        Some(split) => split,
+        None => return false,
+    };
+    let name = name.trim();
+    if name.eq_ignore_ascii_case("authentication-results") {
+        // the authserv-id may be followed by a version
+        let id = value.split(';').next().unwrap_or_default();
+        id.split_whitespace()
+            .next()
+            .map(|id| id.eq_ignore_ascii_case(authserv_id))
+            .unwrap_or_default()
+    } else if name.eq_ignore_ascii_case("received-spf") {
+        value
+            .split(|c: char| c == ';' || c.is_whitespace())
+            .filter_map(|pair| pair.split_once('='))
+            .any(|(key, value)| {
+                key.eq_ignore_ascii_case("receiver") && value.eq_ignore_ascii_case(authserv_id)
+            })
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::AuthResult;
+    use crate::smtp::{command::SmtpMail, SmtpPath};
+    use async_std::io::WriteExt;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.0.lock().expect("lock").extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn session(capture: &Capture) -> SmtpSession {
+        let mut session = SmtpSession {
+            service_name: "mx.example.org".to_owned(),
+            peer_name: Some("mail.example.com".to_owned()),
+            ..Default::default()
+        };
+        session.connection.peer_addr = "192.0.2.1:1234".to_owned();
+        session
+            .auth_results
+            .push(AuthResult::new("iprev", "pass").with_property("policy.iprev", "192.0.2.1"));
+        session.transaction.mail = Some(SmtpMail::Mail(
+            SmtpPath::Mailbox {
+                name: "joe".to_owned(),
+                host: crate::smtp::SmtpHost::Domain("example.com".to_owned()),
+                relays: vec![],
+            },
+            vec![],
+        ));
+        session.transaction.sink = Some(Box::pin(capture.clone()));
+        session
+    }
+
+    #[test]
+    fn prepend_results_and_pass_the_body_on() {
+        async_std::task::block_on(async move {
+            let capture = Capture::default();
+            let mut session = session(&capture);
+            // content checks ran before the dispatch
+            session
+                .transaction
+                .auth_results
+                .push(AuthResult::new("spf", "pass").with_property("smtp.mailfrom", "example.com"));
+            let sut = AuthResultsHeader::default();
+            sut.open_mail_body(&mut session).await.expect("dispatch");
+            let mut sink = session.transaction.sink.take().expect("sink");
+            sink.write_all(b"Subject: hi\r\n").await.expect("write");
+            sink.flush().await.expect("flush");
+            // the field may still be folded
+            assert!(
+                !String::from_utf8_lossy(capture.0.lock().expect("lock").as_slice())
+                    .contains("Subject")
+            );
+
+            sink.write_all(b"\r\nHello\r\n").await.expect("write");
+            sink.write_all(b"more\r\n").await.expect("write");
+            assert!(capture.0.lock().expect("lock").ends_with(b"more\r\n"));
+            poll_fn(|cx| sink.as_mut().poll_close(cx))
+                .await
+                .expect("close");
+
+            assert_eq!(
+                String::from_utf8_lossy(capture.0.lock().expect("lock").as_slice()),
+                "Authentication-Results: mx.example.org;\r\n\
+                 \tiprev=pass policy.iprev=192.0.2.1;\r\n\
+                 \tspf=pass smtp.mailfrom=example.com\r\n\
+                 Received-SPF: pass (mx.example.org: SPF pass for example.com) \
+                 receiver=mx.example.org; client-ip=192.0.2.1; \
+                 envelope-from=\"joe@example.com\"; helo=mail.example.com;\r\n\
+                 Subject: hi\r\n\r\nHello\r\nmore\r\n"
+            );
+        })
+    }
+
+    async fn received(mail: &[u8], piece: usize) -> String {
+        let capture = Capture::default();
+        let mut session = session(&capture);
+        session.auth_results.clear();
+        AuthResultsHeader::default()
+            .open_mail_body(&mut session)
+            .await
+            .expect("dispatch");
+        let mut sink = session.transaction.sink.take().expect("sink");
+        for chunk in mail.chunks(piece) {
+            sink.write_all(chunk).await.expect("write");
+        }
+        poll_fn(|cx| sink.as_mut().poll_close(cx))
+            .await
+            .expect("close");
+        let received = capture.0.lock().expect("lock").clone();
+        String::from_utf8(received).expect("utf8")
+    }
+
+    #[test]
+    fn strip_forged_results() {
+        async_std::task::block_on(async move {
+            let mail = b"Authentication-Results: MX.example.org 1;\r\n\tdkim=pass\r\n\
+                Authentication-Results: other.example.net; spf=fail\r\n\
+                Received-SPF: pass receiver=mx.example.org; client-ip=192.0.2.9\r\n\
+                Subject: Authentication-Results: mx.example.org; x\r\n\
+                \r\n\
+                Authentication-Results: mx.example.org; body is not touched\r\n";
+            for piece in [1, 7, mail.len()] {
+                assert_eq!(
+                    received(mail, piece).await,
+                    "Authentication-Results: mx.example.org; none\r\n\
+                     Authentication-Results: other.example.net; spf=fail\r\n\
+                     Subject: Authentication-Results: mx.example.org; x\r\n\
+                     \r\n\
+                     Authentication-Results: mx.example.org; body is not touched\r\n"
+                );
+            }
+        })
+    }
+
+    #[test]
+    fn strip_forged_results_after_oversized_fields() {
+        async_std::task::block_on(async move {
+            let padding = "x".repeat(AuthResultsHeader::HEADER_LIMIT);
+            let big = format!("X-Big: {}\r\n", padding);
+            let mail = format!(
+                "{}Received-SPF: pass {}\r\n\treceiver=mx.example.org\r\n\
+                 Authentication-Results: mx.example.org; spf=pass\r\n\
+                 Subject: hi\r\n\r\nbody\r\n",
+                big, padding
+            );
+            assert_eq!(
+                received(mail.as_bytes(), 4096).await,
+                format!(
+                    "Authentication-Results: mx.example.org; none\r\n\
+                     {}Subject: hi\r\n\r\nbody\r\n",
+                    big
+                )
+            );
+        })
+    }
+}
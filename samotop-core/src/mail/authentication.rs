@@ -0,0 +1,130 @@
+use crate::common::*;
+use std::sync::Mutex;
+
+/// The result of one message authentication check - SPF, DKIM, DMARC, iprev...
+///
+/// Checks record their results in `session.transaction.auth_results`,
+/// or in `session.auth_results` if they apply to the whole session,
+/// so that other checks can build on them and so they can be reported
+/// in the `Authentication-Results` header (RFC 8601) - see `AuthResultsHeader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthResult {
+    /// The authentication method such as "spf" or "dkim"
+    pub method: String,
+    /// The result such as "pass", "fail" or "none"
+    pub result: String,
+    /// Human readable explanation of the result
+    pub reason: Option<String>,
+    /// Properties as "ptype.property" and value pairs such as "header.d" and "example.com"
+    pub properties: Vec<(String, String)>,
+}
+
+impl AuthResult {
+    pub fn new(method: impl Into<String>, result: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            result: result.into(),
+            reason: None,
+            properties: vec![],
+        }
+    }
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+    pub fn with_property(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties.push((name.into(), value.into()));
+        self
+    }
+    /// The value of the first property with the given name, case insensitive
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// Is this a result of the given method, case insensitive?
+    pub fn is_method(&self, method: &str) -> bool {
+        self.method.eq_ignore_ascii_case(method)
+    }
+    /// Did the check pass?
+    pub fn is_pass(&self) -> bool {
+        self.result.eq_ignore_ascii_case("pass")
+    }
+}
+
+/// Formats the result as a resinfo of the `Authentication-Results` header
+impl fmt::Display for AuthResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.method, self.result)?;
+        if let Some(ref reason) = self.reason {
+            write!(
+                f,
+                " reason=\"{}\"",
+                reason.replace('\\', "\\\\").replace('"', "\\\"")
+            )?;
+        }
+        for (name, value) in self.properties.iter() {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Authentication results of a mail transaction.
+///
+/// Clones share the results so that a mail data sink can pick up
+/// the results of the checks that run after the mail data have been received.
+#[derive(Clone, Default)]
+pub struct AuthResults {
+    results: Arc<Mutex<Vec<AuthResult>>>,
+}
+
+impl AuthResults {
+    pub fn push(&self, result: AuthResult) {
+        self.lock().push(result)
+    }
+    /// A copy of the results recorded so far
+    pub fn to_vec(&self) -> Vec<AuthResult> {
+        self.lock().clone()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<AuthResult>> {
+        self.results.lock().expect("auth results lock")
+    }
+}
+
+impl From<Vec<AuthResult>> for AuthResults {
+    fn from(results: Vec<AuthResult>) -> Self {
+        Self {
+            results: Arc::new(Mutex::new(results)),
+        }
+    }
+}
+
+impl fmt::Debug for AuthResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.lock().iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_resinfo() {
+        let sut = AuthResult::new("dkim", "fail")
+            .with_reason("body hash \"bh\" mismatch")
+            .with_property("header.d", "example.com")
+            .with_property("header.s", "sel");
+        assert_eq!(
+            sut.to_string(),
+            "dkim=fail reason=\"body hash \\\"bh\\\" mismatch\" header.d=example.com header.s=sel"
+        );
+        assert_eq!(sut.property("Header.D"), Some("example.com"));
+        assert!(!sut.is_pass());
+    }
+}
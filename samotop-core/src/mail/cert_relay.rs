@@ -0,0 +1,211 @@
+use crate::{
+    common::*,
+    io::tls::TlsInfo,
+    mail::*,
+    smtp::{SmtpPath, SmtpSession},
+};
+
+/// A mail guard that allows relaying to non-local recipients only
+/// for clients authenticated with a TLS client certificate.
+///
+/// The client certificate must be verified by the TLS provider
+/// (see the client auth setup of the TLS provider) and its fingerprint
+/// or subject must be on the allow-list.
+/// Recipients in local domains are left for other guards to decide.
+/// It can also decide on the certificates for `RelayPolicy`, see `RelayPolicy::with_cert_relay()`.
+#[derive(Debug, Default, Clone)]
+pub struct CertRelay {
+    local_domains: LocalDomains,
+    fingerprints: Vec<String>,
+    subjects: Vec<String>,
+}
+
+impl CertRelay {
+    /// Treat recipients in the given domain as local - no relaying
+    pub fn with_local_domain(mut self, domain: impl AsRef<str>) -> Self {
+        self.local_domains = self.local_domains.with_domain(domain);
+        self
+    }
+    /// Treat recipients in the given domains as local - no relaying
+    pub fn with_local_domains(mut self, domains: LocalDomains) -> Self {
+        self.local_domains = domains;
+        self
+    }
+    /// Allow relaying for a client certificate with the given SHA-256 fingerprint.
+    /// Hex encoded, case and colons do not matter.
+    pub fn allow_fingerprint(mut self, fingerprint: impl AsRef<str>) -> Self {
+        self.fingerprints
+            .push(Self::normalize_fingerprint(fingerprint.as_ref()));
+        self
+    }
+    /// Allow relaying for a client certificate with the given subject, such as "CN=client.example.org"
+    pub fn allow_subject(mut self, subject: impl ToString) -> Self {
+        self.subjects.push(subject.to_string());
+        self
+    }
+    /// Is the recipient in one of the local domains?
+    pub fn is_local(&self, path: &SmtpPath) -> bool {
+        self.local_domains.contains(path)
+    }
+    /// Is the TLS client certificate verified and on the allow-list?
+    pub fn is_allowed(&self, tls: Option<&TlsInfo>) -> bool {
+        // an unverified certificate says whatever the client wants
+        let cert = match tls.filter(|tls| tls.peer_verified) {
+            None => return false,
+            Some(tls) => match tls.peer_certificate() {
+                None => return false,
+                Some(cert) => cert,
+            },
+        };
+        let fingerprint = Self::normalize_fingerprint(cert.fingerprint.as_str());
+        self.fingerprints.contains(&fingerprint)
+            || cert
+                .subject
+                .as_ref()
+                .map(|subject| self.subjects.contains(subject))
+                .unwrap_or_default()
+    }
+    fn normalize_fingerprint(fingerprint: &str) -> String {
+        fingerprint
+            .chars()
+            .filter(|c| *c != ':')
+            .collect::<String>()
+            .to_ascii_lowercase()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for CertRelay {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for CertRelay {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = if self.is_local(&rcpt.address) || self.is_allowed(session.tls.as_ref()) {
+            AddRecipientResult::Inconclusive(rcpt)
+        } else {
+            AddRecipientResult::Failed(
+                AddRecipientFailure::RejectedPermanently,
+                format!(
+                    "Relaying to {} denied, client certificate is not allowed",
+                    rcpt.address
+                ),
+            )
+        };
+        Box::pin(ready(result))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tls::PeerCertificate;
+    use crate::smtp::SmtpHost;
+
+    fn rcpt(domain: &str) -> Recipient {
+        Recipient::new(SmtpPath::Mailbox {
+            name: "user".to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        })
+    }
+
+    fn tls(subject: &str, fingerprint: &str) -> TlsInfo {
+        TlsInfo {
+            peer_certificates: vec![PeerCertificate {
+                subject: Some(subject.to_owned()),
+                fingerprint: fingerprint.to_owned(),
+                der: vec![],
+            }],
+            peer_verified: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn local_recipient_is_inconclusive() {
+        let sut = CertRelay::default().with_local_domain("Example.org");
+        let mut session = SmtpSession::default();
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.ORG")));
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    }
+
+    #[test]
+    fn relay_without_certificate_is_denied() {
+        let sut = CertRelay::default()
+            .with_local_domain("example.org")
+            .allow_subject("CN=client");
+        let mut session = SmtpSession::default();
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.com")));
+        assert!(matches!(
+            res,
+            AddRecipientResult::Failed(AddRecipientFailure::RejectedPermanently, _)
+        ));
+    }
+
+    #[test]
+    fn relay_with_allowed_fingerprint_is_inconclusive() {
+        let sut = CertRelay::default().allow_fingerprint("AB:CD:EF");
+        let mut session = SmtpSession {
+            tls: Some(tls("CN=other", "abcdef")),
+            ..Default::default()
+        };
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.com")));
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    }
+
+    #[test]
+    fn relay_with_allowed_subject_is_inconclusive() {
+        let sut = CertRelay::default().allow_subject("CN=client");
+        let mut session = SmtpSession {
+            tls: Some(tls("CN=client", "abcdef")),
+            ..Default::default()
+        };
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.com")));
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    }
+
+    #[test]
+    fn relay_with_unknown_certificate_is_denied() {
+        let sut = CertRelay::default()
+            .allow_subject("CN=client")
+            .allow_fingerprint("00");
+        let mut session = SmtpSession {
+            tls: Some(tls("CN=other", "abcdef")),
+            ..Default::default()
+        };
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.com")));
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+    }
+
+    #[test]
+    fn relay_with_unverified_certificate_is_denied() {
+        let sut = CertRelay::default().allow_subject("CN=client");
+        let mut session = SmtpSession {
+            tls: Some(TlsInfo {
+                peer_verified: false,
+                ..tls("CN=client", "abcdef")
+            }),
+            ..Default::default()
+        };
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.com")));
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{common::*, io::tls::MayBeTls, mail::*, smtp::*};
+use crate::{
+    common::*,
+    io::tls::MayBeTls,
+    mail::*,
+    smtp::{command::SmtpHelo, *},
+};
 
 /// Service builder configuration passed to `MailSetup`
 #[derive(Debug)]
@@ -190,36 +195,153 @@ impl SessionService for SvcBunch<Box<dyn SessionService + Sync + Send>> {
     }
 }
 
-impl MailGuard for SvcBunch<Box<dyn MailGuard + Sync + Send>> {
-    fn add_recipient<'a, 's, 'f>(
+/// Is the client or the sender of the current transaction whitelisted?
+fn is_whitelisted(session: &SmtpSession) -> bool {
+    session.whitelisted || session.transaction.whitelisted
+}
+
+impl SvcBunch<Box<dyn MailGuard + Sync + Send>> {
+    /// Pass the recipient to the guards starting with the one at index `first`.
+    ///
+    /// Each recipient a guard expands to, such as the targets of an alias,
+    /// goes on to the guards after that guard, just like the recipient itself.
+    /// If any of them is refused, the whole recipient is refused.
+    /// The accepted ones are returned in `Recipient::expansion`.
+    fn add_recipient_from<'a, 's, 'f>(
         &'a self,
+        first: usize,
         session: &'s mut SmtpSession,
         mut rcpt: Recipient,
     ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let fut = async move {
+            let mut expansion = vec![];
+            for (index, guard) in self.items.iter().enumerate().skip(first) {
+                if guard.is_check() && (is_whitelisted(session) || rcpt.whitelisted) {
+                    trace!("Guard {} add_recipient skipping {:?}", self.id, guard);
+                    continue;
+                }
+                trace!("Guard {} add_recipient calling {:?}", self.id, guard);
+                match guard.add_recipient(session, rcpt).await {
+                    AddRecipientResult::Inconclusive(mut r) => {
+                        for mut expanded in std::mem::take(&mut r.expansion) {
+                            expanded.whitelisted |= r.whitelisted;
+                            match self.add_recipient_from(index + 1, session, expanded).await {
+                                AddRecipientResult::Inconclusive(mut expanded) => {
+                                    let nested = std::mem::take(&mut expanded.expansion);
+                                    expansion.push(expanded);
+                                    expansion.extend(nested);
+                                }
+                                AddRecipientResult::Accepted
+                                | AddRecipientResult::AcceptedWithNewPath(_) => {}
+                                failed => return failed,
+                            }
+                        }
+                        rcpt = r
+                    }
+                    otherwise => return otherwise,
+                }
+            }
+            match Dummy.add_recipient(session, rcpt).await {
+                AddRecipientResult::Inconclusive(mut r) => {
+                    r.expansion.extend(expansion);
+                    AddRecipientResult::Inconclusive(r)
+                }
+                otherwise => otherwise,
+            }
+        };
+        Box::pin(fut)
+    }
+}
+
+impl MailGuard for SvcBunch<Box<dyn MailGuard + Sync + Send>> {
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
     where
         'a: 'f,
         's: 'f,
     {
         trace!(
-            "Guard {} with {} guards add_recipient {:?} to mail id {}",
+            "Guard {} with {} guards open_session conn id {}",
             self.id,
             self.items.len(),
-            rcpt,
-            session.transaction.id
+            session.connection.id
         );
         let fut = async move {
             for guard in self.items.iter() {
-                trace!("Guard {} add_recipient calling {:?}", self.id, guard);
-                match guard.add_recipient(session, rcpt).await {
-                    AddRecipientResult::Inconclusive(r) => rcpt = r,
+                if guard.is_check() && session.whitelisted {
+                    trace!("Guard {} open_session skipping {:?}", self.id, guard);
+                    continue;
+                }
+                trace!("Guard {} open_session calling {:?}", self.id, guard);
+                match guard.open_session(session).await {
+                    OpenSessionResult::Accepted => {}
+                    otherwise => return otherwise,
+                }
+            }
+            Dummy.open_session(session).await
+        };
+        Box::pin(fut)
+    }
+
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        trace!(
+            "Guard {} with {} guards check_helo {} conn id {}",
+            self.id,
+            self.items.len(),
+            helo.host,
+            session.connection.id
+        );
+        let fut = async move {
+            for guard in self.items.iter() {
+                if guard.is_check() && session.whitelisted {
+                    trace!("Guard {} check_helo skipping {:?}", self.id, guard);
+                    continue;
+                }
+                trace!("Guard {} check_helo calling {:?}", self.id, guard);
+                match guard.check_helo(session, helo).await {
+                    CheckHeloResult::Accepted => {}
                     otherwise => return otherwise,
                 }
             }
-            Dummy.add_recipient(session, rcpt).await
+            Dummy.check_helo(session, helo).await
         };
         Box::pin(fut)
     }
 
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        trace!(
+            "Guard {} with {} guards add_recipient {:?} to mail id {}",
+            self.id,
+            self.items.len(),
+            rcpt,
+            session.transaction.id
+        );
+        self.add_recipient_from(0, session, rcpt)
+    }
+
     fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
     where
         'a: 'f,
@@ -233,6 +355,10 @@ impl MailGuard for SvcBunch<Box<dyn MailGuard + Sync + Send>> {
         );
         let fut = async move {
             for guard in self.items.iter() {
+                if guard.is_check() && is_whitelisted(session) {
+                    trace!("Guard {} start_mail skipping {:?}", self.id, guard);
+                    continue;
+                }
                 trace!("Guard {} start_mail calling {:?}", self.id, guard);
                 match guard.start_mail(session).await {
                     StartMailResult::Accepted => {}
@@ -243,6 +369,34 @@ impl MailGuard for SvcBunch<Box<dyn MailGuard + Sync + Send>> {
         };
         Box::pin(fut)
     }
+
+    fn check_body<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        trace!(
+            "Guard {} with {} guards check_body id {}",
+            self.id,
+            self.items.len(),
+            session.transaction.id
+        );
+        let fut = async move {
+            for guard in self.items.iter() {
+                if guard.is_check() && is_whitelisted(session) {
+                    trace!("Guard {} check_body skipping {:?}", self.id, guard);
+                    continue;
+                }
+                trace!("Guard {} check_body calling {:?}", self.id, guard);
+                match guard.check_body(session).await {
+                    CheckBodyResult::Accepted => {}
+                    otherwise => return otherwise,
+                }
+            }
+            Dummy.check_body(session).await
+        };
+        Box::pin(fut)
+    }
 }
 
 impl MailDispatch for SvcBunch<Box<dyn MailDispatch + Sync + Send>> {
@@ -1,7 +1,7 @@
 use crate::{
     common::*,
     mail::Recipient,
-    smtp::{SmtpPath, SmtpSession},
+    smtp::{command::SmtpHelo, SmtpPath, SmtpReply, SmtpSession},
 };
 use std::ops::Deref;
 
@@ -9,13 +9,65 @@ use std::ops::Deref;
 A mail guard opens the mail transaction after a MAIL command - `start_mail`.
 It will then be queried whether each individual recepient (RCPT command) is accepted on which address.
 It can also modify the recipient address with an optional notification back to the client.
+
+Before that, it has a chance to refuse the connection before the banner - `open_session`,
+and to refuse the client greeting - `check_helo`. Both accept by default.
+
+Guards that only check and refuse, such as SPF, say so with `is_check`. The guard chain skips them
+for whitelisted clients (`SmtpSession::whitelisted`), senders (`Transaction::whitelisted`)
+and recipients (`Recipient::whitelisted`). Guards that also adjust the mail, such as aliases, always run.
 */
 pub trait MailGuard: fmt::Debug {
+    /// Does this guard only check and refuse? Then whitelisted subjects pass without it.
+    fn is_check(&self) -> bool {
+        false
+    }
+    /// The connection has been opened and the banner is about to be sent.
+    /// Here we have the opportunity to check the peer address and refuse the session.
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(OpenSessionResult::Accepted))
+    }
+    /// The client introduced itself with HELO/EHLO/LHLO.
+    /// Here we have the opportunity to check the announced name and refuse the greeting.
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        Box::pin(ready(CheckHeloResult::Accepted))
+    }
     /// Open the mail transaction. Here we have the opportunity to check the sender, adjust transaction ID...
+    /// A guard checking the content enables `session.transaction.spool` here. It may also wrap
+    /// `session.transaction.sink` to inspect the held mail data as it arrives, for instance to hash it.
     fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
     where
         'a: 'f,
         's: 'f;
+    /// All the mail data has been received and held back in `session.transaction.spool`.
+    /// Here we have the opportunity to check the content and decide the fate of the mail.
+    /// The mail is only dispatched once all guards accept it.
+    /// The inspecting sink set up in `start_mail` has been closed and is still in place.
+    /// This is only called if a guard enabled the spool in `start_mail`,
+    /// otherwise the mail data goes straight to the dispatch.
+    fn check_body<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(CheckBodyResult::Accepted))
+    }
     /// Add given RCPT to the list. Here we can immediately add and further processing will stop with success.
     /// Or we can refuse and stop further processing with a failure.
     /// Last option is to return `Inconclusive` in which case other MailGuards will have a chance.
@@ -35,6 +87,31 @@ where
     T: fmt::Debug + Send + Sync,
     S: Sync,
 {
+    fn is_check(&self) -> bool {
+        S::is_check(Deref::deref(self))
+    }
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::open_session(Deref::deref(self), session).await })
+    }
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        Box::pin(async move { S::check_helo(Deref::deref(self), session, helo).await })
+    }
     fn add_recipient<'a, 's, 'f>(
         &'a self,
         session: &'s mut SmtpSession,
@@ -53,6 +130,13 @@ where
     {
         Box::pin(async move { S::start_mail(Deref::deref(self), session).await })
     }
+    fn check_body<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::check_body(Deref::deref(self), session).await })
+    }
 }
 
 impl MailGuard for Dummy {
@@ -78,6 +162,75 @@ impl MailGuard for Dummy {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenSessionResult {
+    /// Failure with explanation for the logs, the session will be closed
+    Failed(OpenSessionFailure, String),
+    /// 220 Service ready
+    Accepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenSessionFailure {
+    /// 554 No SMTP service here
+    NoService,
+    /// 521 Host does not accept mail (RFC 7504)
+    MailNotAccepted,
+    /// 421 <domain> Service not available, closing transmission channel
+    FailedTemporarily,
+    /// The given reply, such as a policy text, the session will be closed
+    Custom(SmtpReply),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckHeloResult {
+    /// Failure with explanation for the logs
+    Failed(CheckHeloFailure, String),
+    /// 250 greeting accepted
+    Accepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckHeloFailure {
+    /// 421 <domain> Service not available, closing transmission channel
+    TerminateSession,
+    /// 550 Requested action not taken: command rejected for policy reasons
+    Rejected,
+    /// 501 Syntax error in parameters or arguments - such as an invalid host name
+    InvalidName,
+    /// 451 Requested action aborted: local error in processing
+    FailedTemporarily,
+    /// The given reply, such as a policy text
+    Custom(SmtpReply),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckBodyResult {
+    /// Failure with explanation for the logs, the mail will not be dispatched
+    Failed(CheckBodyFailure, String),
+    /// 250 the mail will be held back from dispatch with the given reason.
+    /// The guard is responsible for keeping the spooled copy.
+    Quarantine(String),
+    /// 250 the mail will be silently dropped for the given reason
+    Discard(String),
+    /// 250 the mail will be dispatched
+    Accepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckBodyFailure {
+    /// 421  <domain> Service not available, closing transmission channel
+    TerminateSession,
+    /// 550 Requested action not taken: rejected for policy reasons
+    Rejected,
+    /// 552  Requested mail action aborted: exceeded storage allocation
+    StorageExhaustedPermanently,
+    /// 451  Requested action aborted: local error in processing
+    FailedTemporarily,
+    /// The given reply, such as a policy text
+    Custom(SmtpReply),
+}
+
 #[derive(Debug, PartialEq, Eq)]
 #[allow(clippy::large_enum_variant)]
 pub enum StartMailResult {
@@ -110,6 +263,10 @@ pub enum StartMailFailure {
     InvalidParameter,
     /// 455  Server unable to accommodate parameters
     InvalidParameterValue,
+    /// 530  Must issue a STARTTLS command first
+    EncryptionRequired,
+    /// The given reply, such as a policy text
+    Custom(SmtpReply),
 }
 
 #[derive(Debug)]
@@ -146,10 +303,130 @@ pub enum AddRecipientFailure {
     StorageExhaustedPermanently,
     /// 452  Requested action not taken: insufficient system storage
     StorageExhaustedTemporarily,
+    /// 452  Too many recipients
+    TooManyRecipients,
     /// 451  Requested action aborted: local error in processing
     FailedTemporarily,
     /// 555  MAIL FROM/RCPT TO parameters not recognized or not implemented
     InvalidParameter,
     /// 455  Server unable to accommodate parameters
     InvalidParameterValue,
+    /// The given reply, such as a policy text
+    Custom(SmtpReply),
+}
+
+#[cfg(test)]
+type BodyVerdict = Arc<dyn Fn(&SmtpSession) -> CheckBodyResult + Send + Sync>;
+
+/// A guard giving the verdicts chosen by the test, accepting anything else
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub(crate) struct TestGuard {
+    open_session: Option<OpenSessionResult>,
+    check_helo: Option<CheckHeloResult>,
+    check_body: Option<BodyVerdict>,
+    checking: bool,
+}
+
+#[cfg(test)]
+impl TestGuard {
+    pub fn open_session(mut self, result: OpenSessionResult) -> Self {
+        self.open_session = Some(result);
+        self
+    }
+    pub fn check_helo(mut self, result: CheckHeloResult) -> Self {
+        self.check_helo = Some(result);
+        self
+    }
+    /// Behave as a check guard, skipped for whitelisted subjects
+    pub fn checking(mut self) -> Self {
+        self.checking = true;
+        self
+    }
+    pub fn check_body(
+        mut self,
+        verdict: impl Fn(&SmtpSession) -> CheckBodyResult + Send + Sync + 'static,
+    ) -> Self {
+        self.check_body = Some(Arc::new(verdict));
+        self
+    }
+}
+
+#[cfg(test)]
+impl fmt::Debug for TestGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestGuard")
+            .field("open_session", &self.open_session)
+            .field("check_helo", &self.check_helo)
+            .field("check_body", &self.check_body.is_some())
+            .field("checking", &self.checking)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+impl<T: crate::mail::AcceptsGuard> crate::mail::MailSetup<T> for TestGuard {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+#[cfg(test)]
+impl MailGuard for TestGuard {
+    fn is_check(&self) -> bool {
+        self.checking
+    }
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = self.open_session.clone();
+        Box::pin(ready(result.unwrap_or(OpenSessionResult::Accepted)))
+    }
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        let result = self.check_helo.clone();
+        Box::pin(ready(result.unwrap_or(CheckHeloResult::Accepted)))
+    }
+    fn check_body<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = match self.check_body {
+            Some(ref verdict) => verdict(session),
+            None => CheckBodyResult::Accepted,
+        };
+        Box::pin(ready(result))
+    }
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
 }
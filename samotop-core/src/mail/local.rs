@@ -0,0 +1,74 @@
+use super::access::matches_domain;
+use crate::smtp::SmtpPath;
+use std::iter::FromIterator;
+
+/// The domains we receive mail for, as opposed to relaying it elsewhere.
+///
+/// Domains are given exactly, `example.org`, or as a wildcard for the subdomains, `*.example.org`.
+/// The postmaster is always local. Build the set once and pass it to each guard that needs it,
+/// such as `RelayPolicy` and `CertRelay`, so they agree on what is local.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LocalDomains {
+    domains: Vec<String>,
+}
+
+impl LocalDomains {
+    /// Add a local domain, `*.example.org` for all its subdomains
+    pub fn with_domain(mut self, domain: impl AsRef<str>) -> Self {
+        let domain = domain.as_ref().trim_end_matches('.').to_ascii_lowercase();
+        if !self.domains.contains(&domain) {
+            self.domains.push(domain);
+        }
+        self
+    }
+    /// Is the domain local?
+    pub fn contains_domain(&self, domain: &str) -> bool {
+        self.domains
+            .iter()
+            .any(|pattern| pattern != "*" && matches_domain(pattern, domain))
+    }
+    /// Is the path in one of the local domains?
+    pub fn contains(&self, path: &SmtpPath) -> bool {
+        match path {
+            SmtpPath::Postmaster => true,
+            SmtpPath::Null => false,
+            SmtpPath::Mailbox { host, .. } => self.contains_domain(host.domain().as_str()),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for LocalDomains {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        iter.into_iter()
+            .fold(LocalDomains::default(), |local, domain| {
+                local.with_domain(domain)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smtp::SmtpHost;
+
+    #[test]
+    fn exact_and_wildcard_domains() {
+        let sut: LocalDomains = vec!["Example.org.", "*.example.net", "*"]
+            .into_iter()
+            .collect();
+        assert!(sut.contains_domain("example.ORG"));
+        assert!(sut.contains_domain("mx.example.net"));
+        assert!(!sut.contains_domain("example.net"));
+        assert!(!sut.contains_domain("example.com"));
+        assert!(sut.contains(&SmtpPath::Postmaster));
+        assert!(!sut.contains(&SmtpPath::Null));
+        assert!(sut.contains(&SmtpPath::Mailbox {
+            name: "user".to_owned(),
+            host: SmtpHost::Domain("example.org".to_owned()),
+            relays: vec![],
+        }));
+    }
+}
@@ -1,23 +1,41 @@
+mod access;
+mod aliases;
+mod auth_header;
+mod authentication;
 mod builder;
+mod cert_relay;
 mod configuration;
 mod dispatch;
 mod guard;
+mod local;
 mod logger;
 mod name;
 mod null;
 mod recipient;
+mod relay;
 mod service;
 mod setup;
+mod spool;
+mod subaddress;
 mod transaction;
 
+pub use self::access::*;
+pub use self::aliases::*;
+pub use self::auth_header::*;
+pub use self::authentication::*;
 pub use self::builder::*;
+pub use self::cert_relay::*;
 pub use self::configuration::*;
 pub use self::dispatch::*;
 pub use self::guard::*;
+pub use self::local::*;
 pub use self::logger::*;
 pub use self::name::*;
 pub use self::null::*;
 pub use self::recipient::*;
+pub use self::relay::*;
 pub use self::service::*;
 pub use self::setup::*;
+pub use self::spool::*;
+pub use self::subaddress::*;
 pub use self::transaction::*;
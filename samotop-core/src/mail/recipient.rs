@@ -3,6 +3,15 @@ use crate::smtp::SmtpPath;
 pub struct Recipient {
     pub address: SmtpPath,
     pub certificate: Option<Certificate>,
+    /// The address given by the client if a guard rewrote it, such as for X-Original-To
+    pub original: Option<SmtpPath>,
+    /// The sub-address detail, such as `tag` of `user+tag@example.org`
+    pub detail: Option<String>,
+    /// Further recipients this one expands to, such as the other members of an alias.
+    /// Each of them is checked by the guards after the expanding one as a recipient of its own.
+    pub expansion: Vec<Recipient>,
+    /// The recipient is whitelisted, the check guards are skipped for it
+    pub whitelisted: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +28,10 @@ impl Recipient {
         Recipient {
             address,
             certificate: None,
+            original: None,
+            detail: None,
+            expansion: vec![],
+            whitelisted: false,
         }
     }
 }
@@ -0,0 +1,222 @@
+use super::access::{in_network, peer_ip};
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{SmtpPath, SmtpReply, SmtpSession},
+};
+use std::net::IpAddr;
+
+/// A mail guard preventing open relay.
+///
+/// Recipients in the local domains and the postmaster are left for other guards to decide.
+/// Mail to other domains is only relayed for clients from the trusted networks
+/// or with a TLS client certificate allowed by the `CertRelay` given in `with_cert_relay()`,
+/// anyone else gets `550 5.7.1 relaying denied`. Clients, senders and recipients
+/// whitelisted by the `AccessList` are not checked.
+///
+/// Set it up before the guards rewriting recipients, such as `Aliases` or `Srs`,
+/// so that the alias given by the client is checked and all its targets may be forwarded.
+#[derive(Debug, Default, Clone)]
+pub struct RelayPolicy {
+    local_domains: LocalDomains,
+    trusted_networks: Vec<(IpAddr, u8)>,
+    cert_relay: Option<CertRelay>,
+}
+
+impl RelayPolicy {
+    /// Accept mail for the domain, `*.example.org` for all its subdomains
+    pub fn with_local_domain(mut self, domain: impl AsRef<str>) -> Self {
+        self.local_domains = self.local_domains.with_domain(domain);
+        self
+    }
+    /// Accept mail for the given domains
+    pub fn with_local_domains(mut self, domains: LocalDomains) -> Self {
+        self.local_domains = domains;
+        self
+    }
+    /// Relay mail for clients with a TLS client certificate allowed by the `CertRelay`.
+    /// Only the certificate allow-list is used, local domains are those of the `RelayPolicy`.
+    pub fn with_cert_relay(mut self, cert_relay: CertRelay) -> Self {
+        self.cert_relay = Some(cert_relay);
+        self
+    }
+    /// Relay mail for clients in the network, such as `(10.0.0.0, 8)`
+    pub fn with_trusted_network(mut self, network: IpAddr, prefix: u8) -> Self {
+        self.trusted_networks.push((network, prefix));
+        self
+    }
+    /// Is the recipient in one of the local domains?
+    pub fn is_local(&self, path: &SmtpPath) -> bool {
+        self.local_domains.contains(path)
+    }
+    /// May the client relay mail to other domains?
+    pub fn is_relay_allowed(&self, session: &SmtpSession) -> bool {
+        let trusted = peer_ip(session)
+            .map(|ip| {
+                self.trusted_networks
+                    .iter()
+                    .any(|(net, prefix)| in_network(ip, *net, *prefix))
+            })
+            .unwrap_or_default();
+        trusted
+            || self
+                .cert_relay
+                .as_ref()
+                .map(|cert_relay| cert_relay.is_allowed(session.tls.as_ref()))
+                .unwrap_or_default()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for RelayPolicy {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for RelayPolicy {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = if self.is_local(&rcpt.address) || self.is_relay_allowed(session) {
+            AddRecipientResult::Inconclusive(rcpt)
+        } else {
+            AddRecipientResult::Failed(
+                AddRecipientFailure::Custom(SmtpReply::Custom(
+                    550,
+                    "5.7.1 relaying denied".to_owned(),
+                )),
+                format!(
+                    "Relaying to {} denied for {}",
+                    rcpt.address, session.connection.peer_addr
+                ),
+            )
+        };
+        Box::pin(ready(result))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tls::{PeerCertificate, TlsInfo};
+    use crate::smtp::SmtpHost;
+
+    fn rcpt(domain: &str) -> Recipient {
+        Recipient::new(SmtpPath::Mailbox {
+            name: "user".to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        })
+    }
+
+    fn sut() -> RelayPolicy {
+        RelayPolicy::default()
+            .with_local_domain("Example.org")
+            .with_local_domain("*.example.net")
+            .with_trusted_network("10.0.0.0".parse().expect("ip"), 8)
+            .with_cert_relay(CertRelay::default().allow_subject("CN=client"))
+    }
+
+    fn session(peer: &str) -> SmtpSession {
+        let mut session = SmtpSession::default();
+        session.connection.peer_addr = peer.to_owned();
+        session
+    }
+
+    fn add(session: &mut SmtpSession, domain: &str) -> AddRecipientResult {
+        async_std::task::block_on(sut().add_recipient(session, rcpt(domain)))
+    }
+
+    #[test]
+    fn local_recipients_are_inconclusive() {
+        let mut session = session("192.0.2.1:2525");
+        for domain in &["example.ORG", "mx.example.net"] {
+            let res = add(&mut session, domain);
+            assert!(
+                matches!(res, AddRecipientResult::Inconclusive(_)),
+                "{}",
+                domain
+            );
+        }
+        let res = async_std::task::block_on(
+            sut().add_recipient(&mut session, Recipient::new(SmtpPath::Postmaster)),
+        );
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    }
+
+    #[test]
+    fn relay_is_denied() {
+        let mut session = session("192.0.2.1:2525");
+        for domain in &["example.com", "example.net", "example.org.example.com"] {
+            let res = add(&mut session, domain);
+            match res {
+                AddRecipientResult::Failed(AddRecipientFailure::Custom(reply), _) => {
+                    assert_eq!(reply.to_string(), "550 5.7.1 relaying denied\r\n")
+                }
+                other => panic!("Unexpected {:?} for {}", other, domain),
+            }
+        }
+    }
+
+    #[test]
+    fn relay_is_allowed_for_trusted_network() {
+        let mut session = session("10.1.2.3:2525");
+        let res = add(&mut session, "example.com");
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    }
+
+    fn tls(subject: &str) -> Option<TlsInfo> {
+        Some(TlsInfo {
+            peer_certificates: vec![PeerCertificate {
+                subject: Some(subject.to_owned()),
+                fingerprint: "abcd".to_owned(),
+                der: vec![],
+            }],
+            peer_verified: true,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn relay_is_allowed_for_allowed_certificate() {
+        let mut session = session("192.0.2.1:2525");
+        session.tls = tls("CN=client");
+        let res = add(&mut session, "example.com");
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    }
+
+    #[test]
+    fn relay_is_denied_for_other_certificate() {
+        let mut session = session("192.0.2.1:2525");
+        session.tls = tls("CN=other");
+        let res = add(&mut session, "example.com");
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+    }
+
+    #[test]
+    fn relay_is_denied_for_certificate_without_cert_relay() {
+        let sut = RelayPolicy::default().with_local_domain("example.org");
+        let mut session = session("192.0.2.1:2525");
+        session.tls = tls("CN=client");
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, rcpt("example.com")));
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+    }
+}
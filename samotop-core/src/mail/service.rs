@@ -2,9 +2,10 @@ use crate::{
     common::*,
     io::{tls::MayBeTls, ConnectionInfo, IoService},
     mail::{
-        AddRecipientResult, DispatchResult, MailDispatch, MailGuard, Recipient, StartMailResult,
+        AddRecipientResult, CheckBodyResult, CheckHeloResult, DispatchResult, MailDispatch,
+        MailGuard, OpenSessionResult, Recipient, StartMailResult,
     },
-    smtp::{Drive, Interpret, SessionService, SmtpContext, SmtpSession},
+    smtp::{command::SmtpHelo, Drive, Interpret, SessionService, SmtpContext, SmtpSession},
 };
 
 /// A short hand for all the mandatory mail services
@@ -79,6 +80,30 @@ impl MailDispatch for Service {
 }
 
 impl MailGuard for Service {
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.guard.open_session(session)
+    }
+
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        self.guard.check_helo(session, helo)
+    }
+
     fn add_recipient<'a, 's, 'f>(
         &'a self,
         session: &'s mut SmtpSession,
@@ -98,6 +123,14 @@ impl MailGuard for Service {
     {
         self.guard.start_mail(session)
     }
+
+    fn check_body<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.guard.check_body(session)
+    }
 }
 
 impl SessionService for Service {
@@ -0,0 +1,132 @@
+use crate::common::*;
+
+/// The mail data as received, held back for content checks at the end of DATA.
+///
+/// Spooling is off unless a guard enables it in `start_mail` by setting
+/// `session.transaction.spool`. Then the mail is only dispatched after all the checks accept it.
+/// The data is kept in memory up to the limit, the size keeps counting beyond it
+/// and a mail that did not fit is refused.
+#[derive(Default, Clone)]
+pub struct MailSpool {
+    data: Vec<u8>,
+    size: usize,
+    limit: Option<usize>,
+}
+
+impl MailSpool {
+    /// Hold at most `limit` bytes of the mail data
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+    /// Append received mail data
+    pub fn append(&mut self, chunk: &[u8]) {
+        self.size += chunk.len();
+        let room = match self.limit {
+            Some(limit) => limit.saturating_sub(self.data.len()),
+            None => chunk.len(),
+        };
+        self.data
+            .extend_from_slice(&chunk[..std::cmp::min(room, chunk.len())]);
+    }
+    /// Total size of the mail data received, including what did not fit the limit
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// Did some of the mail data not fit the limit?
+    pub fn is_truncated(&self) -> bool {
+        self.size > self.data.len()
+    }
+    /// The spooled mail data
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+    /// The header section including the separating empty line
+    pub fn headers(&self) -> &[u8] {
+        &self.data[..self.body_start()]
+    }
+    /// The spooled body following the header section
+    pub fn body(&self) -> &[u8] {
+        &self.data[self.body_start()..]
+    }
+    /// Unfolded values of all headers with the given name, case insensitive
+    pub fn header(&self, name: &str) -> Vec<String> {
+        let headers = String::from_utf8_lossy(self.headers());
+        let mut values = vec![];
+        let mut current: Option<String> = None;
+        for line in headers.lines() {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // folded continuation
+                if let Some(ref mut value) = current {
+                    value.push_str(line);
+                }
+                continue;
+            }
+            values.extend(current.take());
+            if let Some((field, value)) = line.split_once(':') {
+                if field.trim().eq_ignore_ascii_case(name) {
+                    current = Some(value.trim_start().to_owned());
+                }
+            }
+        }
+        values.extend(current.take());
+        values
+    }
+    fn body_start(&self) -> usize {
+        let data = self.data.as_slice();
+        if data.starts_with(b"\r\n") {
+            return 2;
+        }
+        if data.starts_with(b"\n") {
+            return 1;
+        }
+        for i in 0..data.len() {
+            if data[i..].starts_with(b"\n\r\n") {
+                return i + 3;
+            }
+            if data[i..].starts_with(b"\n\n") {
+                return i + 2;
+            }
+        }
+        data.len()
+    }
+}
+
+impl fmt::Debug for MailSpool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailSpool")
+            .field("size", &self.size)
+            .field("limit", &self.limit)
+            .field("truncated", &self.is_truncated())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_and_body_are_split() {
+        let mut sut = MailSpool::default();
+        sut.append(b"Subject: hello\r\n wor");
+        sut.append(b"ld\r\nX-Spam: no\r\n\r\nbody\r\n");
+        assert_eq!(sut.header("subject"), vec!["hello world".to_owned()]);
+        assert_eq!(sut.header("X-SPAM"), vec!["no".to_owned()]);
+        assert_eq!(sut.body(), b"body\r\n");
+        assert_eq!(sut.size(), 44);
+        assert!(!sut.is_truncated());
+    }
+
+    #[test]
+    fn size_counts_beyond_limit() {
+        let mut sut = MailSpool::with_limit(4);
+        sut.append(b"abc");
+        sut.append(b"def");
+        assert_eq!(sut.data(), b"abcd");
+        assert_eq!(sut.size(), 6);
+        assert!(sut.is_truncated());
+    }
+}
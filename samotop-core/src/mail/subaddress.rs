@@ -0,0 +1,130 @@
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{SmtpPath, SmtpSession},
+};
+
+/// Sub-addressing, delivering mail for `user+tag@example.org` to `user@example.org`.
+///
+/// The local part is cut at the first separator, `+` by default. The recipient address
+/// is rewritten so that the following guards, such as the smime `Accounts` lookup,
+/// and the delivery see the plain mailbox. The tag is kept in `Recipient::detail`,
+/// for example as a folder hint, and the address as given in `Recipient::original`.
+///
+/// The `MailDir` dispatch of samotop-delivery gives each recipient a copy of its own
+/// with the `Delivered-To` and `X-Original-To` headers and can file it in the Maildir++
+/// folder named after the tag.
+///
+/// Set it up before the guards looking up the recipients.
+#[derive(Debug, Clone)]
+pub struct SubAddressing {
+    separators: String,
+}
+
+impl Default for SubAddressing {
+    fn default() -> Self {
+        Self {
+            separators: "+".to_owned(),
+        }
+    }
+}
+
+impl SubAddressing {
+    /// Any of the given characters separate the tag, such as `+-`
+    pub fn with_separators(mut self, separators: impl Into<String>) -> Self {
+        self.separators = separators.into();
+        self
+    }
+    /// The mailbox and the tag of the local part, if it has a tag
+    pub fn split<'l>(&self, local: &'l str) -> Option<(&'l str, &'l str)> {
+        let at = local.find(|c| self.separators.contains(c))?;
+        match &local[..at] {
+            "" => None,
+            mailbox => Some((mailbox, &local[at + 1..])),
+        }
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for SubAddressing {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for SubAddressing {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        mut rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let original = rcpt.address.clone();
+        if let SmtpPath::Mailbox { name, .. } = &mut rcpt.address {
+            if let Some((mailbox, detail)) = self.split(name.as_str()) {
+                let (mailbox, detail) = (mailbox.to_owned(), detail.to_owned());
+                trace!("Recipient {} has detail {:?}", original, detail);
+                *name = mailbox;
+                rcpt.detail = Some(detail);
+                rcpt.original.get_or_insert(original);
+            }
+        }
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smtp::SmtpHost;
+
+    fn path(name: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain("example.org".to_owned()),
+            relays: vec![],
+        }
+    }
+
+    fn add(sut: &SubAddressing, session: &mut SmtpSession, name: &str) -> Recipient {
+        match async_std::task::block_on(sut.add_recipient(session, Recipient::new(path(name)))) {
+            AddRecipientResult::Inconclusive(rcpt) => rcpt,
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tag_is_removed_and_kept() {
+        let sut = SubAddressing::default();
+        let mut session = SmtpSession::default();
+        let rcpt = add(&sut, &mut session, "user+lists+rust");
+        assert_eq!(rcpt.address, path("user"));
+        assert_eq!(rcpt.detail.as_deref(), Some("lists+rust"));
+        assert_eq!(rcpt.original, Some(path("user+lists+rust")));
+        assert!(session.transaction.extra_headers.is_empty());
+    }
+
+    #[test]
+    fn plain_addresses_are_left_alone() {
+        let sut = SubAddressing::default().with_separators("+-");
+        let mut session = SmtpSession::default();
+        for name in &["user", "+tag", "user.name"] {
+            let rcpt = add(&sut, &mut session, name);
+            assert_eq!(rcpt.address, path(name));
+            assert_eq!(rcpt.detail, None);
+            assert_eq!(rcpt.original, None);
+        }
+        let rcpt = add(&sut, &mut session, "owner-list");
+        assert_eq!(rcpt.address, path("owner"));
+    }
+}
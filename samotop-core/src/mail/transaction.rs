@@ -1,5 +1,5 @@
 use crate::common::{io::Write, *};
-use crate::mail::Recipient;
+use crate::mail::{AuthResults, MailSpool, Recipient};
 use crate::smtp::*;
 
 /// Mail envelope before sending mail data
@@ -13,6 +13,16 @@ pub struct Transaction {
     pub rcpts: Vec<Recipient>,
     /// Extra headers prepended to the e-mail
     pub extra_headers: String,
+    /// The sender requires verified TLS on every hop - REQUIRETLS (RFC 8689)
+    pub require_tls: bool,
+    /// Copy of the mail data for content checks, if enabled by a guard
+    pub spool: Option<MailSpool>,
+    /// Results of the message authentication checks - SPF, DKIM...
+    pub auth_results: AuthResults,
+    /// Notes left by guards about this mail transaction, such as rules that matched the sender
+    pub tags: Vec<String>,
+    /// The sender is whitelisted, the check guards are skipped for the rest of the transaction
+    pub whitelisted: bool,
     /// Write sink to write the mail into
     pub sink: Option<Pin<Box<dyn MailDataSink>>>,
 }
@@ -26,6 +36,11 @@ impl Transaction {
         self.mail = None;
         self.rcpts = vec![];
         self.extra_headers = String::new();
+        self.require_tls = false;
+        self.spool = None;
+        self.auth_results = AuthResults::default();
+        self.tags = vec![];
+        self.whitelisted = false;
     }
     pub fn is_empty(&self) -> bool {
         let Transaction {
@@ -33,12 +48,22 @@ impl Transaction {
             ref mail,
             ref rcpts,
             ref extra_headers,
+            ref require_tls,
+            ref spool,
+            ref auth_results,
+            ref tags,
+            ref whitelisted,
             ref sink,
         } = self;
         id.is_empty()
             && mail.is_none()
             && rcpts.is_empty()
             && extra_headers.is_empty()
+            && !require_tls
+            && spool.is_none()
+            && auth_results.is_empty()
+            && tags.is_empty()
+            && !whitelisted
             && sink.is_none()
     }
 }
@@ -50,6 +75,11 @@ impl fmt::Debug for Transaction {
             ref mail,
             ref rcpts,
             ref extra_headers,
+            ref require_tls,
+            ref spool,
+            ref auth_results,
+            ref tags,
+            ref whitelisted,
             sink: _sink,
         } = self;
         f.debug_struct("Transaction")
@@ -57,6 +87,11 @@ impl fmt::Debug for Transaction {
             .field("mail", mail)
             .field("rcpts", rcpts)
             .field("extra_headers", extra_headers)
+            .field("require_tls", require_tls)
+            .field("spool", spool)
+            .field("auth_results", auth_results)
+            .field("tags", tags)
+            .field("whitelisted", whitelisted)
             .field("sink", &"*")
             .finish()
     }
@@ -26,4 +26,20 @@ impl SmtpMail {
             SmtpMail::Soml(p, _) => p,
         }
     }
+    pub fn sender_mut(&mut self) -> &mut SmtpPath {
+        match self {
+            SmtpMail::Mail(p, _) => p,
+            SmtpMail::Send(p, _) => p,
+            SmtpMail::Saml(p, _) => p,
+            SmtpMail::Soml(p, _) => p,
+        }
+    }
+    pub fn parameters(&self) -> &[String] {
+        match self {
+            SmtpMail::Mail(_, p) => p,
+            SmtpMail::Send(_, p) => p,
+            SmtpMail::Saml(_, p) => p,
+            SmtpMail::Soml(_, p) => p,
+        }
+    }
 }
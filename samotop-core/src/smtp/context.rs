@@ -151,11 +151,16 @@ mod store_tests {
                         tv_nsec: --redacted--,
                     },
                 },
+                tls: None,
                 extensions: ExtensionSet {
                     map: {},
                 },
                 service_name: "samotop",
                 peer_name: None,
+                verified_peer_name: None,
+                tags: [],
+                whitelisted: false,
+                auth_results: [],
                 output: [],
                 input: [],
                 mode: None,
@@ -164,6 +169,11 @@ mod store_tests {
                     mail: None,
                     rcpts: [],
                     extra_headers: "",
+                    require_tls: false,
+                    spool: None,
+                    auth_results: [],
+                    tags: [],
+                    whitelisted: false,
                     sink: "*",
                 },
             },
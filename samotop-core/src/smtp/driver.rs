@@ -19,6 +19,24 @@ pub trait Drive: fmt::Debug {
         's: 'f;
 }
 
+impl Drive for Dummy {
+    /// Does nothing with the session
+    fn drive<'a, 'i, 'x, 's, 'f>(
+        &'a self,
+        _io: &'i mut Box<dyn MayBeTls>,
+        _interpretter: &'x (dyn Interpret + Send + Sync),
+        _state: &'s mut SmtpContext,
+    ) -> S1Fut<'f, std::result::Result<(), DriverError>>
+    where
+        'a: 'f,
+        'i: 'f,
+        'x: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(Ok(())))
+    }
+}
+
 #[cfg(feature = "driver")]
 #[derive(Debug)]
 pub struct SmtpDriver;
@@ -80,6 +98,11 @@ impl Drive for SmtpDriver {
                     }
                 }
 
+                if state.session.tls.is_none() {
+                    // pick up TLS details once the handshake is done
+                    state.session.tls = io.get_ref().tls_info();
+                }
+
                 match interpretter.interpret(state).await {
                     Ok(None) => {
                         // Action taken, but no input consumed (i.e. session setup / shut down)
@@ -3,3 +3,4 @@ use super::extensions::*;
 pub const STARTTLS: Flag = Flag { code: "STARTTLS" };
 pub const PIPELINING: Flag = Flag { code: "PIPELINING" };
 pub const EIGHTBITMIME: Flag = Flag { code: "8BITMIME" };
+pub const REQUIRETLS: Flag = Flag { code: "REQUIRETLS" };
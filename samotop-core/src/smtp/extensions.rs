@@ -69,6 +69,10 @@ impl ExtensionSet {
     pub fn disable_code(&mut self, code: &str) -> bool {
         self.map.remove(code).is_some()
     }
+    /// Enable all extensions of the other set, replacing existing values
+    pub fn merge(&mut self, other: ExtensionSet) {
+        self.map.extend(other.map)
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
@@ -10,10 +10,12 @@ mod path;
 #[cfg(feature = "prudence")]
 mod prudence;
 mod reply;
+mod require_tls;
 mod rfc2033;
 mod rfc3207;
 mod rfc5321;
 mod rfc821;
+mod rfc8689;
 mod session;
 mod session_service;
 
@@ -27,10 +29,12 @@ pub use self::path::*;
 #[cfg(feature = "prudence")]
 pub use self::prudence::*;
 pub use self::reply::*;
+pub use self::require_tls::*;
 pub use self::rfc2033::*;
 pub use self::rfc3207::*;
 pub use self::rfc5321::*;
 pub use self::rfc5321::*;
 pub use self::rfc821::*;
+pub use self::rfc8689::*;
 pub use self::session::*;
 pub use self::session_service::*;
@@ -1,40 +1,135 @@
 use crate::common::*;
 use crate::io::tls::MayBeTls;
-use crate::mail::{AcceptsInterpretter, AcceptsSessionService, MailSetup};
-use crate::smtp::{Interpret, InterpretResult, ParseError, SessionService, SmtpContext};
+use crate::mail::{
+    in_network, peer_ip, AcceptsGuard, AcceptsInterpretter, AcceptsSessionService,
+    AddRecipientFailure, AddRecipientResult, MailGuard, MailSetup, Recipient, StartMailResult,
+};
+use crate::smtp::{
+    DriverControl, Interpret, InterpretResult, ParseError, SessionService, SmtpContext, SmtpSession,
+};
 use smol_timeout::TimeoutExt;
+use std::net::IpAddr;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 /// Prevent bad SMTP behavior
 #[derive(Debug, Default, Clone)]
 pub struct Prudence {
-    /// Monitor bad behavior of clients not waiting for a banner given time
+    /// Hold the banner back for the given time and monitor clients not waiting for it
     wait_for_banner_delay: Option<Duration>,
+    /// First line of a multi-line banner sent before the banner delay
+    pre_greeting: Option<String>,
+    /// Networks of peers which do not wait for the banner
+    banner_delay_exemptions: Vec<(IpAddr, u8)>,
     /// Maximum read time
     read_timeout: Option<Duration>,
+    /// Maximum RCPT count per transaction
+    max_recipients: Option<usize>,
+    /// Maximum mail transactions per session
+    max_transactions: Option<usize>,
+    /// Maximum bad commands per session
+    max_errors: Option<usize>,
+    /// Delay added per bad command
+    error_delay: Option<Duration>,
+    /// Maximum total session time
+    max_session_duration: Option<Duration>,
+    /// Maximum DATA phase time
+    max_data_duration: Option<Duration>,
+    /// Minimum average DATA throughput - bytes per duration
+    min_data_throughput: Option<(usize, Duration)>,
 }
 
 impl Prudence {
-    /// Shut the session down if the client sends commands before the delayed banner
+    /// Hold the banner back for the given delay and
+    /// shut the session down if the client sends commands before the banner
     pub fn with_banner_delay(mut self, delay: Duration) -> Self {
         self.wait_for_banner_delay = Some(delay);
         self
     }
+    /// Send "220-`text`" right away as the first line of a multi-line banner
+    /// and complete the banner after the banner delay.
+    /// Pipelining bots tend to talk after the first line and get caught.
+    ///
+    /// The banner must be prepared before Prudence, so set Prudence up after Esmtp or Lmtp.
+    pub fn with_pre_greeting(mut self, text: impl Into<String>) -> Self {
+        self.pre_greeting = Some(text.into());
+        self
+    }
+    /// Peers from the given network (such as allow-listed relays)
+    /// get the banner without the banner delay and the pre-greeting
+    pub fn with_banner_delay_exemption(mut self, network: IpAddr, prefix: u8) -> Self {
+        self.banner_delay_exemptions.push((network, prefix));
+        self
+    }
+    fn is_exempt_from_banner_delay(&self, session: &SmtpSession) -> bool {
+        match peer_ip(session) {
+            Some(ip) => self
+                .banner_delay_exemptions
+                .iter()
+                .any(|(net, prefix)| in_network(ip, *net, *prefix)),
+            None => false,
+        }
+    }
     /// Shut the session down if the client takes too long to send a command
     pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
         self.read_timeout = Some(timeout);
         self
     }
+    /// Refuse further recipients of a transaction with 452 once there are `max`
+    pub fn with_max_recipients(mut self, max: usize) -> Self {
+        self.max_recipients = Some(max);
+        self
+    }
+    /// Shut the session down with 421 when the client starts more than `max` mail transactions.
+    /// Every MAIL command counts, accepted or not and with or without RSET before it.
+    pub fn with_max_transactions(mut self, max: usize) -> Self {
+        self.max_transactions = Some(max);
+        self
+    }
+    /// Shut the session down with 421 after `max` bad commands -
+    /// syntax errors, command sequence errors and unknown commands
+    pub fn with_max_errors(mut self, max: usize) -> Self {
+        self.max_errors = Some(max);
+        self
+    }
+    /// Tarpit - delay the reply to a bad command by the given delay times the bad command count
+    pub fn with_error_delay(mut self, delay: Duration) -> Self {
+        self.error_delay = Some(delay);
+        self
+    }
+    /// Shut the session down with 421 once it lasts longer than `duration`,
+    /// no matter how active the client is
+    pub fn with_max_session_duration(mut self, duration: Duration) -> Self {
+        self.max_session_duration = Some(duration);
+        self
+    }
+    /// Shut the session down with 421 if sending the mail data takes longer than `duration`
+    pub fn with_max_data_duration(mut self, duration: Duration) -> Self {
+        self.max_data_duration = Some(duration);
+        self
+    }
+    /// Shut the session down with 421 if the mail data arrive slower than `bytes` per `period`
+    /// on average. The throughput is first checked after one `period` into the DATA phase.
+    pub fn with_min_data_throughput(mut self, bytes: usize, period: Duration) -> Self {
+        self.min_data_throughput = Some((bytes, period));
+        self
+    }
 }
 
 impl<T> MailSetup<T> for Prudence
 where
-    T: AcceptsSessionService + AcceptsInterpretter,
+    T: AcceptsSessionService + AcceptsInterpretter + AcceptsGuard,
 {
     fn setup(self, config: &mut T) {
+        if let Some(max) = self.max_recipients {
+            config.add_first_guard(PrudentGuard {
+                max_recipients: max,
+            });
+        }
+        let config_copy = self.clone();
         config.wrap_interpretter(|inner| PrudentInterpretter {
             inner,
-            timeout: self.read_timeout,
+            config: config_copy,
         });
         config.wrap_session_service(|others| PrudentService {
             config: self,
@@ -61,57 +156,152 @@ impl SessionService for PrudentService {
         's: 'f,
     {
         Box::pin(async move {
+            self.others.prepare_session(io, state).await;
+
             if let Some(delay) = self.config.wait_for_banner_delay {
-                let mut buf = [0u8; 425];
-                use async_std::io::ReadExt;
-                match io.read(&mut buf[..]).timeout(delay).await {
-                    Some(Ok(0)) => {
-                        // this just looks like the client gave up and left
-                        warn!("{} touch and go!", state.session.connection.peer_addr)
-                    }
-                    Some(Ok(len)) => {
-                        state.session.input.extend_from_slice(&buf[0..len]);
-                        state.session.say_shutdown_processing_err(
-                            "Client sent commands before banner".into(),
-                        );
-                    }
-                    Some(Err(e)) => {
-                        state
-                            .session
-                            .say_shutdown_processing_err(format!("IO read failed {}", e));
-                    }
-                    None => {
-                        // timeout is correct behavior, well done!
-                    }
+                if self.config.is_exempt_from_banner_delay(&state.session) {
+                    debug!(
+                        "{} is exempt from the banner delay",
+                        state.session.connection.peer_addr
+                    );
+                } else {
+                    self.delay_banner(io, state, delay).await;
                 }
             }
 
+            let data_phase = DataPhase::default();
+            state.set(data_phase.clone());
             *io = Box::new(PrudentIo::new(
-                self.config.read_timeout,
+                &self.config,
+                data_phase,
                 std::mem::replace(io, Box::new(Dummy)),
             ));
-
-            self.others.prepare_session(io, state).await;
         })
     }
 }
 
+impl PrudentService {
+    /// Hold back the prepared banner until the delay passes
+    async fn delay_banner(
+        &self,
+        io: &mut Box<dyn MayBeTls>,
+        state: &mut SmtpContext,
+        delay: Duration,
+    ) {
+        let mut banner = std::mem::take(&mut state.session.output);
+
+        if let Some(ref text) = self.config.pre_greeting {
+            if Self::is_greeting(&banner) {
+                use async_std::io::WriteExt;
+                let line = format!("220-{}\r\n", text);
+                if let Err(e) = async {
+                    io.write_all(line.as_bytes()).await?;
+                    io.flush().await
+                }
+                .await
+                {
+                    state
+                        .session
+                        .say_shutdown_processing_err(format!("IO write failed {}", e));
+                    return;
+                }
+            }
+        }
+
+        let mut buf = [0u8; 425];
+        use async_std::io::ReadExt;
+        match io.read(&mut buf[..]).timeout(delay).await {
+            Some(Ok(0)) => {
+                // this just looks like the client gave up and left
+                warn!("{} touch and go!", state.session.connection.peer_addr)
+            }
+            Some(Ok(len)) => {
+                state.session.input.extend_from_slice(&buf[0..len]);
+                state
+                    .session
+                    .say_shutdown_processing_err("Client sent commands before banner".into());
+                return;
+            }
+            Some(Err(e)) => {
+                state
+                    .session
+                    .say_shutdown_processing_err(format!("IO read failed {}", e));
+                return;
+            }
+            None => {
+                // timeout is correct behavior, well done!
+            }
+        }
+
+        banner.append(&mut state.session.output);
+        state.session.output = banner;
+    }
+    /// Is the prepared output a service ready banner?
+    fn is_greeting(output: &[DriverControl]) -> bool {
+        matches!(output.first(), Some(DriverControl::Response(bytes)) if bytes.starts_with(b"220 "))
+    }
+}
+
 #[derive(Debug)]
 struct PrudentState {
     pub last_command_at: Instant,
+    pub transactions: usize,
+    pub errors: usize,
+}
+
+impl Default for PrudentState {
+    fn default() -> Self {
+        Self {
+            last_command_at: Instant::now(),
+            transactions: 0,
+            errors: 0,
+        }
+    }
+}
+
+/// The start of the DATA phase, shared by the interpretter and the IO
+#[derive(Debug, Clone, Default)]
+struct DataPhase(Arc<Mutex<Option<Instant>>>);
+
+impl DataPhase {
+    fn started(&self) -> Option<Instant> {
+        *self.0.lock().expect("data phase lock")
+    }
+    fn update(&self, in_data: bool) {
+        let mut started = self.0.lock().expect("data phase lock");
+        match (in_data, started.is_some()) {
+            (true, false) => *started = Some(Instant::now()),
+            (false, true) => *started = None,
+            _ => {}
+        }
+    }
 }
 
 struct PrudentIo {
     expired: Pin<Box<dyn Future<Output = ()> + Sync + Send>>,
     timeout: Option<Duration>,
+    session_expired: Pin<Box<dyn Future<Output = ()> + Sync + Send>>,
+    data_phase: DataPhase,
+    data_started: Option<Instant>,
+    data_read: usize,
+    data_expired: Pin<Box<dyn Future<Output = ()> + Sync + Send>>,
+    max_data_duration: Option<Duration>,
+    min_data_throughput: Option<(usize, Duration)>,
     io: Box<dyn MayBeTls>,
 }
 
 impl PrudentIo {
-    pub fn new<IO: MayBeTls + 'static>(timeout: Option<Duration>, io: IO) -> Self {
+    pub fn new<IO: MayBeTls + 'static>(config: &Prudence, data_phase: DataPhase, io: IO) -> Self {
         PrudentIo {
-            expired: Box::pin(Self::expire(timeout)),
-            timeout,
+            expired: Box::pin(Self::expire(config.read_timeout)),
+            timeout: config.read_timeout,
+            session_expired: Box::pin(Self::expire(config.max_session_duration)),
+            data_phase,
+            data_started: None,
+            data_read: 0,
+            data_expired: Box::pin(Self::expire(None)),
+            max_data_duration: config.max_data_duration,
+            min_data_throughput: config.min_data_throughput,
             io: Box::new(io),
         }
     }
@@ -122,6 +312,17 @@ impl PrudentIo {
             pending::<()>().await;
         }
     }
+    /// Is the client sending mail data too slowly?
+    fn is_too_slow(&self) -> bool {
+        match (self.data_started, self.min_data_throughput) {
+            (Some(started), Some((bytes, period))) if !period.is_zero() => {
+                let elapsed = Instant::now().saturating_duration_since(started);
+                let expected = bytes as u128 * elapsed.as_nanos() / period.as_nanos();
+                elapsed >= period && (self.data_read as u128) < expected
+            }
+            _ => false,
+        }
+    }
 }
 
 impl MayBeTls for PrudentIo {
@@ -140,6 +341,10 @@ impl MayBeTls for PrudentIo {
     fn is_encrypted(&self) -> bool {
         self.io.is_encrypted()
     }
+
+    fn tls_info(&self) -> Option<crate::io::tls::TlsInfo> {
+        self.io.tls_info()
+    }
 }
 
 impl io::Read for PrudentIo {
@@ -151,11 +356,36 @@ impl io::Read for PrudentIo {
         if let Poll::Ready(()) = self.expired.as_mut().poll(cx) {
             return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
         }
+        if let Poll::Ready(()) = self.session_expired.as_mut().poll(cx) {
+            warn!("Session duration exceeded");
+            return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+        }
+
+        let data_started = self.data_phase.started();
+        if data_started != self.data_started {
+            self.data_started = data_started;
+            self.data_read = 0;
+            self.data_expired = Box::pin(Self::expire(match data_started {
+                Some(_) => self.max_data_duration,
+                None => None,
+            }));
+        }
+        if let Poll::Ready(()) = self.data_expired.as_mut().poll(cx) {
+            warn!("DATA duration exceeded");
+            return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+        }
 
         let res = Pin::new(&mut self.io).poll_read(cx, buf);
 
-        if let Poll::Ready(Ok(_)) = res {
-            self.expired = Box::pin(Self::expire(self.timeout))
+        if let Poll::Ready(Ok(len)) = res {
+            self.expired = Box::pin(Self::expire(self.timeout));
+            if self.data_started.is_some() {
+                self.data_read += len;
+                if self.is_too_slow() {
+                    warn!("DATA throughput too low");
+                    return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+                }
+            }
         }
 
         res
@@ -179,11 +409,11 @@ impl io::Write for PrudentIo {
     }
 }
 
-/// Enforces the specified command timeout
+/// Enforces the specified command timeout and session limits
 #[derive(Debug)]
 struct PrudentInterpretter {
     inner: Box<dyn Interpret + Sync + Send>,
-    timeout: Option<Duration>,
+    config: Prudence,
 }
 
 impl Interpret for PrudentInterpretter {
@@ -198,27 +428,127 @@ impl Interpret for PrudentInterpretter {
 
 impl PrudentInterpretter {
     pub async fn interpret_inner(&self, state: &mut SmtpContext) -> InterpretResult {
-        let res = self.inner.interpret(state).await;
+        // every MAIL command counts, whether the previous transaction ended or not
+        let is_mail = Self::is_mail_command(&state.session);
+        if let Some(max) = self.config.max_transactions {
+            let transactions = state.get_or_insert(PrudentState::default).transactions;
+            if transactions >= max && is_mail {
+                warn!(
+                    "{} reached the limit of {} transactions",
+                    state.session.connection.peer_addr, max
+                );
+                state.session.say_shutdown_service_err();
+                return Ok(None);
+            }
+        }
 
-        if let Some(timeout) = self.timeout {
-            let mystate = state.get_or_insert(|| PrudentState {
-                last_command_at: Instant::now(),
-            });
+        let replied = state.session.output.len();
 
-            match res {
-                Ok(Some(consumed)) if consumed != 0 => {
-                    mystate.last_command_at = std::time::Instant::now();
+        let mut res = self.inner.interpret(state).await;
+
+        if let Some(data_phase) = state.get::<DataPhase>() {
+            data_phase.update(state.session.mode.is_some());
+        }
+
+        let bad_reply = state.session.output[replied..]
+            .iter()
+            .any(Self::is_bad_command_reply);
+        let mystate = state.get_or_insert(PrudentState::default);
+
+        let bad_command = match res {
+            Ok(Some(consumed)) if consumed != 0 => {
+                if is_mail {
+                    mystate.transactions += 1;
                 }
-                Err(ParseError::Incomplete) => {
+                mystate.last_command_at = Instant::now();
+                bad_reply
+            }
+            Err(ParseError::Incomplete) => {
+                if let Some(timeout) = self.config.read_timeout {
                     if Instant::now().saturating_duration_since(mystate.last_command_at) > timeout {
                         state.session.say_shutdown_timeout();
                         return Ok(None);
                     }
                 }
+                false
+            }
+            // the driver will say 500
+            Err(_) => true,
+            Ok(_) => false,
+        };
+
+        if bad_command {
+            mystate.errors += 1;
+            let errors = mystate.errors;
+            if let Some(delay) = self.config.error_delay {
+                async_std::task::sleep(delay * errors as u32).await;
+            }
+            match self.config.max_errors {
+                Some(max) if errors >= max => {
+                    warn!(
+                        "{} reached the limit of {} bad commands",
+                        state.session.connection.peer_addr, max
+                    );
+                    if res.is_err() {
+                        state.session.say_invalid_syntax();
+                        res = Ok(None);
+                    }
+                    state.session.say_shutdown_service_err();
+                }
                 _ => {}
             }
         }
 
         res
     }
+    fn is_mail_command(session: &SmtpSession) -> bool {
+        session.mode.is_none()
+            && session.input.len() >= 5
+            && session.input[..5].eq_ignore_ascii_case(b"MAIL ")
+    }
+    /// 500 - 504 replies indicate a bad command - syntax or sequence
+    fn is_bad_command_reply(control: &DriverControl) -> bool {
+        match control {
+            DriverControl::Response(bytes) => {
+                bytes.len() > 3 && bytes.starts_with(b"50") && (b'0'..=b'4').contains(&bytes[2])
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Limits the recipients of a transaction
+#[derive(Debug)]
+struct PrudentGuard {
+    max_recipients: usize,
+}
+
+impl MailGuard for PrudentGuard {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = if session.transaction.rcpts.len() >= self.max_recipients {
+            AddRecipientResult::Failed(
+                AddRecipientFailure::TooManyRecipients,
+                format!("Reached the limit of {} recipients", self.max_recipients),
+            )
+        } else {
+            AddRecipientResult::Inconclusive(rcpt)
+        };
+        Box::pin(ready(result))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
 }
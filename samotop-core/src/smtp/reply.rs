@@ -90,6 +90,8 @@ pub enum SmtpReply {
     ServiceNotAvailableError(String),
     /// 521 RFC 7504
     MailNotAcceptedByHostFailure,
+    /// 554 @domain no SMTP service here (connection-opening response)
+    NoServiceFailure(String),
 
     /// 250 Ok
     OkInfo,
@@ -114,8 +116,12 @@ pub enum SmtpReply {
     ProcesingError,
     /// 452 Requested action not taken
     StorageError,
+    /// 452 Too many recipients (RFC 5321 section 4.5.3.1.10)
+    TooManyRecipientsError,
     /// 455 right now the parameters given cannot be accomodated
     ParametersNotAccommodatedError,
+    /// 530 Must issue a STARTTLS command first (RFC 3207)
+    EncryptionRequiredFailure,
     /// 550 Requested action not taken: mailbox unavailable (e.g.,
     ///     mailbox not found, no access, or command rejected for policy reasons)
     MailboxNotAvailableFailure,
@@ -131,6 +137,9 @@ pub enum SmtpReply {
     UnknownMailParametersFailure,
     /// 556 RFC 7504
     MailNotAcceptedByDomainFailure,
+
+    /// Any reply code with a custom text, such as a policy rejection
+    Custom(u16, String),
 }
 
 impl SmtpReply {
@@ -157,6 +166,8 @@ impl SmtpReply {
             ServiceNotAvailableError(_) => 421,
             // RFC 7504
             MailNotAcceptedByHostFailure => 521,
+            // connection-opening response
+            NoServiceFailure(_) => 554,
 
             // first line is either Ok or specific message
             OkInfo => 250,
@@ -175,8 +186,10 @@ impl SmtpReply {
             ProcesingError => 451,
             // Requested action not taken
             StorageError => 452,
+            TooManyRecipientsError => 452,
             // right now the parameters given cannot be accomodated
             ParametersNotAccommodatedError => 455,
+            EncryptionRequiredFailure => 530,
             // Requested action not taken: mailbox unavailable (e.g.,
             // mailbox not found, no access, or command rejected for policy reasons)
             MailboxNotAvailableFailure => 550,
@@ -192,6 +205,7 @@ impl SmtpReply {
             UnknownMailParametersFailure => 555,
             // RFC 7504
             MailNotAcceptedByDomainFailure => 556,
+            Custom(code, _) => code,
         }
     }
 
@@ -216,6 +230,7 @@ impl SmtpReply {
                 domain
             ),
             MailNotAcceptedByHostFailure => "Host does not accept mail".to_owned(),
+            NoServiceFailure(ref domain) => format!("{} no SMTP service here", domain),
 
             OkInfo => "Ok".to_owned(),
             OkMessageInfo(ref text) => text.to_string(),
@@ -238,7 +253,9 @@ impl SmtpReply {
             ProcesingError => "Requested action aborted: error in processing.".to_owned(),
 
             StorageError => "Requested action not taken: insufficient system storage".to_owned(),
+            TooManyRecipientsError => "Too many recipients".to_owned(),
             ParametersNotAccommodatedError => "Server unable to accommodate parameters".to_owned(),
+            EncryptionRequiredFailure => "Must issue STARTTLS first".to_owned(),
             MailboxNotAvailableFailure => {
                 "Requested action not taken: mailbox unavailable".to_owned()
             }
@@ -256,6 +273,7 @@ impl SmtpReply {
                 "MAIL FROM/RCPT TO parameters not recognized or not implemented".to_owned()
             }
             MailNotAcceptedByDomainFailure => "Domain does not accept mail".to_owned(),
+            Custom(_, ref text) => text.to_string(),
         }
     }
     pub fn items(&self) -> Vec<String> {
@@ -0,0 +1,248 @@
+use crate::common::*;
+use crate::mail::{
+    AcceptsGuard, AcceptsInterpretter, AddRecipientResult, MailGuard, MailSetup, Recipient,
+    StartMailFailure, StartMailResult,
+};
+use crate::smtp::{extension, ExtensionSet, Interpret, InterpretResult, SmtpContext, SmtpSession};
+use std::net::{IpAddr, SocketAddr};
+
+/// Refuse mail over plaintext sessions.
+///
+/// Use together with `EsmtpStartTls` so that clients can upgrade the session.
+/// Sessions are considered encrypted once the `MayBeTls` stream reports TLS details.
+#[derive(Debug, Default, Clone)]
+pub struct RequireTls {
+    mode: RequireTlsMode,
+    exempt: Vec<IpAddr>,
+}
+
+/// How strictly should `RequireTls` treat plaintext sessions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RequireTlsMode {
+    /// Reject MAIL with "530 Must issue STARTTLS first" until the session is encrypted
+    #[default]
+    RejectMail,
+    /// As `RejectMail` and also advertise only STARTTLS in EHLO until the session is encrypted
+    HideExtensions,
+    /// As `RejectMail` and also close the session after rejecting MAIL
+    Disconnect,
+}
+
+impl RequireTls {
+    pub fn new(mode: RequireTlsMode) -> Self {
+        Self {
+            mode,
+            exempt: vec![],
+        }
+    }
+    /// Do not require TLS from the given peer, such as a local submission client
+    pub fn exempt(mut self, peer: IpAddr) -> Self {
+        self.exempt.push(peer);
+        self
+    }
+    /// Is the session plaintext and subject to this policy?
+    pub fn applies(&self, session: &SmtpSession) -> bool {
+        if session.tls.is_some() {
+            return false;
+        }
+        match session.connection.peer_addr.parse::<SocketAddr>() {
+            Ok(peer) => !self.exempt.contains(&peer.ip()),
+            Err(_) => true,
+        }
+    }
+}
+
+impl<T: AcceptsGuard + AcceptsInterpretter> MailSetup<T> for RequireTls {
+    fn setup(self, config: &mut T) {
+        config.wrap_interpretter(|inner| RequireTlsInterpretter {
+            inner,
+            config: self.clone(),
+        });
+        config.add_first_guard(self);
+    }
+}
+
+impl MailGuard for RequireTls {
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let result = if self.applies(session) {
+            StartMailResult::Failed(
+                StartMailFailure::EncryptionRequired,
+                format!(
+                    "Plaintext session from {} refused",
+                    session.connection.peer_addr
+                ),
+            )
+        } else {
+            StartMailResult::Accepted
+        };
+        Box::pin(ready(result))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+/// Hides extensions and closes sessions according to the `RequireTlsMode`
+#[derive(Debug)]
+struct RequireTlsInterpretter {
+    inner: Box<dyn Interpret + Sync + Send>,
+    config: RequireTls,
+}
+
+/// Extensions hidden until the session is encrypted
+#[derive(Debug)]
+struct RequireTlsState {
+    hidden: Option<ExtensionSet>,
+}
+
+impl Interpret for RequireTlsInterpretter {
+    fn interpret<'a, 's, 'f>(&'a self, state: &'s mut SmtpContext) -> S1Fut<'f, InterpretResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let applies = self.config.applies(&state.session);
+
+            if self.config.mode == RequireTlsMode::HideExtensions {
+                let hidden = state
+                    .get_mut::<RequireTlsState>()
+                    .and_then(|mystate| mystate.hidden.take());
+                let hidden = match (applies, hidden) {
+                    (true, None) => {
+                        let mut visible = ExtensionSet::new();
+                        if let Some(starttls) = state
+                            .session
+                            .extensions
+                            .get_string(extension::STARTTLS.code)
+                        {
+                            visible.enable_string(extension::STARTTLS.code, starttls);
+                        }
+                        let mut hidden = std::mem::replace(&mut state.session.extensions, visible);
+                        hidden.disable(&extension::STARTTLS);
+                        Some(hidden)
+                    }
+                    (false, Some(hidden)) => {
+                        // keep what has been enabled in the meantime
+                        let visible = std::mem::replace(&mut state.session.extensions, hidden);
+                        state.session.extensions.merge(visible);
+                        None
+                    }
+                    (_, hidden) => hidden,
+                };
+                state.set(RequireTlsState { hidden });
+            }
+
+            let res = self.inner.interpret(state).await;
+
+            if applies
+                && self.config.mode == RequireTlsMode::Disconnect
+                && state.session.transaction.mail.is_some()
+            {
+                // MAIL has been refused in a plaintext session
+                state.session.shutdown();
+            }
+
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tls::TlsInfo;
+    use crate::smtp::{command::SmtpMail, DriverControl, SmtpPath};
+
+    fn mail_failure(sut: &RequireTls, session: &mut SmtpSession) -> Option<StartMailFailure> {
+        match async_std::task::block_on(sut.start_mail(session)) {
+            StartMailResult::Accepted => None,
+            StartMailResult::Failed(failure, _) => Some(failure),
+        }
+    }
+
+    #[test]
+    fn plaintext_mail_is_refused() {
+        let sut = RequireTls::default();
+        let mut session = SmtpSession::default();
+        assert_eq!(
+            mail_failure(&sut, &mut session),
+            Some(StartMailFailure::EncryptionRequired)
+        );
+        session.say_mail_failed(StartMailFailure::EncryptionRequired, String::default());
+        match session.pop_control() {
+            Some(DriverControl::Response(bytes)) if bytes.starts_with(b"530 ") => {}
+            otherwise => panic!("Expected 530, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn encrypted_mail_is_accepted() {
+        let sut = RequireTls::default();
+        let mut session = SmtpSession {
+            tls: Some(TlsInfo::default()),
+            ..Default::default()
+        };
+        assert_eq!(mail_failure(&sut, &mut session), None);
+    }
+
+    #[test]
+    fn exempt_peer_is_accepted() {
+        let sut = RequireTls::default().exempt("127.0.0.1".parse().expect("ip"));
+        let mut session = SmtpSession::default();
+        session.connection.peer_addr = "127.0.0.1:12345".to_owned();
+        assert_eq!(mail_failure(&sut, &mut session), None);
+    }
+
+    #[test]
+    fn extensions_are_hidden_until_encrypted() {
+        async_std::task::block_on(async move {
+            let sut = RequireTlsInterpretter {
+                inner: Box::new(Dummy),
+                config: RequireTls::new(RequireTlsMode::HideExtensions),
+            };
+            let mut set = SmtpContext::default();
+            set.session.extensions.enable(&extension::STARTTLS);
+            set.session.extensions.enable(&extension::PIPELINING);
+
+            let _ = sut.interpret(&mut set).await;
+            assert!(set.session.extensions.is_enabled(&extension::STARTTLS));
+            assert!(!set.session.extensions.is_enabled(&extension::PIPELINING));
+
+            set.session.extensions.disable(&extension::STARTTLS);
+            set.session.tls = Some(TlsInfo::default());
+
+            let _ = sut.interpret(&mut set).await;
+            assert!(!set.session.extensions.is_enabled(&extension::STARTTLS));
+            assert!(set.session.extensions.is_enabled(&extension::PIPELINING));
+        })
+    }
+
+    #[test]
+    fn plaintext_session_is_closed_after_mail() {
+        async_std::task::block_on(async move {
+            let sut = RequireTlsInterpretter {
+                inner: Box::new(Dummy),
+                config: RequireTls::new(RequireTlsMode::Disconnect),
+            };
+            let mut set = SmtpContext::default();
+            set.session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
+
+            let _ = sut.interpret(&mut set).await;
+            assert_eq!(set.session.pop_control(), Some(DriverControl::Shutdown));
+        })
+    }
+}
@@ -17,7 +17,7 @@ impl Action<SmtpHelo> for Lmtp {
     {
         Box::pin(async move {
             match cmd.verb.to_ascii_uppercase().as_str() {
-                "LHLO" => apply_helo(cmd, true, state),
+                "LHLO" => apply_helo(cmd, true, state).await,
                 _ => Esmtp.apply(SmtpUnknownCommand::default(), state).await,
             }
         })
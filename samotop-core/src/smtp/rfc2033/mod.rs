@@ -71,7 +71,6 @@ where
         'i: 'f,
         's: 'f,
     {
-        state.session.say_service_ready();
-        Box::pin(ready(()))
+        Box::pin(apply_session_open(state))
     }
 }
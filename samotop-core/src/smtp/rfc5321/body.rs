@@ -1,6 +1,9 @@
 use super::Esmtp;
 use crate::{
     common::*,
+    mail::{
+        CheckBodyFailure, CheckBodyResult, DispatchError, MailDataSink, MailDispatch, MailGuard,
+    },
     smtp::{command::MailBody, Action, SmtpContext, SmtpSession},
 };
 
@@ -18,7 +21,6 @@ pub async fn apply_mail_body<B>(lmtp: bool, cmd: MailBody<B>, state: &mut SmtpCo
 where
     B: AsRef<[u8]> + Sync + Send + fmt::Debug + 'static,
 {
-    let sink = state.session.transaction.sink.take();
     let mailid = state.session.transaction.id.clone();
 
     match cmd {
@@ -26,33 +28,37 @@ where
             data,
             ends_with_new_line,
         } => {
-            let mut sink = if let Some(sink) = sink {
+            let mode = match ends_with_new_line {
+                true => SmtpSession::DATA_MODE,
+                false => SmtpSession::DATA_PARTIAL_MODE,
+            };
+
+            if let Some(ref mut spool) = state.session.transaction.spool {
+                // the mail is held back until the content is checked
+                spool.append(data.as_ref());
+                let inspect = !spool.is_truncated();
+                state.session.mode = Some(mode);
+                // guards inspecting the held mail see the data as it arrives
+                if let (true, Some(sink)) = (inspect, state.session.transaction.sink.as_mut()) {
+                    if let Err(e) = write_all(sink, data.as_ref()).await {
+                        warn!("Failed to inspect mail data for {} - {}", mailid, e);
+                        state.session.transaction.sink = None;
+                    }
+                }
+                return;
+            }
+
+            let mut sink = if let Some(sink) = state.session.transaction.sink.take() {
                 sink
             } else {
                 // CheckMe: silence. MailBody::End should respond with error.
                 return;
             };
 
-            let mut copy_from = data.as_ref();
-            let mut copy_to = sink.as_mut();
-            let copy = Box::pin(poll_fn(move |cx| loop {
-                match copy_to.as_mut().poll_write(cx, copy_from)? {
-                    Poll::Ready(written) => copy_from = &copy_from[written..],
-                    Poll::Pending => return Poll::Pending,
-                }
-                if copy_from.is_empty() {
-                    break Poll::Ready(Ok::<(), io::Error>(()));
-                }
-            }));
-
-            match copy.await {
+            match write_all(&mut sink, data.as_ref()).await {
                 Ok(()) => {
-                    //let WriteAll { to, .. } = write_all;
                     state.session.transaction.sink = Some(sink);
-                    state.session.mode = Some(match ends_with_new_line {
-                        true => SmtpSession::DATA_MODE,
-                        false => SmtpSession::DATA_PARTIAL_MODE,
-                    })
+                    state.session.mode = Some(mode)
                 }
                 Err(e) => {
                     warn!("Failed to write mail data for {} - {}", mailid, e);
@@ -62,13 +68,20 @@ where
             };
         }
         MailBody::End => {
-            let mut sink = if let Some(sink) = sink {
+            if state.session.transaction.spool.is_some() && !dispatch_held(lmtp, state).await {
+                // the mail was refused or dropped and the reply given
+                state.session.reset();
+                return;
+            }
+
+            let mut sink = if let Some(sink) = state.session.transaction.sink.take() {
                 sink
             } else {
                 state.session.say_mail_queue_failed_temporarily();
                 state.session.reset();
                 return;
             };
+
             if match poll_fn(move |cx| sink.as_mut().poll_close(cx)).await {
                 Ok(()) => true,
                 Err(e) if e.kind() == std::io::ErrorKind::NotConnected => true,
@@ -77,20 +90,7 @@ where
                     false
                 }
             } {
-                if lmtp {
-                    for msg in state
-                        .session
-                        .transaction
-                        .rcpts
-                        .iter()
-                        .map(|rcpt| format!("{} for {}", mailid, rcpt.address))
-                        .collect::<Vec<String>>()
-                    {
-                        state.session.say_mail_queued(msg.as_str());
-                    }
-                } else {
-                    state.session.say_mail_queued(mailid.as_str());
-                }
+                say_mail_queued(lmtp, mailid.as_str(), state);
             } else {
                 state.session.say_mail_queue_failed_temporarily();
             }
@@ -98,3 +98,291 @@ where
         }
     }
 }
+
+/// Check the held mail and if it is accepted, open the dispatch and write the mail into the sink.
+///
+/// A sink set up by the guards in `start_mail` only inspects the held mail,
+/// it is closed before the check and replaced by the dispatch.
+///
+/// Returns false if the mail is not going to be dispatched, the reply has been given then.
+/// Nothing reaches the dispatch unless the mail is accepted.
+async fn dispatch_held(lmtp: bool, state: &mut SmtpContext) -> bool {
+    let mailid = state.session.transaction.id.clone();
+
+    // the guards inspecting the held mail have seen it all
+    if let Some(sink) = state.session.transaction.sink.as_mut() {
+        if let Err(e) = poll_fn(|cx| sink.as_mut().poll_close(cx)).await {
+            warn!("Failed to close the mail inspection of {}: {}", mailid, e);
+        }
+    }
+
+    let verdict = match state.session.transaction.spool {
+        Some(ref spool) if spool.is_truncated() => CheckBodyResult::Failed(
+            CheckBodyFailure::StorageExhaustedPermanently,
+            format!("The mail size {} exceeds the spool limit", spool.size()),
+        ),
+        _ => state.service().check_body(&mut state.session).await,
+    };
+
+    match verdict {
+        CheckBodyResult::Accepted => {}
+        CheckBodyResult::Failed(CheckBodyFailure::TerminateSession, description) => {
+            state
+                .session
+                .say_body_failed(CheckBodyFailure::TerminateSession, description);
+            return false;
+        }
+        CheckBodyResult::Failed(failure, description) => {
+            for _ in 0..replies(lmtp, state) {
+                state
+                    .session
+                    .say_body_failed(failure.clone(), description.clone());
+            }
+            return false;
+        }
+        CheckBodyResult::Quarantine(reason) => {
+            warn!("Mail {} quarantined: {}", mailid, reason);
+            say_mail_queued(lmtp, mailid.as_str(), state);
+            return false;
+        }
+        CheckBodyResult::Discard(reason) => {
+            warn!("Mail {} discarded: {}", mailid, reason);
+            say_mail_queued(lmtp, mailid.as_str(), state);
+            return false;
+        }
+    }
+
+    // the dispatch opens a sink of its own
+    state.session.transaction.sink = None;
+    let refused = match state.service().open_mail_body(&mut state.session).await {
+        Ok(()) if state.session.transaction.sink.is_some() => None,
+        Ok(()) => {
+            warn!(
+                "Send_mail returned OK message without sink for transaction {}",
+                mailid
+            );
+            Some(DispatchError::Temporary)
+        }
+        Err(e) => Some(e),
+    };
+    if let Some(e) = refused {
+        for _ in 0..replies(lmtp, state) {
+            match e {
+                DispatchError::Permanent => state.session.say_mail_queue_refused(),
+                DispatchError::Temporary => state.session.say_mail_queue_failed_temporarily(),
+            };
+        }
+        return false;
+    }
+
+    let spool = state.session.transaction.spool.take().unwrap_or_default();
+    let sink = state
+        .session
+        .transaction
+        .sink
+        .as_mut()
+        .expect("checked above");
+    if let Err(e) = write_all(sink, spool.data()).await {
+        warn!("Failed to write mail data for {} - {}", mailid, e);
+        for _ in 0..replies(lmtp, state) {
+            state.session.say_mail_queue_failed_temporarily();
+        }
+        return false;
+    }
+    true
+}
+
+async fn write_all(sink: &mut Pin<Box<dyn MailDataSink>>, data: &[u8]) -> std::io::Result<()> {
+    let mut copy_from = data;
+    let mut copy_to = sink.as_mut();
+    poll_fn(move |cx| loop {
+        if copy_from.is_empty() {
+            break Poll::Ready(Ok(()));
+        }
+        match copy_to.as_mut().poll_write(cx, copy_from)? {
+            Poll::Ready(0) => break Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())),
+            Poll::Ready(written) => copy_from = &copy_from[written..],
+            Poll::Pending => return Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// LMTP replies for each recipient, SMTP just once
+fn replies(lmtp: bool, state: &SmtpContext) -> usize {
+    match lmtp {
+        true => state.session.transaction.rcpts.len(),
+        false => 1,
+    }
+}
+
+fn say_mail_queued(lmtp: bool, mailid: &str, state: &mut SmtpContext) {
+    if lmtp {
+        for msg in state
+            .session
+            .transaction
+            .rcpts
+            .iter()
+            .map(|rcpt| format!("{} for {}", mailid, rcpt.address))
+            .collect::<Vec<String>>()
+        {
+            state.session.say_mail_queued(msg.as_str());
+        }
+    } else {
+        state.session.say_mail_queued(mailid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::ConnectionInfo,
+        mail::*,
+        smtp::{DriverControl, SmtpSession},
+    };
+
+    fn context(spool: MailSpool) -> (SmtpContext, Capture) {
+        let capture = Capture::default();
+        let service = Builder + capture.clone() + TestGuard::default().check_body(spam_filter);
+        let mut set = SmtpContext::new(service.build_with_driver(Dummy), ConnectionInfo::default());
+        set.session.transaction.id = "someid".to_owned();
+        set.session.transaction.spool = Some(spool);
+        (set, capture)
+    }
+
+    async fn send(set: &mut SmtpContext, data: &'static str) {
+        let chunk = MailBody::Chunk {
+            data: data.as_bytes().to_vec(),
+            ends_with_new_line: true,
+        };
+        apply_mail_body(false, chunk, set).await;
+        apply_mail_body(false, MailBody::<Vec<u8>>::End, set).await;
+    }
+
+    #[test]
+    fn clean_mail_is_queued() {
+        async_std::task::block_on(async move {
+            let (mut set, capture) = context(MailSpool::default());
+            send(&mut set, "Subject: hi\r\n\r\nham\r\n").await;
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"250 ") => {}
+                otherwise => panic!("Expected 250, got {:?}", otherwise),
+            }
+            assert_eq!(capture.data(), b"Subject: hi\r\n\r\nham\r\n");
+        })
+    }
+
+    #[test]
+    fn spam_is_rejected() {
+        async_std::task::block_on(async move {
+            let (mut set, capture) = context(MailSpool::default());
+            send(&mut set, "Subject: buy now\r\n\r\nspam\r\n").await;
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"550 ") => {}
+                otherwise => panic!("Expected 550, got {:?}", otherwise),
+            }
+            assert!(set.session.transaction.is_empty());
+            assert!(!capture.is_opened(), "spam must not reach the dispatch");
+        })
+    }
+
+    #[test]
+    fn oversized_mail_is_refused() {
+        async_std::task::block_on(async move {
+            let (mut set, capture) = context(MailSpool::with_limit(10));
+            send(&mut set, "Subject: hi\r\n\r\nham\r\n").await;
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"552 ") => {}
+                otherwise => panic!("Expected 552, got {:?}", otherwise),
+            }
+            assert!(
+                !capture.is_opened(),
+                "truncated mail must not reach the dispatch"
+            );
+        })
+    }
+
+    #[test]
+    fn held_mail_is_inspected_as_it_arrives() {
+        async_std::task::block_on(async move {
+            let (mut set, capture) = context(MailSpool::default());
+            let inspect = Capture::default();
+            *inspect.0.lock().expect("lock") = Some(vec![]);
+            set.session.transaction.sink = Some(Box::pin(inspect.clone()));
+            send(&mut set, "Subject: hi\r\n\r\nham\r\n").await;
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"250 ") => {}
+                otherwise => panic!("Expected 250, got {:?}", otherwise),
+            }
+            assert_eq!(inspect.data(), b"Subject: hi\r\n\r\nham\r\n");
+            assert_eq!(capture.data(), b"Subject: hi\r\n\r\nham\r\n");
+        })
+    }
+
+    /// Dispatch collecting the mail data
+    #[derive(Debug, Clone, Default)]
+    struct Capture(Arc<std::sync::Mutex<Option<Vec<u8>>>>);
+
+    impl Capture {
+        fn is_opened(&self) -> bool {
+            self.0.lock().expect("lock").is_some()
+        }
+        fn data(&self) -> Vec<u8> {
+            self.0.lock().expect("lock").clone().unwrap_or_default()
+        }
+    }
+
+    impl<T: AcceptsDispatch> MailSetup<T> for Capture {
+        fn setup(self, config: &mut T) {
+            config.add_last_dispatch(self)
+        }
+    }
+
+    impl MailDispatch for Capture {
+        fn open_mail_body<'a, 's, 'f>(
+            &'a self,
+            session: &'s mut SmtpSession,
+        ) -> S1Fut<'f, DispatchResult>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            *self.0.lock().expect("lock") = Some(vec![]);
+            session.transaction.sink = Some(Box::pin(self.clone()));
+            Box::pin(ready(Ok(())))
+        }
+    }
+
+    impl io::Write for Capture {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if let Some(data) = self.0.lock().expect("lock").as_mut() {
+                data.extend_from_slice(buf)
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn spam_filter(session: &SmtpSession) -> CheckBodyResult {
+        let spam = session
+            .transaction
+            .spool
+            .as_ref()
+            .map(|spool| spool.header("subject") == vec!["buy now".to_owned()])
+            .unwrap_or_default();
+        match spam {
+            true => CheckBodyResult::Failed(CheckBodyFailure::Rejected, "spam".to_owned()),
+            false => CheckBodyResult::Accepted,
+        }
+    }
+}
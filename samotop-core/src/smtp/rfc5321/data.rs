@@ -20,6 +20,12 @@ impl Action<SmtpData> for Esmtp {
                 return;
             }
 
+            if state.session.transaction.spool.is_some() {
+                // the mail is held back and dispatched after the content check
+                state.session.say_start_data_challenge();
+                return;
+            }
+
             match state.service().open_mail_body(&mut state.session).await {
                 Ok(()) if state.session.transaction.sink.is_none() => {
                     warn!(
@@ -78,6 +84,26 @@ mod tests {
         })
     }
 
+    #[test]
+    fn held_mail_is_not_dispatched_yet() {
+        async_std::task::block_on(async move {
+            let mut set = SmtpContext::default();
+            set.session.peer_name = Some("xx.io".to_owned());
+            set.session.transaction.id = "someid".to_owned();
+            set.session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
+            set.session.transaction.rcpts.push(Recipient::null());
+            set.session.transaction.spool = Some(Default::default());
+
+            Esmtp.apply(SmtpData, &mut set).await;
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"354 ") => {}
+                otherwise => panic!("Expected mail data input challenge, got {:?}", otherwise),
+            }
+
+            assert!(set.session.transaction.sink.is_none());
+        })
+    }
+
     #[test]
     fn command_sequence_is_assured_missing_helo() {
         async_std::task::block_on(async move {
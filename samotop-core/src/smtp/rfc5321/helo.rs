@@ -1,5 +1,6 @@
 use crate::{
     common::S1Fut,
+    mail::{CheckHeloResult, MailGuard},
     smtp::{
         command::{SmtpHelo, SmtpUnknownCommand},
         Action, Esmtp, SmtpContext,
@@ -14,8 +15,8 @@ impl Action<SmtpHelo> for Esmtp {
     {
         Box::pin(async move {
             match cmd.verb.to_ascii_uppercase().as_str() {
-                "EHLO" => apply_helo(cmd, true, state),
-                "HELO" => apply_helo(cmd, false, state),
+                "EHLO" => apply_helo(cmd, true, state).await,
+                "HELO" => apply_helo(cmd, false, state).await,
                 verb => {
                     Esmtp
                         .apply(
@@ -31,21 +32,30 @@ impl Action<SmtpHelo> for Esmtp {
 
 /// Applies given helo to the state
 /// It assumes it is the right HELO/EHLO/LHLO variant
-pub fn apply_helo(helo: SmtpHelo, is_extended: bool, state: &mut SmtpContext) {
+pub async fn apply_helo(helo: SmtpHelo, is_extended: bool, state: &mut SmtpContext) {
     state.session.reset_helo(helo.host.to_string());
 
-    match is_extended {
-        false => state.session.say_helo(),
-        true => state.session.say_ehlo(),
-    };
+    match state.service().check_helo(&mut state.session, &helo).await {
+        CheckHeloResult::Failed(failure, description) => {
+            // the client has not been greeted
+            state.session.peer_name = None;
+            state.session.say_helo_failed(failure, description);
+        }
+        CheckHeloResult::Accepted => match is_extended {
+            false => state.session.say_helo(),
+            true => state.session.say_ehlo(),
+        },
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        mail::Recipient,
-        smtp::{command::SmtpMail, SmtpHost, SmtpPath},
+        common::*,
+        io::ConnectionInfo,
+        mail::*,
+        smtp::{command::SmtpMail, DriverControl, SmtpHost, SmtpPath},
     };
 
     #[test]
@@ -91,6 +101,34 @@ mod tests {
         })
     }
 
+    #[test]
+    fn refused_helo_is_not_set() {
+        async_std::task::block_on(async move {
+            let service = Builder
+                + TestGuard::default().check_helo(CheckHeloResult::Failed(
+                    CheckHeloFailure::Rejected,
+                    "wex.xor.ro is not welcome".to_owned(),
+                ));
+            let mut set =
+                SmtpContext::new(service.build_with_driver(Dummy), ConnectionInfo::default());
+
+            Esmtp
+                .apply(
+                    SmtpHelo {
+                        verb: "EHLO".to_string(),
+                        host: SmtpHost::Domain("wex.xor.ro".to_owned()),
+                    },
+                    &mut set,
+                )
+                .await;
+            assert_eq!(set.session.peer_name, None);
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"550 ") => {}
+                otherwise => panic!("Expected 550, got {:?}", otherwise),
+            }
+        })
+    }
+
     #[test]
     fn is_sync_and_send() {
         let mut set = SmtpContext::default();
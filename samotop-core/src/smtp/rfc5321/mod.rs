@@ -13,7 +13,9 @@ pub(crate) use self::body::apply_mail_body;
 pub(crate) use self::helo::apply_helo;
 use crate::common::*;
 use crate::io::tls::MayBeTls;
-use crate::mail::{AcceptsInterpretter, AcceptsSessionService, MailSetup};
+use crate::mail::{
+    AcceptsInterpretter, AcceptsSessionService, MailGuard, MailSetup, OpenSessionResult,
+};
 use crate::smtp::command::*;
 use crate::smtp::*;
 
@@ -78,8 +80,17 @@ where
         'i: 'f,
         's: 'f,
     {
-        state.session.say_service_ready();
-        Box::pin(ready(()))
+        Box::pin(apply_session_open(state))
+    }
+}
+
+/// Let the guards check the new connection and send the banner or refuse the session
+pub(crate) async fn apply_session_open(state: &mut SmtpContext) {
+    match state.service().open_session(&mut state.session).await {
+        OpenSessionResult::Failed(failure, description) => {
+            state.session.say_session_refused(failure, description)
+        }
+        OpenSessionResult::Accepted => state.session.say_service_ready(),
     }
 }
 
@@ -106,3 +117,29 @@ impl Action<SmtpCommand> for Esmtp {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::ConnectionInfo, mail::*};
+
+    #[test]
+    fn refused_session_gets_no_banner() {
+        async_std::task::block_on(async move {
+            let service = Builder
+                + TestGuard::default().open_session(OpenSessionResult::Failed(
+                    OpenSessionFailure::NoService,
+                    "go away".to_owned(),
+                ));
+            let mut set =
+                SmtpContext::new(service.build_with_driver(Dummy), ConnectionInfo::default());
+
+            apply_session_open(&mut set).await;
+            match set.session.pop_control() {
+                Some(DriverControl::Response(bytes)) if bytes.starts_with(b"554 ") => {}
+                otherwise => panic!("Expected 554, got {:?}", otherwise),
+            }
+            assert_eq!(set.session.pop_control(), Some(DriverControl::Shutdown));
+        })
+    }
+}
@@ -23,8 +23,11 @@ impl Action<SmtpRcpt> for Esmtp {
                 .add_recipient(&mut state.session, rcpt)
                 .await
             {
-                AddRecipientResult::Inconclusive(rcpt) => {
+                AddRecipientResult::Inconclusive(mut rcpt) => {
+                    // the expansion has been checked by the guards already
+                    let expansion = std::mem::take(&mut rcpt.expansion);
                     state.session.transaction.rcpts.push(rcpt);
+                    state.session.transaction.rcpts.extend(expansion);
                     state.session.say_ok();
                 }
                 AddRecipientResult::Failed(failure, description) => {
@@ -44,7 +47,9 @@ impl Action<SmtpRcpt> for Esmtp {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::smtp::{command::SmtpMail, SmtpPath};
+    use crate::common::Dummy;
+    use crate::mail::{AliasFormat, Aliases, Builder, RelayPolicy};
+    use crate::smtp::{command::SmtpMail, SmtpHost, SmtpPath};
 
     #[test]
     fn recipient_is_added() {
@@ -64,4 +69,51 @@ mod tests {
             assert_eq!(set.session.transaction.rcpts.len(), 2);
         })
     }
+
+    #[test]
+    fn expanded_recipient_is_checked_by_the_following_guards() {
+        async_std::task::block_on(async move {
+            let aliases = || {
+                Aliases::default()
+                    .with_map(
+                        "team: alice, bob@example.net\nfwd: carol@example.net",
+                        AliasFormat::Aliases,
+                    )
+                    .expect("aliases")
+            };
+            let relay = || RelayPolicy::default().with_local_domain("example.org");
+            let path = |name: &str| SmtpPath::Mailbox {
+                name: name.to_owned(),
+                host: SmtpHost::Domain("example.org".to_owned()),
+                relays: vec![],
+            };
+            let rcpts = |set: &SmtpContext| {
+                set.session
+                    .transaction
+                    .rcpts
+                    .iter()
+                    .map(|r| r.address.address())
+                    .collect::<Vec<_>>()
+            };
+
+            // the relay policy checks the alias, all its targets may be forwarded
+            let service = Builder + relay() + aliases();
+            let mut set = SmtpContext::new(service.build_with_driver(Dummy), Default::default());
+            set.session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
+            Esmtp.apply(SmtpRcpt(path("fwd"), vec![]), &mut set).await;
+            Esmtp.apply(SmtpRcpt(path("team"), vec![]), &mut set).await;
+            assert_eq!(
+                rcpts(&set),
+                vec!["carol@example.net", "alice@example.org", "bob@example.net"]
+            );
+
+            // the relay policy checks every target, none of them is local
+            let service = Builder + aliases() + relay();
+            let mut set = SmtpContext::new(service.build_with_driver(Dummy), Default::default());
+            set.session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
+            Esmtp.apply(SmtpRcpt(path("fwd"), vec![]), &mut set).await;
+            Esmtp.apply(SmtpRcpt(path("team"), vec![]), &mut set).await;
+            assert!(rcpts(&set).is_empty());
+        })
+    }
 }
@@ -0,0 +1,144 @@
+use crate::common::*;
+use crate::mail::{
+    AcceptsGuard, AcceptsInterpretter, AddRecipientResult, MailGuard, MailSetup, Recipient,
+    StartMailFailure, StartMailResult,
+};
+use crate::smtp::{extension, Interpret, InterpretResult, SmtpContext, SmtpSession};
+
+/// An implementation of ESMTP REQUIRETLS - RFC 8689 - SMTP Require TLS Option
+///
+/// REQUIRETLS is only advertised on encrypted sessions.
+/// The MAIL parameter is accepted only if advertised
+/// and then it is stored in `Transaction::require_tls`
+/// so that the dispatch can honor it on relay.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EsmtpRequireTls;
+
+pub type Rfc8689 = EsmtpRequireTls;
+
+impl<T: AcceptsGuard + AcceptsInterpretter> MailSetup<T> for EsmtpRequireTls {
+    fn setup(self, config: &mut T) {
+        config.wrap_interpretter(|inner| RequireTlsAdvertiser { inner });
+        config.add_first_guard(self);
+    }
+}
+
+impl MailGuard for EsmtpRequireTls {
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let requested = session
+            .transaction
+            .mail
+            .as_ref()
+            .map(|mail| {
+                mail.parameters()
+                    .iter()
+                    .any(|p| p.eq_ignore_ascii_case(extension::REQUIRETLS.code))
+            })
+            .unwrap_or_default();
+
+        let result = if !requested {
+            StartMailResult::Accepted
+        } else if session.extensions.is_enabled(&extension::REQUIRETLS) {
+            session.transaction.require_tls = true;
+            StartMailResult::Accepted
+        } else {
+            StartMailResult::Failed(
+                StartMailFailure::InvalidParameter,
+                "REQUIRETLS is not available on a plaintext session".to_owned(),
+            )
+        };
+        Box::pin(ready(result))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+/// Advertises REQUIRETLS once the session is encrypted
+#[derive(Debug)]
+struct RequireTlsAdvertiser {
+    inner: Box<dyn Interpret + Sync + Send>,
+}
+
+impl Interpret for RequireTlsAdvertiser {
+    fn interpret<'a, 's, 'f>(&'a self, state: &'s mut SmtpContext) -> S1Fut<'f, InterpretResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if state.session.tls.is_some() {
+            state.session.extensions.enable(&extension::REQUIRETLS);
+        } else {
+            state.session.extensions.disable(&extension::REQUIRETLS);
+        }
+        self.inner.interpret(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tls::TlsInfo;
+    use crate::smtp::{command::SmtpMail, SmtpPath};
+
+    #[test]
+    fn advertised_only_when_encrypted() {
+        async_std::task::block_on(async move {
+            let sut = RequireTlsAdvertiser {
+                inner: Box::new(Dummy),
+            };
+            let mut set = SmtpContext::default();
+            let _ = sut.interpret(&mut set).await;
+            assert!(!set.session.extensions.is_enabled(&extension::REQUIRETLS));
+
+            set.session.tls = Some(TlsInfo::default());
+            let _ = sut.interpret(&mut set).await;
+            assert!(set.session.extensions.is_enabled(&extension::REQUIRETLS));
+        })
+    }
+
+    #[test]
+    fn flag_is_stored_on_transaction() {
+        async_std::task::block_on(async move {
+            let mut session = SmtpSession::default();
+            session.extensions.enable(&extension::REQUIRETLS);
+            session.transaction.mail = Some(SmtpMail::Mail(
+                SmtpPath::Null,
+                vec!["requiretls".to_owned()],
+            ));
+            let res = EsmtpRequireTls.start_mail(&mut session).await;
+            assert_eq!(res, StartMailResult::Accepted);
+            assert!(session.transaction.require_tls);
+        })
+    }
+
+    #[test]
+    fn flag_is_refused_on_plaintext() {
+        async_std::task::block_on(async move {
+            let mut session = SmtpSession::default();
+            session.transaction.mail = Some(SmtpMail::Mail(
+                SmtpPath::Null,
+                vec!["REQUIRETLS".to_owned()],
+            ));
+            let res = EsmtpRequireTls.start_mail(&mut session).await;
+            assert!(matches!(
+                res,
+                StartMailResult::Failed(StartMailFailure::InvalidParameter, _)
+            ));
+            assert!(!session.transaction.require_tls);
+        })
+    }
+}
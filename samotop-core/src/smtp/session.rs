@@ -1,17 +1,31 @@
+use crate::io::tls::TlsInfo;
 use crate::io::ConnectionInfo;
-use crate::mail::{AddRecipientFailure, StartMailFailure, Transaction};
+use crate::mail::{
+    AddRecipientFailure, AuthResult, CheckBodyFailure, CheckHeloFailure, OpenSessionFailure,
+    StartMailFailure, Transaction,
+};
 use crate::smtp::*;
 
 #[derive(Debug)]
 pub struct SmtpSession {
     /// Description of the underlying connection
     pub connection: ConnectionInfo,
+    /// Details of the TLS session once the connection is encrypted
+    pub tls: Option<TlsInfo>,
     /// ESMTP extensions enabled for this session
     pub extensions: ExtensionSet,
     /// The name of the service serving this session
     pub service_name: String,
     /// The name of the peer as introduced by the HELO command
     pub peer_name: Option<String>,
+    /// The name of the peer as verified by forward-confirmed reverse DNS (FCrDNS)
+    pub verified_peer_name: Option<String>,
+    /// Notes left by guards about the session, such as failed checks that were tolerated
+    pub tags: Vec<String>,
+    /// The client is whitelisted, the check guards are skipped for the rest of the session
+    pub whitelisted: bool,
+    /// Results of the authentication checks of the connection, such as iprev, for all mail of the session
+    pub auth_results: Vec<AuthResult>,
     /// Output to be processed by a driver - responses and IO controls
     pub output: Vec<DriverControl>,
     /// Input to be interpretted
@@ -26,9 +40,14 @@ impl Default for SmtpSession {
     fn default() -> Self {
         Self {
             connection: Default::default(),
+            tls: Default::default(),
             extensions: Default::default(),
             service_name: "samotop".to_string(),
             peer_name: Default::default(),
+            verified_peer_name: Default::default(),
+            tags: Default::default(),
+            whitelisted: Default::default(),
+            auth_results: Default::default(),
             output: Default::default(),
             input: Default::default(),
             mode: Default::default(),
@@ -157,6 +176,34 @@ impl SmtpSession {
     pub fn say_shutdown_ok(&mut self) -> SayResult {
         self.say_shutdown(SmtpReply::ClosingConnectionInfo(self.service_name.clone()))
     }
+    /// Refuse the session with the connection-opening response and shut it down
+    pub fn say_session_refused(
+        &mut self,
+        failure: OpenSessionFailure,
+        description: String,
+    ) -> SayResult {
+        use OpenSessionFailure as F;
+        warn!("Session refused: {:?}, {}", failure, description);
+        match failure {
+            F::NoService => {
+                self.say_shutdown(SmtpReply::NoServiceFailure(self.service_name.clone()))
+            }
+            F::MailNotAccepted => self.say_shutdown(SmtpReply::MailNotAcceptedByHostFailure),
+            F::FailedTemporarily => self.say_shutdown_service_err(),
+            F::Custom(reply) => self.say_shutdown(reply),
+        }
+    }
+    pub fn say_helo_failed(&mut self, failure: CheckHeloFailure, description: String) -> SayResult {
+        use CheckHeloFailure as F;
+        warn!("Greeting refused: {:?}, {}", failure, description);
+        match failure {
+            F::TerminateSession => self.say_shutdown_service_err(),
+            F::Rejected => self.say_reply(SmtpReply::MailboxNotAvailableFailure),
+            F::InvalidName => self.say_reply(SmtpReply::ParameterSyntaxFailure),
+            F::FailedTemporarily => self.say_reply(SmtpReply::ProcesingError),
+            F::Custom(reply) => self.say_reply(reply),
+        }
+    }
     pub fn say_mail_failed(&mut self, failure: StartMailFailure, description: String) -> SayResult {
         use StartMailFailure as F;
         error!("Sending mail failed: {:?}, {}", failure, description);
@@ -169,6 +216,8 @@ impl SmtpSession {
             F::StorageExhaustedPermanently => self.say_reply(SmtpReply::StorageFailure),
             F::StorageExhaustedTemporarily => self.say_reply(SmtpReply::StorageError),
             F::FailedTemporarily => self.say_reply(SmtpReply::ProcesingError),
+            F::EncryptionRequired => self.say_reply(SmtpReply::EncryptionRequiredFailure),
+            F::Custom(reply) => self.say_reply(reply),
         }
     }
     pub fn say_rcpt_failed(
@@ -188,7 +237,20 @@ impl SmtpSession {
             F::InvalidParameterValue => self.say_reply(SmtpReply::ParametersNotAccommodatedError),
             F::StorageExhaustedPermanently => self.say_reply(SmtpReply::StorageFailure),
             F::StorageExhaustedTemporarily => self.say_reply(SmtpReply::StorageError),
+            F::TooManyRecipients => self.say_reply(SmtpReply::TooManyRecipientsError),
+            F::FailedTemporarily => self.say_reply(SmtpReply::ProcesingError),
+            F::Custom(reply) => self.say_reply(reply),
+        }
+    }
+    pub fn say_body_failed(&mut self, failure: CheckBodyFailure, description: String) -> SayResult {
+        use CheckBodyFailure as F;
+        error!("Mail content refused: {:?}, {}", failure, description);
+        match failure {
+            F::TerminateSession => self.say_shutdown_service_err(),
+            F::Rejected => self.say_reply(SmtpReply::MailboxNotAvailableFailure),
+            F::StorageExhaustedPermanently => self.say_reply(SmtpReply::StorageFailure),
             F::FailedTemporarily => self.say_reply(SmtpReply::ProcesingError),
+            F::Custom(reply) => self.say_reply(reply),
         }
     }
     pub fn say_ok_recipient_not_local(&mut self, path: SmtpPath) -> SayResult {
@@ -220,8 +282,9 @@ impl std::fmt::Display for SmtpSession {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         write!(
             f,
-            "Client {:?} using service {} with extensions {} on {}. There are {} input bytes and {} output items pending.",
+            "Client {:?} ({}) using service {} with extensions {} on {}. There are {} input bytes and {} output items pending.",
             self.peer_name,
+            self.verified_peer_name.as_deref().unwrap_or("unverified"),
             self.service_name,
             self.extensions
                 .iter()
@@ -248,6 +311,8 @@ mod tests {
         sut.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
         sut.transaction.rcpts.push(Recipient::null());
         sut.transaction.extra_headers.insert_str(0, "feeeha");
+        sut.transaction.require_tls = true;
+        sut.transaction.spool = Some(Default::default());
         sut.reset();
         assert!(sut.transaction.is_empty());
     }
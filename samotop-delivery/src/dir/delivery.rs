@@ -1,25 +1,160 @@
-use crate::dir::MaildirTransport;
-use crate::dispatch::DispatchMail;
-use samotop_core::{common::*, mail::*};
+use crate::dir::{MailFile, MaildirTransport};
+use crate::prelude::{EmailAddress, Envelope, Transport};
+use crate::MailDataStream;
+use async_std::io::prelude::WriteExt;
+use samotop_core::{common::*, mail::*, smtp::SmtpSession};
 use std::path::PathBuf;
 
 /// MailSetup that adds a mail dir dispatch.
 ///
 /// E-mails are stored in the given folder according to MailDir standard.
+/// Each recipient gets a copy of its own starting with a `Delivered-To` header
+/// and, if a guard such as `SubAddressing` rewrote the address, an `X-Original-To`
+/// header with the address as given by the client.
 #[derive(Debug)]
 pub struct MailDir {
     pub path: PathBuf,
+    /// File the copy of a recipient with a sub-address detail into the `.detail` Maildir++ folder
+    pub detail_folders: bool,
 }
 
 impl MailDir {
     pub fn new(path: PathBuf) -> Result<MailDir> {
-        Ok(MailDir { path })
+        Ok(MailDir {
+            path,
+            detail_folders: false,
+        })
+    }
+    /// File the copy of a recipient with a sub-address detail into the `.detail` Maildir++ folder.
+    /// Details that are not plain names, such as `../x`, are delivered to the inbox.
+    pub fn with_detail_folders(mut self) -> Self {
+        self.detail_folders = true;
+        self
+    }
+    fn folder(&self, rcpt: &Recipient) -> PathBuf {
+        match rcpt.detail.as_deref() {
+            Some(detail) if self.detail_folders && is_folder_name(detail) => {
+                self.path.join(format!(".{}", detail))
+            }
+            _ => self.path.clone(),
+        }
+    }
+    async fn deliver(&self, transaction: &mut Transaction) -> Result<()> {
+        if transaction.rcpts.is_empty() {
+            return Err("No recipients to deliver to".into());
+        }
+        let sender = transaction
+            .mail
+            .as_ref()
+            .map(|mail| EmailAddress::new(mail.sender().address()))
+            .transpose()?;
+
+        let mut copies = Vec::with_capacity(transaction.rcpts.len());
+        for (index, rcpt) in transaction.rcpts.iter().enumerate() {
+            let id = match transaction.rcpts.len() {
+                1 => transaction.id.clone(),
+                _ => format!("{}.{}", transaction.id, index + 1),
+            };
+            let recipient = EmailAddress::new(rcpt.address.address())?;
+            let envelope =
+                Envelope::new(sender.clone(), vec![recipient], id).map_err(Error::from)?;
+            let transport = MaildirTransport::new(self.folder(rcpt));
+            let mut copy = transport.send_stream(envelope).await?;
+            copy.write_all(delivery_headers(rcpt).as_bytes()).await?;
+            copies.push(copy);
+        }
+        trace!("Writing {} mail dir copies.", copies.len());
+        transaction.sink = Some(Box::pin(MailCopies {
+            written: vec![0; copies.len()],
+            copies,
+        }));
+
+        Ok(())
     }
 }
 
 impl<T: AcceptsDispatch> MailSetup<T> for MailDir {
     fn setup(self, config: &mut T) {
-        let transport = MaildirTransport::new(self.path);
-        config.add_last_dispatch(DispatchMail::new(transport))
+        config.add_last_dispatch(self)
+    }
+}
+
+impl MailDispatch for MailDir {
+    fn open_mail_body<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, DispatchResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            match self.deliver(&mut session.transaction).await {
+                Err(e) => {
+                    error!("Failed to start mail: {:?}", e);
+                    Err(DispatchError::Temporary)
+                }
+                Ok(()) => Ok(()),
+            }
+        })
+    }
+}
+
+fn delivery_headers(rcpt: &Recipient) -> String {
+    let mut headers = format!("Delivered-To: {}\r\n", rcpt.address.address());
+    if let Some(original) = rcpt.original.as_ref() {
+        headers += format!("X-Original-To: {}\r\n", original.address()).as_str();
+    }
+    headers
+}
+
+fn is_folder_name(detail: &str) -> bool {
+    !detail.is_empty()
+        && detail
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Writes the same mail data into the copies of all recipients.
+///
+/// A pending write is resumed where each copy left off,
+/// so it must be retried with the same data.
+#[derive(Debug)]
+struct MailCopies {
+    copies: Vec<MailFile>,
+    written: Vec<usize>,
+}
+
+impl io::Write for MailCopies {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        for (copy, written) in this.copies.iter_mut().zip(this.written.iter_mut()) {
+            while *written < buf.len() {
+                match ready!(Pin::new(&mut *copy).poll_write(cx, &buf[*written..]))? {
+                    0 => return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())),
+                    len => *written += len,
+                }
+            }
+        }
+        this.written.iter_mut().for_each(|written| *written = 0);
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        for copy in self.get_mut().copies.iter_mut() {
+            ready!(Pin::new(copy).poll_flush(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        for copy in self.get_mut().copies.iter_mut() {
+            if !copy.is_done() {
+                ready!(Pin::new(copy).poll_close(cx))?;
+            }
+        }
+        Poll::Ready(Ok(()))
     }
 }
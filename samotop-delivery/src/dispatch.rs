@@ -34,7 +34,7 @@ where
             match deliver_mail(&self.transport, &mut session.transaction).await {
                 Err(e) => {
                     error!("Failed to start mail: {:?}", e);
-                    Err(DispatchError::Temporary)
+                    Err(dispatch_error(e.as_ref()))
                 }
                 Ok(()) => Ok(()),
             }
@@ -42,6 +42,21 @@ where
     }
 }
 
+/// A mail requiring TLS that cannot get it (RFC 8689) and a mail refused
+/// with a 5xx reply fail permanently. Anything else may pass later.
+fn dispatch_error(error: &(dyn std::error::Error + 'static)) -> DispatchError {
+    #[cfg(feature = "smtp-transport")]
+    {
+        use crate::smtp::Error;
+        if let Some(Error::RequireTls(_)) | Some(Error::Permanent(_)) = error.downcast_ref() {
+            return DispatchError::Permanent;
+        }
+    }
+    #[cfg(not(feature = "smtp-transport"))]
+    let _ = error;
+    DispatchError::Temporary
+}
+
 async fn deliver_mail<T>(transport: &T, transaction: &mut Transaction) -> Result<()>
 where
     T: Transport + Send + Sync,
@@ -59,11 +74,74 @@ where
         .map(|rcpt| EmailAddress::new(rcpt.address.address()))
         .collect();
 
-    let envelope =
-        Envelope::new(sender, recipients?, transaction.id.clone()).map_err(Error::from)?;
+    let envelope = Envelope::new(sender, recipients?, transaction.id.clone())
+        .map_err(Error::from)?
+        .with_require_tls(transaction.require_tls);
     trace!("Starting downstream mail transaction.");
     let stream = transport.send_stream(envelope).await?;
     transaction.sink = Some(Box::pin(stream));
 
     Ok(())
 }
+
+#[cfg(all(test, feature = "smtp-transport"))]
+mod tests {
+    use super::*;
+    use crate::smtp::Error;
+    use crate::stub::StubStream;
+    use crate::SyncFuture;
+    use samotop_core::smtp::{command::SmtpMail, SmtpHost, SmtpPath};
+
+    #[derive(Debug)]
+    struct Failing(fn() -> Error);
+
+    impl Transport for Failing {
+        type DataStream = StubStream;
+        type Error = Error;
+        fn send_stream<'s, 'a>(
+            &'s self,
+            _envelope: Envelope,
+        ) -> SyncFuture<'a, std::result::Result<StubStream, Error>>
+        where
+            's: 'a,
+        {
+            Box::pin(ready(Err((self.0)())))
+        }
+    }
+
+    async fn dispatch(error: fn() -> Error) -> DispatchResult {
+        let path = SmtpPath::Mailbox {
+            name: "user".to_owned(),
+            host: SmtpHost::Domain("example.org".to_owned()),
+            relays: vec![],
+        };
+        let mut session = SmtpSession::default();
+        session.transaction.mail = Some(SmtpMail::Mail(path.clone(), vec![]));
+        session.transaction.rcpts.push(Recipient::new(path));
+        DispatchMail::new(Failing(error))
+            .open_mail_body(&mut session)
+            .await
+    }
+
+    #[async_attributes::test]
+    async fn require_tls_failure_is_permanent() {
+        let res = dispatch(|| Error::RequireTls("the server does not support REQUIRETLS")).await;
+        assert!(matches!(res, Err(DispatchError::Permanent)));
+    }
+
+    #[async_attributes::test]
+    async fn refused_mail_failure_is_permanent() {
+        let res =
+            dispatch(|| Error::Permanent("554 5.7.1 no thanks\r\n".parse().expect("reply"))).await;
+        assert!(matches!(res, Err(DispatchError::Permanent)));
+    }
+
+    #[async_attributes::test]
+    async fn other_failures_are_temporary() {
+        let res =
+            dispatch(|| Error::Transient("451 4.3.0 later\r\n".parse().expect("reply"))).await;
+        assert!(matches!(res, Err(DispatchError::Temporary)));
+        let res = dispatch(|| Error::Resolution).await;
+        assert!(matches!(res, Err(DispatchError::Temporary)));
+    }
+}
@@ -0,0 +1,159 @@
+//! The DKIM transport signs the mail and passes it on to another transport.
+//! The mail is buffered in memory until it is complete, then the `DKIM-Signature`
+//! header is prepended and the mail is written to the inner transport.
+//! Mail larger than the `max_size` fails instead of filling up the memory.
+//!
+
+pub use samotop_with_dkim::{Canonicalization, DkimPrivateKey, DkimSigner};
+
+use crate::{Envelope, MailDataStream, SyncFuture, Transport};
+use pin_project::pin_project;
+use samotop_core::common::*;
+
+/// Signs the mail with DKIM before sending it with the inner transport
+#[derive(Debug)]
+pub struct DkimSigningTransport<T> {
+    inner: T,
+    signer: Arc<DkimSigner>,
+    max_size: usize,
+}
+
+impl<T> DkimSigningTransport<T> {
+    /// The default limit of the mail size, 50 MiB
+    pub const DEFAULT_MAX_SIZE: usize = 50 * 1024 * 1024;
+    /// Creates a new transport signing mail with the signer and sending it with the inner transport
+    pub fn new(inner: T, signer: DkimSigner) -> Self {
+        Self {
+            inner,
+            signer: Arc::new(signer),
+            max_size: Self::DEFAULT_MAX_SIZE,
+        }
+    }
+    /// Fail mail larger than `max_size` bytes rather than buffering it for signing
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<T> Transport for DkimSigningTransport<T>
+where
+    T: Transport + Sync,
+{
+    type DataStream = DkimSigningStream<T::DataStream>;
+    type Error = T::Error;
+    fn send_stream<'s, 'a>(
+        &'s self,
+        envelope: Envelope,
+    ) -> SyncFuture<'a, std::result::Result<Self::DataStream, Self::Error>>
+    where
+        's: 'a,
+    {
+        let signer = self.signer.clone();
+        let max_size = self.max_size;
+        let inner = self.inner.send_stream(envelope);
+        Box::pin(
+            async move { Ok(DkimSigningStream::new(inner.await?, signer).with_max_size(max_size)) },
+        )
+    }
+}
+
+/// Buffers the mail and writes it signed to the inner stream on close
+#[pin_project]
+pub struct DkimSigningStream<S> {
+    #[pin]
+    inner: S,
+    signer: Arc<DkimSigner>,
+    buffer: Vec<u8>,
+    max_size: Option<usize>,
+    signed: bool,
+    written: usize,
+}
+
+impl<S> DkimSigningStream<S> {
+    pub fn new(inner: S, signer: Arc<DkimSigner>) -> Self {
+        Self {
+            inner,
+            signer,
+            buffer: vec![],
+            max_size: None,
+            signed: false,
+            written: 0,
+        }
+    }
+    /// Fail writes beyond `max_size` bytes of mail
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for DkimSigningStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DkimSigningStream")
+            .field("inner", &self.inner)
+            .field("signer", &self.signer)
+            .field("buffered", &self.buffer.len())
+            .field("max_size", &self.max_size)
+            .field("signed", &self.signed)
+            .field("written", &self.written)
+            .finish()
+    }
+}
+
+impl<S: MailDataStream> MailDataStream for DkimSigningStream<S> {
+    fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl<S: io::Write> io::Write for DkimSigningStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.project();
+        if *this.signed {
+            return Poll::Ready(Err(std::io::Error::other(
+                "the mail has been signed already",
+            )));
+        }
+        if let Some(max_size) = *this.max_size {
+            if this.buffer.len() + buf.len() > max_size {
+                return Poll::Ready(Err(std::io::Error::other(format!(
+                    "the mail is too large to sign, the limit is {} bytes",
+                    max_size
+                ))));
+            }
+        }
+        this.buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        // nothing goes out before the mail is complete and signed
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut this = self.project();
+        if !*this.signed {
+            let header = this
+                .signer
+                .sign(this.buffer.as_slice())
+                .map_err(std::io::Error::other)?;
+            this.buffer.splice(0..0, header.into_bytes());
+            *this.signed = true;
+        }
+        while *this.written < this.buffer.len() {
+            let len = ready!(this
+                .inner
+                .as_mut()
+                .poll_write(cx, &this.buffer[*this.written..]))?;
+            if len == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            *this.written += len;
+        }
+        this.inner.poll_close(cx)
+    }
+}
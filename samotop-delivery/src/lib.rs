@@ -22,6 +22,7 @@ as a dispatch solution for incoming mail, but you can use it to send mail, too.
     - [x] Write mail to a single dir - fit for debug only
  - [x] Popular integrations:
     - [x] Send mail with sendmail
+ - [x] Sign mail with DKIM - wrap any transport in `DkimSigningTransport` (feature `dkim`)
 
 LMTP on Unix socket enables wide range of local delivery integrations, dovecot or postfix for instance. Some mail delivery programs speak LMTP, too.
 
@@ -48,6 +49,8 @@ extern crate log;
 
 pub mod dir;
 mod dispatch;
+#[cfg(feature = "dkim")]
+pub mod dkim;
 #[cfg(feature = "file-transport")]
 pub mod file;
 #[cfg(feature = "journal-transport")]
@@ -61,6 +64,8 @@ pub mod types;
 
 pub mod prelude {
     pub use crate::dir::*;
+    #[cfg(feature = "dkim")]
+    pub use crate::dkim::*;
     #[cfg(feature = "file-transport")]
     pub use crate::file::*;
     #[cfg(feature = "journal-transport")]
@@ -0,0 +1,240 @@
+use crate::smtp::{
+    net::Connector,
+    response::{Response, Severity},
+    Error, SmtpClient, SmtpTransport,
+};
+use crate::EmailAddress;
+use samotop_core::{common::*, mail::*, smtp::SmtpSession};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Verifies each RCPT with the downstream server before accepting it.
+///
+/// The downstream (typically the LMTP server behind the dispatch) is asked with
+/// MAIL FROM:<>, RCPT and RSET and its answer is mapped to `AddRecipientResult`.
+/// Unknown users are thus refused during the SMTP dialogue rather than bounced later.
+/// Definite answers are cached, temporary failures and errors are not.
+pub struct RecipientCallout<C: Connector> {
+    transport: SmtpTransport<SmtpClient, C>,
+    timeout: Duration,
+    valid_ttl: Duration,
+    invalid_ttl: Duration,
+    cache: CalloutCache<Response>,
+}
+
+impl<C: Connector> RecipientCallout<C> {
+    pub fn new(client: SmtpClient, connector: C) -> Self {
+        Self {
+            transport: client.connect_with(connector),
+            timeout: Duration::from_secs(30),
+            valid_ttl: Duration::from_secs(3600),
+            invalid_ttl: Duration::from_secs(600),
+            cache: CalloutCache::new(10_000),
+        }
+    }
+    /// How long to wait for the whole callout, connection included
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// How long to remember accepted and refused recipients
+    pub fn with_cache_ttl(mut self, valid: Duration, invalid: Duration) -> Self {
+        self.valid_ttl = valid;
+        self.invalid_ttl = invalid;
+        self
+    }
+    /// How many recipients to remember at most
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = CalloutCache::new(capacity);
+        self
+    }
+    /// Ask the downstream about the recipient, or the cache if it knows.
+    pub async fn verify(&self, recipient: &str) -> std::result::Result<Response, Error> {
+        let key = recipient.to_lowercase();
+        if let Some(response) = self.cache.get(key.as_str()) {
+            trace!("Callout for {} is cached: {:?}", recipient, response);
+            return Ok(response);
+        }
+        let address = EmailAddress::new(recipient.to_owned())
+            .map_err(|_| Error::Client("invalid recipient address"))?;
+        let response = match self
+            .transport
+            .verify_recipient(None, address, self.timeout)
+            .await
+        {
+            Ok(response) => response,
+            Err(Error::Permanent(response)) => response,
+            Err(e) => return Err(e),
+        };
+        let ttl = match response.is_positive() {
+            true => self.valid_ttl,
+            false => self.invalid_ttl,
+        };
+        self.cache.insert(key, response.clone(), ttl);
+        Ok(response)
+    }
+}
+
+/// Remembers callout answers until they expire, keeping at most `capacity` of them.
+///
+/// The addresses come from the clients, so the cache must not grow without bounds.
+/// Expired answers are dropped when they are looked up or when room is needed.
+#[derive(Debug)]
+pub(crate) struct CalloutCache<V> {
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+}
+
+impl<V: Clone> CalloutCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.lock();
+        match entries.get(key) {
+            Some((expires, value)) if *expires > Instant::now() => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+    pub fn insert(&self, key: String, value: V, ttl: Duration) {
+        if ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.lock();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (expires, _)| *expires > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            // make room by dropping the answer that would expire first
+            let first = entries
+                .iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(key, _)| key.clone());
+            if let Some(first) = first {
+                entries.remove(&first);
+            }
+        }
+        entries.insert(key, (now + ttl, value));
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Instant, V)>> {
+        // the map is consistent even if another thread panicked
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<C: Connector> fmt::Debug for RecipientCallout<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecipientCallout")
+            .field("transport", &self.transport)
+            .field("timeout", &self.timeout)
+            .field("valid_ttl", &self.valid_ttl)
+            .field("invalid_ttl", &self.invalid_ttl)
+            .finish()
+    }
+}
+
+impl<C: Connector, T: AcceptsGuard> MailSetup<T> for RecipientCallout<C>
+where
+    C: 'static,
+{
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl<C: Connector> MailGuard for RecipientCallout<C> {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let address = rcpt.address.address();
+            let response = match self.verify(address.as_str()).await {
+                Ok(response) => response,
+                Err(Error::Transient(response)) => response,
+                Err(e) => {
+                    warn!("Callout for {} failed: {}", address, e);
+                    return AddRecipientResult::Failed(
+                        AddRecipientFailure::FailedTemporarily,
+                        format!("Callout for {} failed", address),
+                    );
+                }
+            };
+            if response.is_positive() {
+                return AddRecipientResult::Inconclusive(rcpt);
+            }
+            let failure = if response.has_code(452) {
+                AddRecipientFailure::StorageExhaustedTemporarily
+            } else if response.has_code(552) {
+                AddRecipientFailure::StorageExhaustedPermanently
+            } else if response.has_code(553) {
+                AddRecipientFailure::InvalidRecipient
+            } else if response.code.severity == Severity::TransientNegativeCompletion {
+                AddRecipientFailure::RejectedTemporarily
+            } else {
+                AddRecipientFailure::RejectedPermanently
+            };
+            AddRecipientResult::Failed(
+                failure,
+                format!(
+                    "Callout for {} refused: {} {}",
+                    address,
+                    response.code,
+                    response.first_line().unwrap_or_default()
+                ),
+            )
+        })
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_keeps_at_most_capacity_answers() {
+        let sut = CalloutCache::new(2);
+        sut.insert("a".to_owned(), 1, Duration::from_secs(60));
+        sut.insert("b".to_owned(), 2, Duration::from_secs(10));
+        sut.insert("c".to_owned(), 3, Duration::from_secs(60));
+        assert_eq!(sut.get("a"), Some(1));
+        assert_eq!(sut.get("b"), None);
+        assert_eq!(sut.get("c"), Some(3));
+        assert_eq!(sut.lock().len(), 2);
+    }
+
+    #[test]
+    fn expired_answers_are_forgotten() {
+        let sut = CalloutCache::new(2);
+        sut.insert("a".to_owned(), 1, Duration::from_nanos(1));
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(sut.get("a"), None);
+        assert!(sut.lock().is_empty());
+    }
+}
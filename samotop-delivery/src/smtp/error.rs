@@ -35,6 +35,9 @@ pub enum Error {
     /// Internal client error
     #[error("client: {0}")]
     Client(&'static str),
+    /// The message requires TLS (RFC 8689) and the connection does not satisfy it
+    #[error("REQUIRETLS support required: {0}")]
+    RequireTls(&'static str),
     /// DNS resolution error
     #[error("could not resolve hostname")]
     Resolution,
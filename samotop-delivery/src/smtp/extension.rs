@@ -94,6 +94,10 @@ pub enum Extension {
     ///
     /// RFC 2487: https://tools.ietf.org/html/rfc2487
     StartTls,
+    /// REQUIRETLS keyword
+    ///
+    /// RFC 8689: https://tools.ietf.org/html/rfc8689
+    RequireTls,
     /// AUTH mechanism
     Authentication(Mechanism),
 }
@@ -104,6 +108,7 @@ impl Display for Extension {
             Extension::EightBitMime => write!(f, "8BITMIME"),
             Extension::SmtpUtfEight => write!(f, "SMTPUTF8"),
             Extension::StartTls => write!(f, "STARTTLS"),
+            Extension::RequireTls => write!(f, "REQUIRETLS"),
             Extension::Authentication(ref mechanism) => write!(f, "AUTH {}", mechanism),
         }
     }
@@ -167,6 +172,9 @@ impl ServerInfo {
                 Some("STARTTLS") => {
                     features.insert(Extension::StartTls);
                 }
+                Some("REQUIRETLS") => {
+                    features.insert(Extension::RequireTls);
+                }
                 Some("AUTH") => {
                     for &mechanism in &split[1..] {
                         match mechanism {
@@ -218,6 +226,8 @@ pub enum MailParameter {
     Size(usize),
     /// `SMTPUTF8` parameter
     SmtpUtfEight,
+    /// `REQUIRETLS` parameter
+    RequireTls,
     /// Custom parameter
     Other {
         /// Parameter keyword
@@ -233,6 +243,7 @@ impl Display for MailParameter {
             MailParameter::Body(ref value) => write!(f, "BODY={}", value),
             MailParameter::Size(size) => write!(f, "SIZE={}", size),
             MailParameter::SmtpUtfEight => f.write_str("SMTPUTF8"),
+            MailParameter::RequireTls => f.write_str("REQUIRETLS"),
             MailParameter::Other {
                 ref keyword,
                 value: Some(ref value),
@@ -13,19 +13,25 @@
 //! * SMTPUTF8 ([RFC 6531](http://tools.ietf.org/html/rfc6531))
 
 pub mod authentication;
+mod callout;
 pub mod commands;
 mod delivery;
 mod error;
 pub mod extension;
 pub mod net;
 pub mod response;
+#[cfg(feature = "sender-callout")]
+mod sender_callout;
 mod smtp_client;
 mod stream;
 mod transport;
 mod util;
 
+pub use self::callout::*;
 pub use self::delivery::*;
 pub use self::error::*;
+#[cfg(feature = "sender-callout")]
+pub use self::sender_callout::*;
 pub use self::smtp_client::*;
 pub use self::stream::*;
 pub use self::transport::*;
@@ -0,0 +1,389 @@
+use crate::smtp::{
+    extension::ClientId, net::DefaultConnector, CalloutCache, ClientSecurity, Error, SmtpClient,
+};
+use crate::EmailAddress;
+use samotop_core::{
+    common::*,
+    dns::{default_resolver, DnsError, DnsResolver},
+    mail::{
+        AcceptsGuard, AddRecipientResult, MailGuard, MailSetup, Recipient, StartMailFailure,
+        StartMailResult,
+    },
+    smtp::{SmtpHost, SmtpPath, SmtpSession},
+};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Sender address verification, complementary to SPF.
+///
+/// On MAIL, the sender domain must have a working MX (or an implicit MX - an A/AAAA record)
+/// and, unless disabled with `without_callout()`, the MX must accept the sender as a recipient
+/// when asked with MAIL FROM:<>, RCPT and RSET. Null senders and address literals are not checked.
+/// Definite answers are cached, if the verification cannot be done the mail is accepted.
+#[derive(Clone)]
+pub struct SenderCallout {
+    resolver: Option<Arc<dyn DnsResolver>>,
+    callout: bool,
+    port: u16,
+    timeout: Duration,
+    valid_ttl: Duration,
+    invalid_ttl: Duration,
+    cache: Arc<CalloutCache<SenderCalloutResult>>,
+}
+
+/// The result of the sender verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SenderCalloutResult {
+    /// The domain has an MX that accepts the sender
+    Verified,
+    /// The domain does not accept mail, it has no MX, A nor AAAA records or a null MX
+    NoMx,
+    /// The MX refused the sender with the given reply
+    Refused(String),
+    /// DNS or the MX failed, we do not know
+    Unknown,
+}
+
+impl Default for SenderCallout {
+    fn default() -> Self {
+        Self {
+            resolver: None,
+            callout: true,
+            port: 25,
+            timeout: Duration::from_secs(30),
+            valid_ttl: Duration::from_secs(24 * 3600),
+            invalid_ttl: Duration::from_secs(3600),
+            cache: Arc::new(CalloutCache::new(10_000)),
+        }
+    }
+}
+
+impl SenderCallout {
+    /// Use the given resolver instead of the shared default one
+    pub fn with_resolver(mut self, resolver: impl DnsResolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    /// Only check that the domain has a working MX, do not call it
+    pub fn without_callout(mut self) -> Self {
+        self.callout = false;
+        self
+    }
+    /// Call the MX on another port than 25
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+    /// How long to wait for the whole callout, MX connection included
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// How long to remember verified and refused senders
+    pub fn with_cache_ttl(mut self, valid: Duration, invalid: Duration) -> Self {
+        self.valid_ttl = valid;
+        self.invalid_ttl = invalid;
+        self
+    }
+    /// How many senders to remember at most
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache = Arc::new(CalloutCache::new(capacity));
+        self
+    }
+    /// Verify the sender address, identifying ourselves with the given name in EHLO
+    pub async fn check(&self, sender: &str, domain: &str, me: &str) -> SenderCalloutResult {
+        let key = sender.to_lowercase();
+        if let Some(result) = self.cache.get(key.as_str()) {
+            trace!("Sender verification of {} is cached: {:?}", sender, result);
+            return result;
+        }
+        let resolver = self.resolver.clone().unwrap_or_else(default_resolver);
+        let result = self.check_with(resolver.as_ref(), sender, domain, me).await;
+        let ttl = match result {
+            SenderCalloutResult::Verified => self.valid_ttl,
+            SenderCalloutResult::Unknown => Duration::ZERO,
+            _ => self.invalid_ttl,
+        };
+        self.cache.insert(key, result.clone(), ttl);
+        result
+    }
+    async fn check_with(
+        &self,
+        resolver: &dyn DnsResolver,
+        sender: &str,
+        domain: &str,
+        me: &str,
+    ) -> SenderCalloutResult {
+        let hosts = match resolver.lookup_mx(domain).await {
+            Ok(mxs) => {
+                let hosts = mxs
+                    .into_iter()
+                    .map(|mx| mx.exchange)
+                    .filter(|host| !host.is_empty())
+                    .collect::<Vec<_>>();
+                // null MX - RFC 7505
+                if hosts.is_empty() {
+                    return SenderCalloutResult::NoMx;
+                }
+                hosts
+            }
+            // implicit MX - RFC 5321 section 5.1
+            Err(DnsError::NotFound) => vec![domain.to_owned()],
+            Err(e) => {
+                warn!("MX lookup of {} failed: {}", domain, e);
+                return SenderCalloutResult::Unknown;
+            }
+        };
+        let mut resolved = false;
+        let mut result = SenderCalloutResult::Unknown;
+        for host in hosts.iter() {
+            let ip = match Self::resolve(resolver, host).await {
+                Ok(Some(ip)) => ip,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Address lookup of MX {} failed: {}", host, e);
+                    resolved = true;
+                    continue;
+                }
+            };
+            resolved = true;
+            if !self.callout {
+                return SenderCalloutResult::Verified;
+            }
+            match self.call(ip, sender, me).await {
+                Ok(()) => return SenderCalloutResult::Verified,
+                Err(Error::Permanent(response)) => {
+                    return SenderCalloutResult::Refused(format!(
+                        "{} {}",
+                        response.code,
+                        response.first_line().unwrap_or_default()
+                    ))
+                }
+                Err(e) => {
+                    debug!("Callout to MX {} ({}) failed: {}", host, ip, e);
+                    result = SenderCalloutResult::Unknown;
+                }
+            }
+        }
+        match resolved {
+            true => result,
+            false => SenderCalloutResult::NoMx,
+        }
+    }
+    async fn resolve(
+        resolver: &dyn DnsResolver,
+        host: &str,
+    ) -> std::result::Result<Option<IpAddr>, DnsError> {
+        match resolver.lookup_a(host).await {
+            Ok(ips) if !ips.is_empty() => return Ok(Some(IpAddr::V4(ips[0]))),
+            Ok(_) | Err(DnsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        match resolver.lookup_aaaa(host).await {
+            Ok(ips) => Ok(ips.first().map(|ip| IpAddr::V6(*ip))),
+            Err(DnsError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    async fn call(&self, ip: IpAddr, sender: &str, me: &str) -> std::result::Result<(), Error> {
+        let address = EmailAddress::new(sender.to_owned())
+            .map_err(|_| Error::Client("invalid sender address"))?;
+        let transport =
+            SmtpClient::with_security(SocketAddr::new(ip, self.port), ClientSecurity::None)?
+                .hello_name(ClientId::Domain(me.to_owned()))
+                .timeout(Some(self.timeout))
+                .connect_with(DefaultConnector::default());
+        transport
+            .verify_recipient(None, address, self.timeout)
+            .await
+            .map(|_| ())
+    }
+}
+
+impl fmt::Debug for SenderCallout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SenderCallout")
+            .field("resolver", &self.resolver.as_ref().map(|_| "*"))
+            .field("callout", &self.callout)
+            .field("port", &self.port)
+            .field("timeout", &self.timeout)
+            .field("valid_ttl", &self.valid_ttl)
+            .field("invalid_ttl", &self.invalid_ttl)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for SenderCallout {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for SenderCallout {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let (sender, domain) = match session.transaction.mail.as_ref().map(|m| m.sender()) {
+            Some(
+                path @ SmtpPath::Mailbox {
+                    host: SmtpHost::Domain(domain),
+                    ..
+                },
+            ) => (path.address(), domain.clone()),
+            _ => return Box::pin(ready(StartMailResult::Accepted)),
+        };
+        let me = session.service_name.clone();
+        // lookup futures are not Sync, running them in a task of their own
+        let callout = self.clone();
+        Box::pin(async move {
+            let result = async_std::task::spawn(async move {
+                callout
+                    .check(sender.as_str(), domain.as_str(), me.as_str())
+                    .await
+            })
+            .await;
+            match result {
+                SenderCalloutResult::Verified | SenderCalloutResult::Unknown => {
+                    StartMailResult::Accepted
+                }
+                SenderCalloutResult::NoMx => StartMailResult::Failed(
+                    StartMailFailure::Rejected,
+                    "Sender domain does not accept mail".to_owned(),
+                ),
+                SenderCalloutResult::Refused(reply) => StartMailResult::Failed(
+                    StartMailFailure::Rejected,
+                    format!("Sender address refused by its MX: {}", reply),
+                ),
+            }
+        })
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::prelude::{BufReadExt, WriteExt};
+    use async_std::io::BufReader;
+    use async_std::net::TcpListener;
+    use samotop_core::dns::StaticResolver;
+    use samotop_core::smtp::command::SmtpMail;
+    use std::net::Ipv4Addr;
+
+    /// A fake MX accepting only postmaster
+    async fn fake_mx() -> (u16, async_std::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("address").port();
+        let server = async_std::task::spawn(async move {
+            let mut commands = vec![];
+            let (stream, _) = listener.accept().await.expect("accept");
+            let mut reader = BufReader::new(stream.clone());
+            let mut writer = stream;
+            writer
+                .write_all(b"220 fake ESMTP\r\n")
+                .await
+                .expect("banner");
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.expect("read") != 0 {
+                let reply: &[u8] = match line.as_str() {
+                    l if l.starts_with("RCPT TO:<postmaster@") => b"250 OK\r\n",
+                    l if l.starts_with("RCPT") => b"550 no such user\r\n",
+                    _ => b"250 OK\r\n",
+                };
+                commands.push(line.trim_end().to_owned());
+                writer.write_all(reply).await.expect("reply");
+                line.clear();
+            }
+            commands
+        });
+        (port, server)
+    }
+
+    fn dns() -> StaticResolver {
+        StaticResolver::default()
+            .with_mx("example.org", 10, "mx.example.org.")
+            .with_ip("mx.example.org", IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)))
+            .with_mx("nomail.example.org", 0, ".")
+    }
+
+    fn start_mail(sut: &SenderCallout, name: &str, domain: &str) -> StartMailResult {
+        let mut session = SmtpSession {
+            service_name: "mx.here.org".to_owned(),
+            ..Default::default()
+        };
+        session.transaction.mail = Some(SmtpMail::Mail(
+            SmtpPath::Mailbox {
+                name: name.to_owned(),
+                host: SmtpHost::Domain(domain.to_owned()),
+                relays: vec![],
+            },
+            vec![],
+        ));
+        async_std::task::block_on(sut.start_mail(&mut session))
+    }
+
+    #[test]
+    fn sender_is_verified_with_mx_callout() {
+        async_std::task::block_on(async move {
+            let (port, server) = fake_mx().await;
+            let sut = SenderCallout::default()
+                .with_resolver(dns())
+                .with_port(port);
+            assert_eq!(
+                sut.check("postmaster@example.org", "example.org", "mx.here.org")
+                    .await,
+                SenderCalloutResult::Verified
+            );
+            let commands = server.await;
+            assert!(commands.contains(&"MAIL FROM:<>".to_owned()));
+            assert!(commands.contains(&"RCPT TO:<postmaster@example.org>".to_owned()));
+            // answered from the cache
+            assert_eq!(
+                start_mail(&sut, "Postmaster", "example.org"),
+                StartMailResult::Accepted
+            );
+        })
+    }
+
+    #[test]
+    fn refused_sender_is_rejected() {
+        let (port, _server) = async_std::task::block_on(fake_mx());
+        let sut = SenderCallout::default()
+            .with_resolver(dns())
+            .with_port(port);
+        assert!(matches!(
+            start_mail(&sut, "nobody", "example.org"),
+            StartMailResult::Failed(StartMailFailure::Rejected, _)
+        ));
+    }
+
+    #[test]
+    fn domain_without_mail_is_rejected() {
+        let sut = SenderCallout::default().with_resolver(dns());
+        assert!(matches!(
+            start_mail(&sut, "x", "nomail.example.org"),
+            StartMailResult::Failed(StartMailFailure::Rejected, _)
+        ));
+        assert!(matches!(
+            start_mail(&sut, "x", "nowhere.example.org"),
+            StartMailResult::Failed(StartMailFailure::Rejected, _)
+        ));
+    }
+}
@@ -1,16 +1,17 @@
 use crate::smtp::error::Error;
 use crate::smtp::extension::{ClientId, Extension, MailBodyParameter, MailParameter, ServerInfo};
 use crate::smtp::net::{ConnectionConfiguration, Connector};
+use crate::smtp::response::Response;
 use crate::smtp::smtp_client::ClientSecurity;
 use crate::smtp::stream::SmtpDataStream;
 use crate::smtp::util::SmtpProto;
 use crate::{smtp::commands::*, SyncFuture};
-use crate::{Envelope, Transport};
+use crate::{EmailAddress, Envelope, Transport};
 use async_std::io::{Read, Write};
 use pin_project::pin_project;
 use potential::{Lease, Potential};
 use samotop_core::io::tls::MayBeTls;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fmt, pin::Pin};
 
 /// Structure that implements the high level SMTP client
@@ -37,9 +38,10 @@ where
     async fn connect(
         configuration: &Conf,
         connector: &Conn,
+        require_tls: bool,
     ) -> Result<SmtpConnection<Conn::Stream>, Error> {
         let mut stream = connector.connect(configuration).await?;
-        let server_info = Self::setup(configuration, &mut stream).await?;
+        let server_info = Self::setup(configuration, &mut stream, require_tls).await?;
         let reuse = configuration.max_reuse_count().saturating_add(1);
         Ok(SmtpConnection {
             stream,
@@ -47,11 +49,19 @@ where
             server_info,
         })
     }
-    async fn setup(configuration: &Conf, stream: &mut Conn::Stream) -> Result<ServerInfo, Error> {
+    async fn setup(
+        configuration: &Conf,
+        stream: &mut Conn::Stream,
+        require_tls: bool,
+    ) -> Result<ServerInfo, Error> {
         let timeout = configuration.timeout();
         let address = configuration.address();
         let my_id = configuration.hello_name();
-        let security = configuration.security();
+        let security = match require_tls {
+            // RFC 8689 - a REQUIRETLS mail never goes over plaintext
+            true => ClientSecurity::Required,
+            false => configuration.security(),
+        };
         let lmtp = configuration.lmtp();
 
         let mut client = SmtpProto::new(Pin::new(stream));
@@ -82,6 +92,9 @@ where
             let can_encrypt =
                 server_info.supports_feature(Extension::StartTls) && client.stream().can_encrypt();
 
+            if require_tls && !can_encrypt {
+                return Err(Error::RequireTls("the connection cannot be encrypted"));
+            }
             let encrypt = match security {
                 ClientSecurity::Required => true,
                 ClientSecurity::Opportunistic => can_encrypt,
@@ -132,6 +145,20 @@ where
             mail_options.push(MailParameter::SmtpUtfEight);
         }
 
+        if envelope.require_tls() {
+            // RFC 8689 - the connection must be encrypted and verified
+            // and the server must support REQUIRETLS as well
+            if !is_verified(&lease.stream) {
+                return Err(Error::RequireTls(
+                    "the connection is not encrypted with a verified certificate",
+                ));
+            }
+            if !lease.server_info.supports_feature(Extension::RequireTls) {
+                return Err(Error::RequireTls("the server does not support REQUIRETLS"));
+            }
+            mail_options.push(MailParameter::RequireTls);
+        }
+
         let mut client = SmtpProto::new(Pin::new(&mut lease.stream));
 
         // MAIL FROM:<reverse-path>
@@ -160,6 +187,54 @@ where
     }
 }
 
+impl<Conf: ConnectionConfiguration, Conn: Connector> SmtpTransport<Conf, Conn> {
+    /// Ask the server whether it would accept the recipient without sending a mail.
+    ///
+    /// Performs MAIL, RCPT and RSET on the connection shared with `send_stream()`.
+    /// The timeout applies to the whole verification, waiting for the connection included.
+    /// Refusals come back as `Error::Transient` or `Error::Permanent` with the RCPT response.
+    pub async fn verify_recipient(
+        &self,
+        sender: Option<EmailAddress>,
+        recipient: EmailAddress,
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        // the timeout applies to the whole verification, connection included
+        let deadline = Instant::now() + timeout;
+        let left = || deadline.saturating_duration_since(Instant::now());
+        let mut lease = match async_std::future::timeout(left(), self.inner.lease()).await? {
+            Ok(lease) => lease,
+            Err(gone) => {
+                let connect = Self::connect(&self.configuration, &self.connector, false);
+                gone.set(async_std::future::timeout(left(), connect).await??)
+            }
+        };
+
+        let mut client = SmtpProto::new(Pin::new(&mut lease.stream));
+        let rcpt = match client
+            .execute_command(MailCommand::new(sender, vec![]), [250], left())
+            .await
+        {
+            Ok(_) => {
+                client
+                    .execute_command(RcptCommand::new(recipient, vec![]), [2], left())
+                    .await
+            }
+            // not the recipient refused, do not mistake it for a recipient refusal
+            Err(Error::Transient(_)) | Err(Error::Permanent(_)) => {
+                Err(Error::Client("the server refused the verification sender"))
+            }
+            Err(e) => Err(e),
+        };
+        let rset = client.execute_command(RsetCommand, [250], left()).await;
+        if rset.is_err() {
+            // the connection is broken. Next call shall establish a new one.
+            lease.steal();
+        }
+        rcpt
+    }
+}
+
 impl<Conf: ConnectionConfiguration, Conn: Connector> Transport for SmtpTransport<Conf, Conn> {
     type DataStream = SmtpDataStream<Conn::Stream>;
     type Error = Error;
@@ -171,10 +246,17 @@ impl<Conf: ConnectionConfiguration, Conn: Connector> Transport for SmtpTransport
         's: 'a,
     {
         Box::pin(async move {
+            let require_tls = envelope.require_tls();
             let mut lease = match self.inner.lease().await {
                 Ok(lease) => lease,
-                Err(gone) => gone.set(Self::connect(&self.configuration, &self.connector).await?),
+                Err(gone) => gone
+                    .set(Self::connect(&self.configuration, &self.connector, require_tls).await?),
             };
+            if require_tls && !is_verified(&lease.stream) {
+                // the shared connection is not good enough, get a verified one
+                let verified = Self::connect(&self.configuration, &self.connector, true).await?;
+                drop(lease.replace(verified));
+            }
             let timeout = self.configuration.timeout();
 
             lease.reuse = lease.reuse.saturating_sub(1);
@@ -199,6 +281,15 @@ impl<Conf: ConnectionConfiguration, Conn: Connector> Transport for SmtpTransport
         })
     }
 }
+/// Is the stream encrypted and the server certificate verified?
+fn is_verified<S: MayBeTls>(stream: &S) -> bool {
+    stream.is_encrypted()
+        && stream
+            .tls_info()
+            .map(|info| info.peer_verified)
+            .unwrap_or_default()
+}
+
 #[derive(Debug)]
 pub(crate) struct SmtpConnection<S> {
     pub stream: S,
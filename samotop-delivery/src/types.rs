@@ -75,6 +75,12 @@ pub struct Envelope {
     reverse_path: Option<EmailAddress>,
     /// Unique message ID to facilitate troubleshooting and matching
     message_id: String,
+    /// The message must only be relayed over verified TLS - REQUIRETLS (RFC 8689)
+    #[cfg_attr(
+        feature = "serde-impls",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    require_tls: bool,
 }
 
 impl Envelope {
@@ -91,9 +97,16 @@ impl Envelope {
             forward_path: to,
             reverse_path: from,
             message_id,
+            require_tls: false,
         })
     }
 
+    /// Require verified TLS on every hop - REQUIRETLS (RFC 8689)
+    pub fn with_require_tls(mut self, require_tls: bool) -> Self {
+        self.require_tls = require_tls;
+        self
+    }
+
     /// Destination addresses of the envelope
     pub fn to(&self) -> &[EmailAddress] {
         self.forward_path.as_slice()
@@ -107,6 +120,11 @@ impl Envelope {
     pub fn message_id(&self) -> &str {
         &self.message_id
     }
+
+    /// Does the message require verified TLS on every hop?
+    pub fn require_tls(&self) -> bool {
+        self.require_tls
+    }
 }
 
 /// Error type for email content
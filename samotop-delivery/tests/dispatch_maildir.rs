@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod test {
+    use async_std::io::prelude::WriteExt;
+    use samotop_core::common::poll_fn;
+    use samotop_core::mail::{MailDispatch, Recipient};
+    use samotop_core::smtp::{command::SmtpMail, SmtpHost, SmtpPath, SmtpSession};
+    use samotop_delivery::prelude::MailDir;
+
+    use std::env::temp_dir;
+    use std::fs::{read_to_string, remove_dir_all};
+
+    fn path(name: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain("example.org".to_owned()),
+            relays: vec![],
+        }
+    }
+
+    #[async_attributes::test]
+    async fn maildir_copy_per_recipient() {
+        let dir = temp_dir().join("samotop-maildir-copies");
+        let _ = remove_dir_all(&dir);
+        let sut = MailDir::new(dir.clone())
+            .expect("maildir")
+            .with_detail_folders();
+
+        let mut tagged = Recipient::new(path("user"));
+        tagged.original = Some(path("user+lists"));
+        tagged.detail = Some("lists".to_owned());
+        let mut escaping = Recipient::new(path("other"));
+        escaping.detail = Some("../x".to_owned());
+
+        let mut session = SmtpSession::default();
+        session.transaction.id = "id".to_owned();
+        session.transaction.mail = Some(SmtpMail::Mail(path("sender"), vec![]));
+        session.transaction.rcpts = vec![tagged, escaping];
+        sut.open_mail_body(&mut session).await.expect("dispatch");
+        let mut sink = session.transaction.sink.take().expect("sink");
+        sink.write_all(b"Subject: hi\r\n\r\nbody\r\n")
+            .await
+            .expect("write");
+        poll_fn(|cx| sink.as_mut().poll_close(cx))
+            .await
+            .expect("close");
+
+        assert_eq!(
+            read_to_string(dir.join(".lists/new/id.1")).expect("tagged copy"),
+            "X-Samotop-From: sender@example.org\r\n\
+            X-Samotop-To: user@example.org\r\n\
+            Delivered-To: user@example.org\r\n\
+            X-Original-To: user+lists@example.org\r\n\
+            Subject: hi\r\n\r\nbody\r\n"
+        );
+        assert_eq!(
+            read_to_string(dir.join("new/id.2")).expect("inbox copy"),
+            "X-Samotop-From: sender@example.org\r\n\
+            X-Samotop-To: other@example.org\r\n\
+            Delivered-To: other@example.org\r\n\
+            Subject: hi\r\n\r\nbody\r\n"
+        );
+
+        remove_dir_all(dir).expect("cleanup");
+    }
+}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+#[cfg(feature = "dkim")]
+mod test {
+    use async_std::io;
+    use samotop_core::common::*;
+    use samotop_delivery::dkim::{DkimPrivateKey, DkimSigner, DkimSigningTransport};
+    use samotop_delivery::prelude::{Envelope, MailDataStream, Transport};
+    use samotop_delivery::SyncFuture;
+    use samotop_with_dkim::{DkimStatus, DkimVerifier, StaticRecords};
+    use std::sync::Mutex;
+
+    /// Keeps the mail in memory
+    #[derive(Debug, Default)]
+    struct MemoryTransport {
+        mail: Arc<Mutex<Vec<u8>>>,
+    }
+
+    #[derive(Debug)]
+    struct MemoryStream {
+        mail: Arc<Mutex<Vec<u8>>>,
+        closed: bool,
+    }
+
+    impl Transport for MemoryTransport {
+        type DataStream = MemoryStream;
+        type Error = io::Error;
+        fn send_stream<'s, 'a>(
+            &'s self,
+            _envelope: Envelope,
+        ) -> SyncFuture<'a, std::result::Result<MemoryStream, io::Error>>
+        where
+            's: 'a,
+        {
+            Box::pin(ready(Ok(MemoryStream {
+                mail: self.mail.clone(),
+                closed: false,
+            })))
+        }
+    }
+
+    impl MailDataStream for MemoryStream {
+        fn is_done(&self) -> bool {
+            self.closed
+        }
+    }
+
+    impl io::Write for MemoryStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            // take it a bit at a time
+            let len = std::cmp::min(buf.len(), 10);
+            self.mail.lock().unwrap().extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            self.closed = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn envelope() -> Envelope {
+        Envelope::new(
+            Some("joe@example.com".parse().unwrap()),
+            vec!["suzie@example.net".parse().unwrap()],
+            "id".to_string(),
+        )
+        .unwrap()
+    }
+
+    #[async_attributes::test]
+    async fn dkim_transport_signs_mail() {
+        let key = DkimPrivateKey::ed25519_from_seed(&[42; 32]).unwrap();
+        let keys = StaticRecords::default().with_key("sel", "example.com", key.public_key_record());
+        let inner = MemoryTransport::default();
+        let mail = inner.mail.clone();
+        let sut = DkimSigningTransport::new(inner, DkimSigner::new("example.com", "sel", key));
+        let message = "From: joe@example.com\r\nSubject: hi\r\n\r\nHello ß☺ example\r\n";
+
+        let stream = sut.send(envelope(), message.as_bytes()).await.unwrap();
+
+        assert!(stream.is_done());
+        let mail = mail.lock().unwrap().clone();
+        assert!(mail.starts_with(b"DKIM-Signature: v=1; a=ed25519-sha256;"));
+        assert!(mail.ends_with(message.as_bytes()));
+        let mut verifier = DkimVerifier::default();
+        verifier.update(mail.as_slice());
+        let results = verifier.verify(&keys).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].status, DkimStatus::Pass);
+    }
+
+    #[async_attributes::test]
+    async fn dkim_transport_fails_unsigned_mail() {
+        let key = DkimPrivateKey::ed25519_from_seed(&[42; 32]).unwrap();
+        let inner = MemoryTransport::default();
+        let mail = inner.mail.clone();
+        let sut = DkimSigningTransport::new(inner, DkimSigner::new("example.com", "sel", key));
+
+        sut.send(envelope(), "Subject: no sender\r\n\r\nHi\r\n".as_bytes())
+            .await
+            .unwrap_err();
+
+        assert!(mail.lock().unwrap().is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn dkim_transport_fails_oversized_mail() {
+        let key = DkimPrivateKey::ed25519_from_seed(&[42; 32]).unwrap();
+        let inner = MemoryTransport::default();
+        let mail = inner.mail.clone();
+        let sut = DkimSigningTransport::new(inner, DkimSigner::new("example.com", "sel", key))
+            .with_max_size(32);
+
+        sut.send(
+            envelope(),
+            "From: joe@example.com\r\n\r\nThis is more than we sign\r\n".as_bytes(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(mail.lock().unwrap().is_empty());
+    }
+}
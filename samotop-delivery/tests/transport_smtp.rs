@@ -43,4 +43,116 @@ mod test {
         let stream = client.connect_and_send_stream(envelope);
         is_sync(stream);
     }
+
+    #[async_attributes::test]
+    async fn smtp_transport_refuses_plaintext_requiretls() {
+        use async_std::io::prelude::{BufReadExt, WriteExt};
+        use async_std::io::BufReader;
+        use async_std::net::TcpListener;
+        use samotop_delivery::smtp::Error;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream.clone());
+            let mut writer = stream;
+            writer.write_all(b"220 fake ESMTP\r\n").await.unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            writer
+                .write_all(b"250-fake greets you\r\n250 REQUIRETLS\r\n")
+                .await
+                .unwrap();
+            // the client should not start a mail transaction
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            line
+        });
+
+        let envelope = Envelope::new(
+            Some("user@localhost".parse().unwrap()),
+            vec!["root@localhost".parse().unwrap()],
+            "id".to_string(),
+        )
+        .unwrap()
+        .with_require_tls(true);
+
+        let client =
+            SmtpClient::with_security(address, ClientSecurity::None).expect("should succeed");
+        let result = client.connect_and_send_stream(envelope).await;
+        assert!(matches!(result, Err(Error::RequireTls(_))));
+        assert!(!server.await.starts_with("MAIL"));
+    }
+
+    #[async_attributes::test]
+    async fn recipient_callout_refuses_unknown_users() {
+        use async_std::io::prelude::{BufReadExt, WriteExt};
+        use async_std::io::BufReader;
+        use async_std::net::TcpListener;
+        use samotop_core::{
+            mail::{AddRecipientFailure, AddRecipientResult, MailGuard, Recipient},
+            smtp::{SmtpHost, SmtpPath, SmtpSession},
+        };
+        use samotop_delivery::prelude::RecipientCallout;
+        use samotop_delivery::smtp::net::DefaultConnector;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = async_std::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream.clone());
+            let mut writer = stream;
+            let mut commands = vec![];
+            writer.write_all(b"220 fake LMTP\r\n").await.unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.unwrap() != 0 {
+                let reply: &[u8] = match line.as_str() {
+                    l if l.starts_with("LHLO") => b"250 fake greets you\r\n",
+                    l if l.starts_with("RCPT TO:<joe@") => b"550 no such user\r\n",
+                    _ => b"250 OK\r\n",
+                };
+                commands.push(line.trim_end().to_owned());
+                writer.write_all(reply).await.unwrap();
+                line.clear();
+            }
+            commands
+        });
+
+        let client = SmtpClient::with_security(address, ClientSecurity::None)
+            .unwrap()
+            .lmtp(true);
+        let sut = RecipientCallout::new(client, DefaultConnector::default());
+        let rcpt = |name: &str| {
+            Recipient::new(SmtpPath::Mailbox {
+                name: name.to_owned(),
+                host: SmtpHost::Domain("localhost".to_owned()),
+                relays: vec![],
+            })
+        };
+        let mut session = SmtpSession::default();
+
+        let res = sut.add_recipient(&mut session, rcpt("root")).await;
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+        let res = sut.add_recipient(&mut session, rcpt("joe")).await;
+        assert!(matches!(
+            res,
+            AddRecipientResult::Failed(AddRecipientFailure::RejectedPermanently, _)
+        ));
+        // answered from the cache
+        let res = sut.add_recipient(&mut session, rcpt("joe")).await;
+        assert!(matches!(res, AddRecipientResult::Failed(_, _)));
+
+        drop(sut);
+        let commands = server.await;
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|c| c.starts_with("RCPT"))
+                .collect::<Vec<_>>(),
+            vec!["RCPT TO:<root@localhost>", "RCPT TO:<joe@localhost>"]
+        );
+        assert!(commands.contains(&"MAIL FROM:<>".to_owned()));
+        assert!(commands.contains(&"RSET".to_owned()));
+    }
 }
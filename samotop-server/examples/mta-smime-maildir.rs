@@ -18,11 +18,10 @@ extern crate log;
 use async_std::fs::File;
 use async_std::io::ReadExt;
 use async_std::task;
-use async_tls::TlsAcceptor;
 use clap::Parser;
 use rustls::ServerConfig;
 use samotop::{
-    io::tls::RustlsProvider,
+    io::tls::{RustlsProvider, TlsAcceptor},
     mail::{
         smime::{Accounts, SMimeMail},
         spf::Spf,
@@ -32,6 +31,7 @@ use samotop::{
     smtp::{Esmtp, EsmtpStartTls, SmtpParser},
 };
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
@@ -54,7 +54,7 @@ async fn main_fut() -> Result<()> {
         + Esmtp.with(SmtpParser)
         + EsmtpStartTls.with(
             SmtpParser,
-            RustlsProvider::from(TlsAcceptor::from(setup.get_tls_config().await?)),
+            RustlsProvider::from(TlsAcceptor::from(Arc::new(setup.get_tls_config().await?))),
         );
 
     info!("I am {}", setup.get_my_name());
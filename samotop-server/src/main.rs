@@ -22,6 +22,7 @@ You can run your own privacy focussed, resource efficient mail server. [Samotop
 ## Mail transfer agent (MTA)
 
 - [ ] Mail relaying
+  - [x] Relaying restricted to allowed TLS client certificates
 - [ ] Antispam features:
   - [x] SPF - refuse mail with failing SPF check
   - [ ] Greylisting
@@ -91,6 +92,16 @@ Test STARTTLS:
 openssl s_client -connect localhost:25 -starttls smtp
 ```
 
+Test STARTTLS with a client certificate (see `--client-ca-file` and `--relay-cert`):
+```bash
+openssl s_client -connect localhost:25 -starttls smtp -cert client.crt -key client.key
+```
+
+Get the SHA-256 fingerprint of a client certificate:
+```bash
+openssl x509 -noout -fingerprint -sha256 -in client.crt
+```
+
 Debug with STARTTLS:
 ```bash
 openssl s_client -connect localhost:25 -debug -starttls smtp
@@ -116,15 +127,15 @@ extern crate log;
 use async_std::fs::File;
 use async_std::io::ReadExt;
 use async_std::task;
-use async_tls::TlsAcceptor;
 use clap::Parser;
 use rustls::ServerConfig;
-use samotop::io::tls::RustlsProvider;
+use samotop::io::tls::{client_auth_config, RustlsProvider, TlsAcceptor};
 use samotop::mail::spf::Spf;
-use samotop::mail::{Builder, DebugService, MailDir, Name};
+use samotop::mail::{Builder, CertRelay, DebugService, LocalDomains, MailDir, Name};
 use samotop::server::TcpServer;
 use samotop::smtp::{Esmtp, EsmtpStartTls, Prudence, SmtpParser};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
@@ -146,7 +157,14 @@ async fn main_fut() -> Result<()> {
         + MailDir::new(setup.mail_dir())?;
 
     if let Some(cfg) = setup.tls_config().await? {
-        service += EsmtpStartTls.with(SmtpParser, RustlsProvider::from(TlsAcceptor::from(cfg)));
+        service += EsmtpStartTls.with(
+            SmtpParser,
+            RustlsProvider::from(TlsAcceptor::from(Arc::new(cfg))),
+        );
+    }
+
+    if let Some(relay) = setup.cert_relay() {
+        service += relay;
     }
 
     TcpServer::on_all(setup.ports())
@@ -221,11 +239,41 @@ impl Setup {
             certs
         };
 
-        let mut config = ServerConfig::new(rustls::NoClientAuth::new());
+        let mut config = match opt.client_ca_file {
+            None => ServerConfig::new(rustls::NoClientAuth::new()),
+            Some(ref ca_file) => {
+                let ca_path = self.absolute_path(ca_file);
+                let mut cafile = File::open(&ca_path)
+                    .await
+                    .map_err(|e| format!("Could not load client CA bundle: {}", e))?;
+                let mut cabuf = vec![];
+                let _ = cafile.read_to_end(&mut cabuf).await?;
+                let mut cabuf = std::io::BufReader::new(&cabuf[..]);
+                client_auth_config(&mut cabuf, false)
+                    .map_err(|e| format!("Could not load client CA from {:?}: {}", ca_path, e))?
+            }
+        };
         config.set_single_cert(certs, key)?;
         Ok(Some(config))
     }
 
+    /// Relaying restricted to clients with allowed certificates, if any are configured
+    pub fn cert_relay(&self) -> Option<CertRelay> {
+        if self.opt.relay_certs.is_empty() {
+            return None;
+        }
+        let local_domains: LocalDomains = if self.opt.local_domains.is_empty() {
+            vec![self.name()].into_iter().collect()
+        } else {
+            self.opt.local_domains.iter().collect()
+        };
+        let mut relay = CertRelay::default().with_local_domains(local_domains);
+        for fingerprint in self.opt.relay_certs.iter() {
+            relay = relay.allow_fingerprint(fingerprint);
+        }
+        Some(relay)
+    }
+
     /// Get all TCP ports to serve the service on
     pub fn ports(&self) -> Vec<String> {
         if self.opt.ports.is_empty() {
@@ -337,4 +385,23 @@ struct Opt {
     /// Timeout is in miliseconds.
     #[arg(long = "command_timeout", name = "timeout")]
     prudent_command_timeout: Option<u64>,
+
+    /// Request TLS client certificates and verify them against this CA bundle (PEM).
+    /// If a relative path is given, it will be relative to base-dir.
+    #[arg(long = "client-ca-file", name = "client CA file path")]
+    client_ca_file: Option<String>,
+
+    /// Allow relaying to non-local domains for clients with this certificate SHA-256 fingerprint.
+    /// The option can be set multiple times. Requires --client-ca-file.
+    #[arg(
+        long = "relay-cert",
+        name = "fingerprint",
+        requires = "client CA file path"
+    )]
+    relay_certs: Vec<String>,
+
+    /// Domains considered local when relaying is restricted with --relay-cert.
+    /// The option can be set multiple times. If none is given, the service name is used.
+    #[arg(long = "local-domain", name = "domain")]
+    local_domains: Vec<String>,
 }
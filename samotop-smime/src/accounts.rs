@@ -1,3 +1,4 @@
+use crate::{contacts::encode, Contacts};
 use log::error;
 use samotop_core::{
     common::{ready, S2Fut},
@@ -9,6 +10,9 @@ use samotop_core::{
 };
 use std::path::PathBuf;
 
+/// Looks up the certificate of the recipient in `<accounts_dir>/<address>/certificate`.
+///
+/// Set up `SubAddressing` before it so that `user+tag@example.org` finds the `user@example.org` account.
 #[derive(Debug, Clone)]
 pub struct Accounts {
     accounts_dir: PathBuf,
@@ -18,6 +22,10 @@ impl Accounts {
     pub fn new(accounts_dir: PathBuf) -> Self {
         Self { accounts_dir }
     }
+    /// Contact approval for these accounts
+    pub fn contacts(&self) -> Contacts {
+        Contacts::new(self.accounts_dir.clone())
+    }
 }
 
 impl<T: AcceptsGuard> MailSetup<T> for Accounts {
@@ -41,7 +49,7 @@ impl MailGuard for Accounts {
         }
         let mut path = async_std::path::PathBuf::from(&self.accounts_dir);
         // TODO: hash the value for privacy
-        path.push(rcpt.address.address().to_lowercase());
+        path.push(encode(rcpt.address.address().to_lowercase().as_str()));
         path.push("certificate");
 
         Box::pin(async move {
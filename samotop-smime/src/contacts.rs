@@ -0,0 +1,225 @@
+use async_std::{fs, path::PathBuf};
+use log::{error, info};
+use samotop_core::{
+    common::{ready, S2Fut},
+    mail::{
+        AcceptsGuard, AddRecipientFailure, AddRecipientResult, MailGuard, MailSetup, Recipient,
+        StartMailResult,
+    },
+    smtp::SmtpSession,
+};
+use std::io;
+
+/// Contact approval for the accounts, as described in `concepts/contacts.plantuml`.
+///
+/// Senders new to an account are held as pending and refused with 450 so that they retry later.
+/// Rejected senders are refused with 550 and approved senders are accepted.
+/// Recipients without an account directory and null senders (bounces) are not checked.
+///
+/// The contacts are stored in the account directory, one file per sender:
+/// `<accounts_dir>/<account>/contacts/<pending|approved|rejected>/<sender>`.
+/// The UI drives the approval with `list()`, `approve()` and `reject()`.
+#[derive(Debug, Clone)]
+pub struct Contacts {
+    accounts_dir: PathBuf,
+}
+
+/// The approval status of a contact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactStatus {
+    /// Waiting for the account owner to decide
+    Pending,
+    /// The account owner accepts mail from the contact
+    Approved,
+    /// The account owner refuses mail from the contact
+    Rejected,
+}
+
+impl ContactStatus {
+    fn folder(self) -> &'static str {
+        match self {
+            ContactStatus::Pending => "pending",
+            ContactStatus::Approved => "approved",
+            ContactStatus::Rejected => "rejected",
+        }
+    }
+    const ALL: [ContactStatus; 3] = [
+        ContactStatus::Approved,
+        ContactStatus::Rejected,
+        ContactStatus::Pending,
+    ];
+}
+
+impl Contacts {
+    pub fn new(accounts_dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            accounts_dir: accounts_dir.into().into(),
+        }
+    }
+    /// Current status of the contact, `None` if the account never heard of it
+    pub async fn status(&self, account: &str, contact: &str) -> Option<ContactStatus> {
+        for status in ContactStatus::ALL.iter() {
+            if self.path(account, *status, contact).exists().await {
+                return Some(*status);
+            }
+        }
+        None
+    }
+    /// Current status of the contact, new contacts are added as pending
+    pub async fn check(&self, account: &str, contact: &str) -> io::Result<ContactStatus> {
+        match self.status(account, contact).await {
+            Some(status) => Ok(status),
+            None => {
+                self.set(account, contact, ContactStatus::Pending).await?;
+                info!("New contact {} pending for {}", contact, account);
+                Ok(ContactStatus::Pending)
+            }
+        }
+    }
+    /// List contacts of the account with the given status
+    pub async fn list(&self, account: &str, status: ContactStatus) -> io::Result<Vec<String>> {
+        let mut dir = self.account_dir(account);
+        dir.push("contacts");
+        dir.push(status.folder());
+        let mut contacts = vec![];
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(contacts),
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = async_std::stream::StreamExt::next(&mut entries).await {
+            if let Some(contact) = entry?.file_name().to_str().and_then(decode) {
+                contacts.push(contact);
+            }
+        }
+        contacts.sort();
+        Ok(contacts)
+    }
+    /// Accept mail from the contact
+    pub async fn approve(&self, account: &str, contact: &str) -> io::Result<()> {
+        self.set(account, contact, ContactStatus::Approved).await
+    }
+    /// Refuse mail from the contact
+    pub async fn reject(&self, account: &str, contact: &str) -> io::Result<()> {
+        self.set(account, contact, ContactStatus::Rejected).await
+    }
+    async fn set(&self, account: &str, contact: &str, status: ContactStatus) -> io::Result<()> {
+        let path = self.path(account, status, contact);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(&path, contact.to_lowercase()).await?;
+        for other in ContactStatus::ALL.iter().filter(|s| **s != status) {
+            match fs::remove_file(self.path(account, *other, contact)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    fn account_dir(&self, account: &str) -> PathBuf {
+        let mut path = self.accounts_dir.clone();
+        // TODO: hash the value for privacy
+        path.push(encode(account.to_lowercase().as_str()));
+        path
+    }
+    fn path(&self, account: &str, status: ContactStatus, contact: &str) -> PathBuf {
+        let mut path = self.account_dir(account);
+        path.push("contacts");
+        path.push(status.folder());
+        path.push(encode(contact.to_lowercase().as_str()));
+        path
+    }
+}
+
+/// Make the address safe for a file name - no path separators and no leading dot
+pub(crate) fn encode(address: &str) -> String {
+    let mut name = String::with_capacity(address.len());
+    for (i, b) in address.bytes().enumerate() {
+        match b {
+            b'.' if i == 0 => name.push_str("%2E"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'@' | b'.' | b'_' | b'+' | b'-' => {
+                name.push(b as char)
+            }
+            b => name.push_str(format!("%{:02X}", b).as_str()),
+        }
+    }
+    name
+}
+
+fn decode(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut input = name.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for Contacts {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for Contacts {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let sender = session
+            .transaction
+            .mail
+            .as_ref()
+            .map(|mail| mail.sender().address())
+            .unwrap_or_default();
+        Box::pin(async move {
+            if sender.is_empty() {
+                // bounces have no contact to approve
+                return AddRecipientResult::Inconclusive(rcpt);
+            }
+            let account = rcpt.address.address();
+            if !self.account_dir(account.as_str()).is_dir().await {
+                return AddRecipientResult::Inconclusive(rcpt);
+            }
+            match self.check(account.as_str(), sender.as_str()).await {
+                Ok(ContactStatus::Approved) => AddRecipientResult::Inconclusive(rcpt),
+                Ok(ContactStatus::Pending) => AddRecipientResult::Failed(
+                    AddRecipientFailure::RejectedTemporarily,
+                    format!("Contact {} is pending approval by {}", sender, account),
+                ),
+                Ok(ContactStatus::Rejected) => AddRecipientResult::Failed(
+                    AddRecipientFailure::RejectedPermanently,
+                    format!("Contact {} was rejected by {}", sender, account),
+                ),
+                Err(e) => {
+                    error!("Contact check of {} for {} failed: {}", sender, account, e);
+                    AddRecipientResult::Failed(
+                        AddRecipientFailure::FailedTemporarily,
+                        "Not ready".to_owned(),
+                    )
+                }
+            }
+        })
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
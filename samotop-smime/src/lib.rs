@@ -1,7 +1,9 @@
 mod accounts;
+mod contacts;
 mod smime;
 mod smimemail;
 
 pub use self::accounts::*;
+pub use self::contacts::*;
 pub use self::smime::*;
 pub use self::smimemail::*;
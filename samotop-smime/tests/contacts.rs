@@ -0,0 +1,88 @@
+use samotop_core::{
+    common::Result,
+    mail::{AddRecipientFailure, AddRecipientResult, MailGuard, Recipient},
+    smtp::{command::SmtpMail, SmtpHost, SmtpPath, SmtpSession},
+};
+use samotop_smime::{Accounts, ContactStatus};
+
+fn path(name: &str, domain: &str) -> SmtpPath {
+    SmtpPath::Mailbox {
+        name: name.to_owned(),
+        host: SmtpHost::Domain(domain.to_owned()),
+        relays: vec![],
+    }
+}
+
+#[async_std::test]
+async fn new_contact_is_pending_until_approved() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("samotop-contacts-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("me@here.org"))?;
+    let accounts = Accounts::new(dir.clone());
+    let sut = accounts.contacts();
+
+    let mut session = SmtpSession::default();
+    session.transaction.mail = Some(SmtpMail::Mail(path("Her", "there.org"), vec![]));
+
+    let res = sut
+        .add_recipient(&mut session, Recipient::new(path("me", "here.org")))
+        .await;
+    assert!(matches!(
+        res,
+        AddRecipientResult::Failed(AddRecipientFailure::RejectedTemporarily, _)
+    ));
+    assert_eq!(
+        sut.list("me@here.org", ContactStatus::Pending).await?,
+        vec!["her@there.org".to_owned()]
+    );
+
+    sut.approve("me@here.org", "her@there.org").await?;
+    let res = sut
+        .add_recipient(&mut session, Recipient::new(path("me", "here.org")))
+        .await;
+    assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    assert!(sut
+        .list("me@here.org", ContactStatus::Pending)
+        .await?
+        .is_empty());
+
+    sut.reject("me@here.org", "her@there.org").await?;
+    let res = sut
+        .add_recipient(&mut session, Recipient::new(path("me", "here.org")))
+        .await;
+    assert!(matches!(
+        res,
+        AddRecipientResult::Failed(AddRecipientFailure::RejectedPermanently, _)
+    ));
+
+    // not an account here
+    let res = sut
+        .add_recipient(&mut session, Recipient::new(path("you", "here.org")))
+        .await;
+    assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn account_cannot_escape_the_accounts_dir() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("samotop-escape-{}", std::process::id()));
+    let name = dir.file_name().and_then(|n| n.to_str()).expect("name");
+    std::fs::create_dir_all(dir.join("accounts"))?;
+    std::fs::create_dir_all(dir.join("me@here.org"))?;
+    let sut = Accounts::new(dir.join("accounts")).contacts();
+
+    let mut session = SmtpSession::default();
+    session.transaction.mail = Some(SmtpMail::Mail(path("her", "there.org"), vec![]));
+
+    // resolves to the `me@here.org` directory if the address is used as a path
+    let escape = path(format!("../../{}/me", name).as_str(), "here.org");
+    let res = sut
+        .add_recipient(&mut session, Recipient::new(escape))
+        .await;
+    assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+    assert!(!dir.join("me@here.org").join("contacts").exists());
+
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
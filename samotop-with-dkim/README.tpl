@@ -0,0 +1,13 @@
+{{badges}}
+
+# {{crate}} {{version}}
+
+{{readme}}
+
+## License
+
+{{license}}
+
+### Contribution
+
+Unless you explicitly state otherwise, any contribution submitted for inclusion in samotop projects by you, as defined in the Apache-2.0 license, shall be licensed as above, without any additional terms or conditions.
\ No newline at end of file
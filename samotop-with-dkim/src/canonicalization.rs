@@ -0,0 +1,230 @@
+/// Header and body canonicalization algorithms (RFC 6376 section 3.4)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    /// Tolerates almost no modification
+    #[default]
+    Simple,
+    /// Tolerates common whitespace and header folding modifications
+    Relaxed,
+}
+
+impl Canonicalization {
+    /// Parse the header/body pair of the `c=` tag, the body defaults to simple
+    pub fn parse_pair(value: &str) -> Option<(Self, Self)> {
+        let (header, body) = match value.split_once('/') {
+            Some((header, body)) => (header, Some(body)),
+            None => (value, None),
+        };
+        let header = Self::parse(header)?;
+        let body = match body {
+            Some(body) => Self::parse(body)?,
+            None => Canonicalization::Simple,
+        };
+        Some((header, body))
+    }
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "simple" => Some(Canonicalization::Simple),
+            "relaxed" => Some(Canonicalization::Relaxed),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Canonicalization::Simple => "simple",
+            Canonicalization::Relaxed => "relaxed",
+        }
+    }
+    /// Canonical form of a raw header field, including the terminating CRLF
+    pub fn header(self, raw: &[u8]) -> Vec<u8> {
+        match self {
+            Canonicalization::Simple => {
+                let mut field = raw.to_vec();
+                if !field.ends_with(b"\r\n") {
+                    if field.ends_with(b"\n") {
+                        field.pop();
+                    }
+                    field.extend_from_slice(b"\r\n");
+                }
+                field
+            }
+            Canonicalization::Relaxed => {
+                let colon = raw.iter().position(|b| *b == b':').unwrap_or(raw.len());
+                let name = trim_wsp(&raw[..colon]).to_ascii_lowercase();
+                let mut value = Vec::with_capacity(raw.len() - colon);
+                if colon < raw.len() {
+                    let unfolded = raw[colon + 1..]
+                        .iter()
+                        .copied()
+                        .filter(|b| *b != b'\r' && *b != b'\n');
+                    compress_wsp(unfolded, &mut value);
+                }
+                let mut field = name;
+                field.push(b':');
+                field.extend_from_slice(trim_wsp(value.as_slice()));
+                field.extend_from_slice(b"\r\n");
+                field
+            }
+        }
+    }
+}
+
+/// Incremental body canonicalization.
+///
+/// Feed the body data as they come, the canonical output is passed on to the given closure.
+/// Trailing empty lines are held back until some content follows.
+#[derive(Debug)]
+pub struct BodyCanonicalizer {
+    method: Canonicalization,
+    line: Vec<u8>,
+    empty_lines: usize,
+    started: bool,
+}
+
+impl BodyCanonicalizer {
+    pub fn new(method: Canonicalization) -> Self {
+        Self {
+            method,
+            line: vec![],
+            empty_lines: 0,
+            started: false,
+        }
+    }
+    /// Canonicalize the next chunk of the body
+    pub fn update(&mut self, mut data: &[u8], out: &mut dyn FnMut(&[u8])) {
+        while let Some(eol) = data.iter().position(|b| *b == b'\n') {
+            self.line.extend_from_slice(&data[..eol]);
+            self.complete_line(out);
+            data = &data[eol + 1..];
+        }
+        self.line.extend_from_slice(data);
+    }
+    /// Canonicalize whatever is left at the end of the body
+    pub fn finish(&mut self, out: &mut dyn FnMut(&[u8])) {
+        if !self.line.is_empty() {
+            self.complete_line(out);
+        }
+        if self.method == Canonicalization::Simple && !self.started {
+            // an empty body is a single CRLF
+            out(b"\r\n");
+            self.started = true;
+        }
+        self.empty_lines = 0;
+    }
+    fn complete_line(&mut self, out: &mut dyn FnMut(&[u8])) {
+        let mut line = std::mem::take(&mut self.line);
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        if self.method == Canonicalization::Relaxed {
+            let mut relaxed = Vec::with_capacity(line.len());
+            compress_wsp(line.iter().copied(), &mut relaxed);
+            while relaxed.ends_with(b" ") {
+                relaxed.pop();
+            }
+            line = relaxed;
+        }
+        if line.is_empty() {
+            self.empty_lines += 1;
+        } else {
+            for _ in 0..self.empty_lines {
+                out(b"\r\n");
+            }
+            self.empty_lines = 0;
+            out(line.as_slice());
+            out(b"\r\n");
+            self.started = true;
+        }
+        // reuse the allocation
+        line.clear();
+        self.line = line;
+    }
+}
+
+fn is_wsp(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+/// Replace runs of whitespace with a single space
+fn compress_wsp(input: impl Iterator<Item = u8>, output: &mut Vec<u8>) {
+    let mut in_wsp = false;
+    for b in input {
+        if is_wsp(b) {
+            if !in_wsp {
+                output.push(b' ');
+            }
+            in_wsp = true;
+        } else {
+            output.push(b);
+            in_wsp = false;
+        }
+    }
+}
+
+fn trim_wsp(mut bytes: &[u8]) -> &[u8] {
+    while let Some((first, rest)) = bytes.split_first() {
+        if !is_wsp(*first) {
+            break;
+        }
+        bytes = rest;
+    }
+    while let Some((last, rest)) = bytes.split_last() {
+        if !is_wsp(*last) {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(method: Canonicalization, chunks: &[&str]) -> String {
+        let mut sut = BodyCanonicalizer::new(method);
+        let mut output = vec![];
+        for chunk in chunks {
+            sut.update(chunk.as_bytes(), &mut |b| output.extend_from_slice(b));
+        }
+        sut.finish(&mut |b| output.extend_from_slice(b));
+        String::from_utf8(output).expect("utf8")
+    }
+
+    #[test]
+    fn rfc_example_headers() {
+        let raw = b"A: X\r\nB : Y\t\r\n\tZ  \r\n";
+        let (a, b) = raw.split_at(6);
+        assert_eq!(Canonicalization::Relaxed.header(a), b"a:X\r\n");
+        assert_eq!(Canonicalization::Relaxed.header(b), b"b:Y Z\r\n");
+        assert_eq!(Canonicalization::Simple.header(b), b"B : Y\t\r\n\tZ  \r\n");
+    }
+
+    #[test]
+    fn rfc_example_body() {
+        let chunks = [" C \r\nD \t E\r", "\n\r\n\r", "\n"];
+        assert_eq!(
+            body(Canonicalization::Relaxed, &chunks[..]),
+            " C\r\nD E\r\n"
+        );
+        assert_eq!(
+            body(Canonicalization::Simple, &chunks[..]),
+            " C \r\nD \t E\r\n"
+        );
+    }
+
+    #[test]
+    fn empty_body() {
+        assert_eq!(body(Canonicalization::Simple, &[]), "\r\n");
+        assert_eq!(body(Canonicalization::Simple, &["\r\n\r\n"]), "\r\n");
+        assert_eq!(body(Canonicalization::Relaxed, &["\r\n \r\n"]), "");
+    }
+
+    #[test]
+    fn missing_final_crlf() {
+        assert_eq!(
+            body(Canonicalization::Simple, &["Hi.\r\n\r\nJoe."]),
+            "Hi.\r\n\r\nJoe.\r\n"
+        );
+    }
+}
@@ -0,0 +1,786 @@
+use crate::signature::parse_tags;
+use crate::{DnsRecords, LookupError, PolicyResolver};
+use ring::rand::{SecureRandom, SystemRandom};
+use samotop_core::{
+    common::*,
+    mail::{
+        AcceptsGuard, AddRecipientResult, AuthResult, CheckBodyFailure, CheckBodyResult, MailGuard,
+        MailSetup, MailSpool, Recipient, StartMailResult,
+    },
+    smtp::{SmtpReply, SmtpSession},
+};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+/// Requested handling of mail failing DMARC - the `p=` and `sp=` tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmarcPolicy {
+    None,
+    Quarantine,
+    Reject,
+}
+
+impl DmarcPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" => Some(DmarcPolicy::None),
+            "quarantine" => Some(DmarcPolicy::Quarantine),
+            "reject" => Some(DmarcPolicy::Reject),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            DmarcPolicy::None => "none",
+            DmarcPolicy::Quarantine => "quarantine",
+            DmarcPolicy::Reject => "reject",
+        }
+    }
+    /// The next milder policy, applied to mail left out by `pct=`
+    fn downgrade(self) -> Self {
+        match self {
+            DmarcPolicy::Reject => DmarcPolicy::Quarantine,
+            _ => DmarcPolicy::None,
+        }
+    }
+}
+
+/// Identifier alignment mode - the `adkim=` and `aspf=` tags
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// The organizational domains must match
+    #[default]
+    Relaxed,
+    /// The domains must match exactly
+    Strict,
+}
+
+impl Alignment {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "r" => Some(Alignment::Relaxed),
+            "s" => Some(Alignment::Strict),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed DMARC policy record (RFC 7489 section 6.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmarcRecord {
+    /// p= policy for the domain
+    pub policy: DmarcPolicy,
+    /// sp= policy for the subdomains, p= applies if missing
+    pub subdomain_policy: Option<DmarcPolicy>,
+    /// pct= percentage of failing mail to apply the policy to
+    pub percent: u8,
+    /// adkim= DKIM alignment mode
+    pub dkim_alignment: Alignment,
+    /// aspf= SPF alignment mode
+    pub spf_alignment: Alignment,
+    /// rua= where to send aggregate reports
+    pub aggregate_reports: Vec<String>,
+    /// ruf= where to send failure reports
+    pub failure_reports: Vec<String>,
+    /// ri= requested aggregate report interval in seconds
+    pub report_interval: u32,
+}
+
+impl DmarcRecord {
+    /// Parse the TXT record of the policy
+    pub fn parse(record: &str) -> std::result::Result<Self, String> {
+        let tags = parse_tags(record)?;
+        match tags.first() {
+            Some((v, version)) if v == "v" && version == "DMARC1" => {}
+            _ => return Err("not a DMARC1 record".to_owned()),
+        }
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let uris = |name: &str| -> Vec<String> {
+            tag(name)
+                .map(|uris| {
+                    uris.split(',')
+                        .filter(|uri| !uri.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let aggregate_reports = uris("rua");
+        let policy = match tag("p").and_then(DmarcPolicy::parse) {
+            Some(policy) => policy,
+            // a record with reporting URIs is valid as a monitoring policy
+            None if !aggregate_reports.is_empty() => DmarcPolicy::None,
+            None => return Err(format!("invalid p= tag {:?}", tag("p").unwrap_or(""))),
+        };
+        Ok(DmarcRecord {
+            policy,
+            subdomain_policy: tag("sp").and_then(DmarcPolicy::parse),
+            percent: tag("pct")
+                .and_then(|pct| pct.parse::<u8>().ok())
+                .filter(|pct| *pct <= 100)
+                .unwrap_or(100),
+            dkim_alignment: tag("adkim").and_then(Alignment::parse).unwrap_or_default(),
+            spf_alignment: tag("aspf").and_then(Alignment::parse).unwrap_or_default(),
+            aggregate_reports,
+            failure_reports: uris("ruf"),
+            report_interval: tag("ri").and_then(|ri| ri.parse().ok()).unwrap_or(86400),
+        })
+    }
+}
+
+/// The overall DMARC status of a mail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmarcStatus {
+    /// There is no policy to evaluate
+    None,
+    /// Aligned SPF or DKIM pass
+    Pass,
+    /// Neither SPF nor DKIM pass aligned with the From domain
+    Fail,
+    /// The policy could not be fetched
+    TempError,
+    /// The From header is not usable
+    PermError,
+}
+
+impl DmarcStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            DmarcStatus::None => "none",
+            DmarcStatus::Pass => "pass",
+            DmarcStatus::Fail => "fail",
+            DmarcStatus::TempError => "temperror",
+            DmarcStatus::PermError => "permerror",
+        }
+    }
+}
+
+/// The outcome of the DMARC evaluation of one mail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmarcEvaluation {
+    pub status: DmarcStatus,
+    /// The domain of the From header
+    pub header_from: Option<String>,
+    /// The domain where the policy was found
+    pub policy_domain: Option<String>,
+    /// The policy found
+    pub record: Option<DmarcRecord>,
+    /// Did a DKIM signature aligned with the From domain pass?
+    pub dkim_aligned: bool,
+    /// Did SPF pass for a sender domain aligned with the From domain?
+    pub spf_aligned: bool,
+    /// The policy actually applied
+    pub disposition: DmarcPolicy,
+    pub reason: Option<String>,
+}
+
+impl DmarcEvaluation {
+    fn new(status: DmarcStatus, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            header_from: None,
+            policy_domain: None,
+            record: None,
+            dkim_aligned: false,
+            spf_aligned: false,
+            disposition: DmarcPolicy::None,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+impl From<&DmarcEvaluation> for AuthResult {
+    fn from(evaluation: &DmarcEvaluation) -> Self {
+        let mut result = AuthResult::new("dmarc", evaluation.status.name());
+        if let Some(ref reason) = evaluation.reason {
+            result = result.with_reason(reason.as_str());
+        }
+        if let Some(ref domain) = evaluation.header_from {
+            result = result.with_property("header.from", domain.as_str());
+        }
+        if evaluation.record.is_some() {
+            result = result.with_property("policy.dmarc", evaluation.disposition.name());
+        }
+        result
+    }
+}
+
+/// Collects the evaluation data, for instance to send aggregate reports.
+///
+/// It is called for every mail evaluated against a published policy.
+pub trait DmarcReporter: fmt::Debug + Send + Sync {
+    fn report(&self, session: &SmtpSession, evaluation: &DmarcEvaluation);
+}
+
+/// One row of an aggregate report (RFC 7489 appendix C)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmarcReportRow {
+    pub policy_domain: String,
+    pub source_ip: String,
+    pub header_from: String,
+    pub disposition: DmarcPolicy,
+    pub dkim_aligned: bool,
+    pub spf_aligned: bool,
+    pub count: usize,
+}
+
+/// Aggregates the evaluations in memory for the aggregate reports.
+/// Clones share the collected data.
+#[derive(Debug, Clone, Default)]
+pub struct DmarcAggregate {
+    rows: Arc<Mutex<Vec<DmarcReportRow>>>,
+}
+
+impl DmarcAggregate {
+    /// Take the rows collected so far and start over
+    pub fn take(&self) -> Vec<DmarcReportRow> {
+        std::mem::take(&mut *self.rows.lock().expect("DMARC lock"))
+    }
+}
+
+impl DmarcReporter for DmarcAggregate {
+    fn report(&self, session: &SmtpSession, evaluation: &DmarcEvaluation) {
+        let (policy_domain, header_from) =
+            match (&evaluation.policy_domain, &evaluation.header_from) {
+                (Some(policy_domain), Some(header_from)) => (policy_domain, header_from),
+                _ => return,
+            };
+        let peer = session.connection.peer_addr.as_str();
+        let source_ip = match peer.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => peer.to_owned(),
+        };
+        let mut rows = self.rows.lock().expect("DMARC lock");
+        let existing = rows.iter_mut().find(|row| {
+            row.policy_domain == *policy_domain
+                && row.source_ip == source_ip
+                && row.header_from == *header_from
+                && row.disposition == evaluation.disposition
+                && row.dkim_aligned == evaluation.dkim_aligned
+                && row.spf_aligned == evaluation.spf_aligned
+        });
+        match existing {
+            Some(row) => row.count += 1,
+            None => rows.push(DmarcReportRow {
+                policy_domain: policy_domain.clone(),
+                source_ip,
+                header_from: header_from.clone(),
+                disposition: evaluation.disposition,
+                dkim_aligned: evaluation.dkim_aligned,
+                spf_aligned: evaluation.spf_aligned,
+                count: 1,
+            }),
+        }
+    }
+}
+
+/// Evaluates the DMARC policy of the From header domain (RFC 7489).
+///
+/// It builds on the SPF and DKIM results in `session.transaction.auth_results`
+/// so it has to be set up after `Spf` and `Dkim`. Mail failing the policy is rejected.
+/// Mail to quarantine is delivered with a `dmarc-quarantine <domain>` tag in
+/// `session.transaction.tags` and `policy.dmarc=quarantine` in the result
+/// recorded in `session.transaction.auth_results`, so that the delivery can file it as spam.
+/// The mail is held back in a spool to read the From header, larger mail than the limit is refused.
+///
+/// Relaxed alignment needs the organizational domain from the public suffix list,
+/// supply it with `with_organizational_domain()`. Until then, relaxed alignment
+/// is evaluated as strict so that tenants of a shared suffix cannot pass for each other.
+#[derive(Clone, Debug)]
+pub struct Dmarc {
+    resolver: Arc<dyn PolicyResolver>,
+    reporter: Option<Arc<dyn DmarcReporter>>,
+    organizational_domain: Option<fn(&str) -> String>,
+    spool_limit: usize,
+}
+
+impl Default for Dmarc {
+    fn default() -> Self {
+        Self {
+            resolver: Arc::new(DnsRecords::default()),
+            reporter: None,
+            organizational_domain: None,
+            spool_limit: Self::DEFAULT_SPOOL_LIMIT,
+        }
+    }
+}
+
+impl Dmarc {
+    /// Hold back at most 50 MiB of mail data by default, as `Dkim` does
+    pub const DEFAULT_SPOOL_LIMIT: usize = crate::Dkim::DEFAULT_SPOOL_LIMIT;
+    /// Fetch the policies with the given resolver rather than from the DNS
+    pub fn with_resolver(mut self, resolver: impl PolicyResolver + 'static) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+    /// Pass the evaluations to the reporter
+    pub fn with_reporter(mut self, reporter: impl DmarcReporter + 'static) -> Self {
+        self.reporter = Some(Arc::new(reporter));
+        self
+    }
+    /// Find the organizational domain with the given function backed by the public suffix list.
+    /// It enables relaxed alignment and is used to discover the organizational policy.
+    pub fn with_organizational_domain(mut self, find: fn(&str) -> String) -> Self {
+        self.organizational_domain = Some(find);
+        self
+    }
+    /// Hold back at most `limit` bytes of mail data, larger mail is refused
+    pub fn with_spool_limit(mut self, limit: usize) -> Self {
+        self.spool_limit = limit;
+        self
+    }
+
+    async fn evaluate(&self, session: &SmtpSession) -> DmarcEvaluation {
+        let from = match header_from(session.transaction.spool.as_ref()) {
+            Ok(Some(from)) => from,
+            Ok(None) => return DmarcEvaluation::new(DmarcStatus::None, "no From domain"),
+            Err(e) => return DmarcEvaluation::new(DmarcStatus::PermError, e),
+        };
+        // the approximation is good enough to discover the policy, but not to align
+        let organization =
+            (self.organizational_domain.unwrap_or(organizational_domain))(from.as_str());
+        let mut evaluation = match self.discover(from.as_str(), organization.as_str()).await {
+            Ok(Some((domain, record))) => DmarcEvaluation {
+                policy_domain: Some(domain),
+                record: Some(record),
+                reason: None,
+                ..DmarcEvaluation::new(DmarcStatus::Fail, "")
+            },
+            Ok(None) => DmarcEvaluation::new(DmarcStatus::None, "no policy"),
+            Err(e) => DmarcEvaluation::new(DmarcStatus::TempError, e),
+        };
+        evaluation.header_from = Some(from.clone());
+        let record = match evaluation.record {
+            Some(ref record) => record,
+            None => return evaluation,
+        };
+
+        let aligned = |domain: &str, mode: Alignment| {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            match mode {
+                Alignment::Strict => domain == from,
+                Alignment::Relaxed => match self.organizational_domain {
+                    Some(find) => find(domain.as_str()) == organization,
+                    None => domain == from,
+                },
+            }
+        };
+        let passed = |method: &str, property: &str, mode: Alignment| {
+            session
+                .transaction
+                .auth_results
+                .to_vec()
+                .iter()
+                .any(|result| {
+                    result.is_method(method)
+                        && result.is_pass()
+                        && result
+                            .property(property)
+                            .map(|domain| aligned(domain, mode))
+                            .unwrap_or_default()
+                })
+        };
+        let dkim_aligned = passed("dkim", "header.d", record.dkim_alignment);
+        let spf_aligned = passed("spf", "smtp.mailfrom", record.spf_alignment);
+
+        let (status, disposition, reason) = if dkim_aligned || spf_aligned {
+            (DmarcStatus::Pass, DmarcPolicy::None, None)
+        } else {
+            let policy = match evaluation.policy_domain.as_deref() == Some(from.as_str()) {
+                true => record.policy,
+                false => record.subdomain_policy.unwrap_or(record.policy),
+            };
+            let disposition = match sampled(record.percent) {
+                true => policy,
+                false => policy.downgrade(),
+            };
+            let reason = "no aligned SPF or DKIM pass".to_owned();
+            (DmarcStatus::Fail, disposition, Some(reason))
+        };
+        evaluation.dkim_aligned = dkim_aligned;
+        evaluation.spf_aligned = spf_aligned;
+        evaluation.status = status;
+        evaluation.disposition = disposition;
+        evaluation.reason = reason;
+        evaluation
+    }
+
+    /// Find the policy of the From domain or of its organizational domain
+    async fn discover(
+        &self,
+        from: &str,
+        organization: &str,
+    ) -> std::result::Result<Option<(String, DmarcRecord)>, String> {
+        if let Some(record) = self.lookup(from).await? {
+            return Ok(Some((from.to_owned(), record)));
+        }
+        if organization != from {
+            if let Some(record) = self.lookup(organization).await? {
+                return Ok(Some((organization.to_owned(), record)));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn lookup(&self, domain: &str) -> std::result::Result<Option<DmarcRecord>, String> {
+        let records = match self.resolver.lookup_policy(domain).await {
+            Ok(records) => records,
+            Err(LookupError::NotFound) => return Ok(None),
+            Err(LookupError::Failed(e)) => return Err(e),
+        };
+        let mut records: Vec<DmarcRecord> = records
+            .iter()
+            .filter_map(|record| match DmarcRecord::parse(record) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    debug!("Ignoring DMARC record of {}: {}", domain, e);
+                    None
+                }
+            })
+            .collect();
+        // more than one policy means no policy
+        match records.len() {
+            1 => Ok(records.pop()),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for Dmarc {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for Dmarc {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        // we need the From header
+        if session.transaction.spool.is_none() {
+            session.transaction.spool = Some(MailSpool::with_limit(self.spool_limit));
+        }
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+
+    fn check_body<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let evaluation = self.evaluate(session).await;
+            debug!(
+                "DMARC result for {}: {:?}",
+                session.transaction.id, evaluation
+            );
+            session
+                .transaction
+                .auth_results
+                .push(AuthResult::from(&evaluation));
+            if let Some(ref reporter) = self.reporter {
+                if evaluation.record.is_some() {
+                    reporter.report(session, &evaluation);
+                }
+            }
+            let domain = evaluation.header_from.unwrap_or_default();
+            match evaluation.disposition {
+                DmarcPolicy::None => CheckBodyResult::Accepted,
+                DmarcPolicy::Quarantine => {
+                    // delivered for the recipient to find among spam
+                    session
+                        .transaction
+                        .tags
+                        .push(format!("dmarc-quarantine {}", domain));
+                    CheckBodyResult::Accepted
+                }
+                DmarcPolicy::Reject => CheckBodyResult::Failed(
+                    CheckBodyFailure::Custom(SmtpReply::Custom(
+                        550,
+                        format!("5.7.1 Mail rejected per DMARC policy of {}", domain),
+                    )),
+                    format!("DMARC policy of {} rejects the mail", domain),
+                ),
+            }
+        })
+    }
+}
+
+/// The domain of the From header, none if there is no From domain
+fn header_from(spool: Option<&MailSpool>) -> std::result::Result<Option<String>, String> {
+    let spool = match spool {
+        Some(spool) => spool,
+        None => return Ok(None),
+    };
+    let mut headers = spool.header("from");
+    let header = match headers.len() {
+        0 => return Ok(None),
+        1 => headers.pop().unwrap_or_default(),
+        _ => return Err("multiple From headers".to_owned()),
+    };
+    let mut domains = address_domains(header.as_str());
+    domains.dedup();
+    match domains.len() {
+        0 => Ok(None),
+        1 => Ok(domains.pop()),
+        _ => Err("multiple From domains".to_owned()),
+    }
+}
+
+/// Domains of the addresses in an address list header value
+fn address_domains(value: &str) -> Vec<String> {
+    // drop comments and split the addresses outside of quotes
+    let mut addresses = vec![String::new()];
+    let mut comment = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if comment == 0 => quoted = !quoted,
+            '(' if !quoted => comment += 1,
+            ')' if !quoted && comment > 0 => comment -= 1,
+            ',' if !quoted && comment == 0 => addresses.push(String::new()),
+            _ if quoted || comment > 0 => {}
+            c => addresses.last_mut().expect("address").push(c),
+        }
+    }
+    addresses
+        .iter()
+        .filter_map(|address| {
+            let address = match address.rsplit_once('<') {
+                Some((_, address)) => address.split('>').next().unwrap_or_default(),
+                None => address.as_str(),
+            };
+            let (_, domain) = address.rsplit_once('@')?;
+            let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+            match domain.is_empty() {
+                true => None,
+                false => Some(domain),
+            }
+        })
+        .collect()
+}
+
+/// Approximates the organizational domain without the public suffix list.
+///
+/// It is the registered domain under a top level domain, or under a second level domain
+/// commonly used for registrations in country code domains, such as `example.co.uk`.
+/// It does not know private suffixes such as `github.io`, so `Dmarc` only uses it
+/// to discover the policy and never for relaxed alignment.
+pub fn organizational_domain(domain: &str) -> String {
+    const SECOND_LEVEL: &[&str] = &["ac", "co", "com", "edu", "gov", "net", "org", "ne", "or"];
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let labels: Vec<&str> = domain.split('.').collect();
+    let keep = match labels.as_slice() {
+        [.., second, top] if top.len() == 2 && SECOND_LEVEL.contains(second) => 3,
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(keep)..].join(".")
+}
+
+/// Should the policy apply to this mail given the `pct=` percentage?
+fn sampled(percent: u8) -> bool {
+    if percent >= 100 {
+        return true;
+    }
+    let mut random = [0u8; 4];
+    match SystemRandom::new().fill(&mut random) {
+        Ok(()) => u32::from_be_bytes(random) % 100 < percent as u32,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StaticRecords;
+
+    fn session(from: &str, results: Vec<AuthResult>) -> SmtpSession {
+        let mut spool = MailSpool::default();
+        spool.append(format!("From: {}\r\nSubject: hi\r\n\r\nHello\r\n", from).as_bytes());
+        let mut session = SmtpSession::default();
+        session.connection.peer_addr = "192.0.2.1:1234".to_owned();
+        session.transaction.spool = Some(spool);
+        session.transaction.auth_results = results.into();
+        session
+    }
+
+    fn dkim_pass(domain: &str) -> AuthResult {
+        AuthResult::new("dkim", "pass").with_property("header.d", domain)
+    }
+
+    fn check(sut: &Dmarc, session: &mut SmtpSession) -> CheckBodyResult {
+        async_std::task::block_on(sut.check_body(session))
+    }
+
+    #[test]
+    fn parse_record() {
+        let sut = DmarcRecord::parse(
+            "v=DMARC1; p=quarantine; sp=reject; pct=20; adkim=s; rua=mailto:a@b.c,mailto:d@e.f",
+        )
+        .expect("valid");
+        assert_eq!(sut.policy, DmarcPolicy::Quarantine);
+        assert_eq!(sut.subdomain_policy, Some(DmarcPolicy::Reject));
+        assert_eq!(sut.percent, 20);
+        assert_eq!(sut.dkim_alignment, Alignment::Strict);
+        assert_eq!(sut.spf_alignment, Alignment::Relaxed);
+        assert_eq!(sut.aggregate_reports, vec!["mailto:a@b.c", "mailto:d@e.f"]);
+        assert!(DmarcRecord::parse("p=reject; v=DMARC1").is_err());
+        assert!(DmarcRecord::parse("v=DMARC1; p=bogus").is_err());
+        assert_eq!(
+            DmarcRecord::parse("v=DMARC1; p=bogus; rua=mailto:a@b.c").map(|r| r.policy),
+            Ok(DmarcPolicy::None)
+        );
+    }
+
+    #[test]
+    fn find_from_domain() {
+        assert_eq!(
+            address_domains("\"Joe, (the) Boss\" <joe@Mail.Example.com> (a@b.c)"),
+            vec!["mail.example.com"]
+        );
+        assert_eq!(
+            address_domains("joe@a.example, suzie@b.example"),
+            vec!["a.example", "b.example"]
+        );
+        assert_eq!(organizational_domain("mail.example.com"), "example.com");
+        assert_eq!(
+            organizational_domain("mail.example.co.uk."),
+            "example.co.uk"
+        );
+    }
+
+    #[test]
+    fn pass_relaxed_dkim_alignment() {
+        let sut = Dmarc::default()
+            .with_resolver(
+                StaticRecords::default().with_policy("example.com", "v=DMARC1; p=reject"),
+            )
+            .with_organizational_domain(organizational_domain);
+        let mut session = session("Joe <joe@example.com>", vec![dkim_pass("mail.example.com")]);
+
+        assert_eq!(check(&sut, &mut session), CheckBodyResult::Accepted);
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[1].to_string(),
+            "dmarc=pass header.from=example.com policy.dmarc=none"
+        );
+    }
+
+    #[test]
+    fn relaxed_alignment_is_strict_without_organizational_domain() {
+        let sut = Dmarc::default().with_resolver(
+            StaticRecords::default().with_policy("victim.github.io", "v=DMARC1; p=reject"),
+        );
+        let mut forged = session(
+            "joe@victim.github.io",
+            vec![dkim_pass("attacker.github.io")],
+        );
+        assert!(matches!(
+            check(&sut, &mut forged),
+            CheckBodyResult::Failed(CheckBodyFailure::Custom(_), _)
+        ));
+
+        let mut genuine = session("joe@victim.github.io", vec![dkim_pass("victim.github.io")]);
+        assert_eq!(check(&sut, &mut genuine), CheckBodyResult::Accepted);
+    }
+
+    #[test]
+    fn reject_unaligned_mail() {
+        let sut = Dmarc::default().with_resolver(
+            StaticRecords::default().with_policy("example.com", "v=DMARC1; p=reject; aspf=s"),
+        );
+        let spf = AuthResult::new("spf", "pass").with_property("smtp.mailfrom", "mail.example.com");
+        let mut session = session("joe@example.com", vec![dkim_pass("example.net"), spf]);
+
+        match check(&sut, &mut session) {
+            CheckBodyResult::Failed(CheckBodyFailure::Custom(reply), _) => {
+                assert_eq!(reply.code(), 550)
+            }
+            otherwise => panic!("Expected rejection, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn apply_organizational_subdomain_policy() {
+        let aggregate = DmarcAggregate::default();
+        let sut = Dmarc::default()
+            .with_resolver(
+                StaticRecords::default()
+                    .with_policy("example.com", "v=DMARC1; p=reject; sp=quarantine"),
+            )
+            .with_reporter(aggregate.clone());
+        let mut session = session("joe@mail.example.com", vec![]);
+
+        assert_eq!(check(&sut, &mut session), CheckBodyResult::Accepted);
+        assert_eq!(
+            session.transaction.tags,
+            vec!["dmarc-quarantine mail.example.com".to_owned()]
+        );
+        assert_eq!(
+            aggregate.take(),
+            vec![DmarcReportRow {
+                policy_domain: "example.com".to_owned(),
+                source_ip: "192.0.2.1".to_owned(),
+                header_from: "mail.example.com".to_owned(),
+                disposition: DmarcPolicy::Quarantine,
+                dkim_aligned: false,
+                spf_aligned: false,
+                count: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn downgrade_mail_left_out_by_percentage() {
+        let sut = Dmarc::default().with_resolver(
+            StaticRecords::default().with_policy("example.com", "v=DMARC1; p=reject; pct=0"),
+        );
+        let mut session = session("joe@example.com", vec![]);
+
+        assert_eq!(check(&sut, &mut session), CheckBodyResult::Accepted);
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "dmarc=fail reason=\"no aligned SPF or DKIM pass\" header.from=example.com policy.dmarc=quarantine"
+        );
+        assert_eq!(
+            session.transaction.tags,
+            vec!["dmarc-quarantine example.com".to_owned()]
+        );
+    }
+
+    #[test]
+    fn accept_mail_without_policy() {
+        let sut = Dmarc::default().with_resolver(StaticRecords::default());
+        let mut session = session("joe@example.com", vec![]);
+
+        assert_eq!(check(&sut, &mut session), CheckBodyResult::Accepted);
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "dmarc=none reason=\"no policy\" header.from=example.com"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use crate::signature::parse_tags;
+use crate::DkimAlgorithm;
+use ring::signature::{UnparsedPublicKey, ED25519, RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY};
+use samotop_core::common::{fmt, ready, Arc, S2Fut};
+use samotop_core::dns::{default_resolver, DnsError, DnsResolver};
+use std::collections::HashMap;
+
+/// Key types of the `k=` tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DkimKeyType {
+    Rsa,
+    Ed25519,
+}
+
+/// A DKIM public key from the key record (RFC 6376 section 3.6.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DkimKey {
+    /// k= the key type
+    pub key_type: DkimKeyType,
+    /// p= the public key data, RSA keys are kept as PKCS#1 RSAPublicKey
+    pub data: Vec<u8>,
+    /// t=y the domain is testing DKIM
+    pub testing: bool,
+}
+
+impl DkimKey {
+    /// Parse the TXT record of the key
+    pub fn parse(record: &str) -> Result<Self, String> {
+        let tags = parse_tags(record)?;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        if let Some(v) = tag("v") {
+            if v != "DKIM1" {
+                return Err(format!("unsupported key version {}", v));
+            }
+        }
+        if let Some(h) = tag("h") {
+            if !h.split(':').any(|h| h.eq_ignore_ascii_case("sha256")) {
+                return Err(format!("unsupported key hash algorithms {}", h));
+            }
+        }
+        let key_type = match tag("k").map(str::to_ascii_lowercase).as_deref() {
+            None | Some("rsa") => DkimKeyType::Rsa,
+            Some("ed25519") => DkimKeyType::Ed25519,
+            Some(k) => return Err(format!("unsupported key type {}", k)),
+        };
+        let data = match tag("p") {
+            None => return Err("missing p= tag".to_owned()),
+            Some("") => return Err("key revoked".to_owned()),
+            Some(p) => base64::decode(p).map_err(|e| format!("invalid p= tag: {}", e))?,
+        };
+        let data = match key_type {
+            DkimKeyType::Rsa => rsa_public_key(data),
+            DkimKeyType::Ed25519 => data,
+        };
+        let testing = tag("t")
+            .map(|t| t.split(':').any(|t| t.eq_ignore_ascii_case("y")))
+            .unwrap_or_default();
+        Ok(DkimKey {
+            key_type,
+            data,
+            testing,
+        })
+    }
+    /// Verify the signature of the given header hash input
+    pub fn verify(&self, algorithm: DkimAlgorithm, message: &[u8], signature: &[u8]) -> bool {
+        match (algorithm, self.key_type) {
+            (DkimAlgorithm::RsaSha256, DkimKeyType::Rsa) => {
+                UnparsedPublicKey::new(&RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, &self.data)
+                    .verify(message, signature)
+                    .is_ok()
+            }
+            (DkimAlgorithm::Ed25519Sha256, DkimKeyType::Ed25519) => {
+                // Ed25519 signs the SHA-256 hash of the data (RFC 8463)
+                let hash = ring::digest::digest(&ring::digest::SHA256, message);
+                UnparsedPublicKey::new(&ED25519, &self.data)
+                    .verify(hash.as_ref(), signature)
+                    .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Unwrap the RSAPublicKey from a SubjectPublicKeyInfo,
+/// keys published as bare RSAPublicKey are returned as is.
+fn rsa_public_key(data: Vec<u8>) -> Vec<u8> {
+    fn unwrap_spki(data: &[u8]) -> Option<&[u8]> {
+        let (tag, spki, _) = der_item(data)?;
+        if tag != 0x30 {
+            return None;
+        }
+        let (tag, _algorithm, rest) = der_item(spki)?;
+        if tag != 0x30 {
+            return None;
+        }
+        let (tag, bits, _) = der_item(rest)?;
+        // the bit string starts with the count of unused bits
+        match bits.split_first() {
+            Some((0, key)) if tag == 0x03 => Some(key),
+            _ => None,
+        }
+    }
+    match unwrap_spki(data.as_slice()) {
+        Some(key) => key.to_vec(),
+        None => data,
+    }
+}
+
+/// Split the first DER item into tag, content and the rest
+fn der_item(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (tag, data) = data.split_first()?;
+    let (len, data) = data.split_first()?;
+    let (len, data) = match *len {
+        len if len < 0x80 => (len as usize, data),
+        len => {
+            let bytes = (len & 0x7f) as usize;
+            if bytes == 0 || bytes > 4 || data.len() < bytes {
+                return None;
+            }
+            let (len, data) = data.split_at(bytes);
+            (len.iter().fold(0usize, |l, b| l << 8 | *b as usize), data)
+        }
+    };
+    if data.len() < len {
+        return None;
+    }
+    let (content, rest) = data.split_at(len);
+    Some((*tag, content, rest))
+}
+
+/// Failure to look up a DKIM key or a DMARC policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupError {
+    /// There is no record - permanent failure
+    NotFound,
+    /// The lookup failed - temporary failure
+    Failed(String),
+}
+
+/// Finds the key records for the DKIM verification
+pub trait KeyResolver: fmt::Debug + Send + Sync {
+    /// TXT records of `<selector>._domainkey.<domain>`
+    fn lookup_key<'a, 'f>(
+        &'a self,
+        selector: &str,
+        domain: &str,
+    ) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f;
+}
+
+/// Finds the DMARC policy records
+pub trait PolicyResolver: fmt::Debug + Send + Sync {
+    /// TXT records of `_dmarc.<domain>`
+    fn lookup_policy<'a, 'f>(&'a self, domain: &str) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f;
+}
+
+/// Key and policy records given in memory, mainly for tests
+#[derive(Debug, Clone, Default)]
+pub struct StaticRecords {
+    records: HashMap<String, Vec<String>>,
+}
+
+impl StaticRecords {
+    /// Add a key record for the selector and domain
+    pub fn with_key(self, selector: &str, domain: &str, record: impl Into<String>) -> Self {
+        self.with_record(key_name(selector, domain), record)
+    }
+    /// Add a DMARC policy record for the domain
+    pub fn with_policy(self, domain: &str, record: impl Into<String>) -> Self {
+        self.with_record(policy_name(domain), record)
+    }
+    fn with_record(mut self, name: String, record: impl Into<String>) -> Self {
+        self.records.entry(name).or_default().push(record.into());
+        self
+    }
+    fn lookup(&self, name: String) -> S2Fut<'static, Result<Vec<String>, LookupError>> {
+        let result = match self.records.get(name.as_str()) {
+            Some(records) => Ok(records.clone()),
+            None => Err(LookupError::NotFound),
+        };
+        Box::pin(ready(result))
+    }
+}
+
+impl KeyResolver for StaticRecords {
+    fn lookup_key<'a, 'f>(
+        &'a self,
+        selector: &str,
+        domain: &str,
+    ) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f,
+    {
+        self.lookup(key_name(selector, domain))
+    }
+}
+
+impl PolicyResolver for StaticRecords {
+    fn lookup_policy<'a, 'f>(&'a self, domain: &str) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f,
+    {
+        self.lookup(policy_name(domain))
+    }
+}
+
+/// Key and policy records from the DNS, by default with the shared resolver
+#[derive(Debug, Clone)]
+pub struct DnsRecords {
+    resolver: Arc<dyn DnsResolver>,
+}
+
+impl Default for DnsRecords {
+    fn default() -> Self {
+        Self {
+            resolver: default_resolver(),
+        }
+    }
+}
+
+impl DnsRecords {
+    /// Look the records up with the given resolver
+    pub fn new(resolver: impl DnsResolver + 'static) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+        }
+    }
+    fn lookup<'a, 'f>(&'a self, name: String) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            match self.resolver.lookup_txt(name.as_str()).await {
+                Ok(records) => Ok(records),
+                Err(DnsError::NotFound) => Err(LookupError::NotFound),
+                Err(DnsError::Failed(e)) => Err(LookupError::Failed(e)),
+            }
+        })
+    }
+}
+
+impl KeyResolver for DnsRecords {
+    fn lookup_key<'a, 'f>(
+        &'a self,
+        selector: &str,
+        domain: &str,
+    ) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f,
+    {
+        self.lookup(key_name(selector, domain))
+    }
+}
+
+impl PolicyResolver for DnsRecords {
+    fn lookup_policy<'a, 'f>(&'a self, domain: &str) -> S2Fut<'f, Result<Vec<String>, LookupError>>
+    where
+        'a: 'f,
+    {
+        self.lookup(policy_name(domain))
+    }
+}
+
+fn key_name(selector: &str, domain: &str) -> String {
+    format!(
+        "{}._domainkey.{}",
+        selector.to_ascii_lowercase(),
+        domain.trim_end_matches('.').to_ascii_lowercase()
+    )
+}
+
+fn policy_name(domain: &str) -> String {
+    format!(
+        "_dmarc.{}",
+        domain.trim_end_matches('.').to_ascii_lowercase()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rsa_spki_key() {
+        let sut = DkimKey::parse(concat!(
+            "v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDkHlOQoBTzWRiGs5V6NpP3id",
+            "Y6Wk08a5qhdR6wy5bdOKb2jLQiY/J16JYi0Qvx/byYzCNb3W91y3FutACDfzwQ/BC/e/8uBsCR+yz1Lx",
+            "j+PL6lHvqMKrM3rG4hstT5QjvHO9PzoxZyVYLzBfO2EeC3Ip3G+2kryOTIKT+l/K4w3QIDAQAB"
+        ))
+        .expect("valid");
+        assert_eq!(sut.key_type, DkimKeyType::Rsa);
+        // RSAPublicKey is a sequence of the modulus and the exponent
+        assert_eq!(&sut.data[..3], &[0x30, 0x81, 0x89]);
+    }
+
+    #[test]
+    fn refuse_revoked_key() {
+        assert_eq!(DkimKey::parse("v=DKIM1; p="), Err("key revoked".to_owned()));
+    }
+}
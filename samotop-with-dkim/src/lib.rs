@@ -0,0 +1,239 @@
+/*!
+DKIM (RFC 6376) and DMARC (RFC 7489) support for samotop.
+
+`Dkim` verifies the signatures of incoming mail. The mail data are canonicalized
+and hashed as they stream in, the keys are fetched and the signatures verified after DATA,
+before the mail is dispatched. The results are recorded in `session.transaction.auth_results`.
+
+`Dmarc` then applies the policy of the From header domain based on the DKIM and SPF results.
+Relaxed alignment needs a public suffix list lookup, see `Dmarc::with_organizational_domain()`.
+
+`DkimSigner` signs outgoing mail, see also the DKIM signing transport in samotop-delivery.
+
+```
+use samotop_core::mail::{Builder, NullDispatch};
+use samotop_with_dkim::{Dkim, Dmarc, StaticRecords};
+
+let records = StaticRecords::default()
+    .with_key("sel", "example.com", "v=DKIM1; k=ed25519; p=...")
+    .with_policy("example.com", "v=DMARC1; p=reject");
+let mail_svc = Builder
+    + NullDispatch
+    + Dkim::default().with_resolver(records.clone())
+    + Dmarc::default().with_resolver(records);
+```
+*/
+
+#[macro_use]
+extern crate log;
+
+mod canonicalization;
+mod dmarc;
+mod key;
+mod message;
+mod sign;
+mod signature;
+mod verify;
+
+pub use self::canonicalization::*;
+pub use self::dmarc::*;
+pub use self::key::*;
+pub use self::sign::*;
+pub use self::signature::*;
+pub use self::verify::*;
+
+use samotop_core::{
+    common::*,
+    mail::{
+        AcceptsGuard, AddRecipientResult, AuthResult, CheckBodyResult, MailDataSink, MailGuard,
+        MailSetup, MailSpool, Recipient, StartMailResult,
+    },
+    smtp::SmtpSession,
+};
+use std::collections::HashMap;
+use std::sync::{Mutex, Weak};
+
+/// Verifies DKIM signatures of incoming mail.
+///
+/// The mail is held back in the spool until it is verified and the mail data are hashed
+/// as they arrive. Mail larger than the spool limit is refused.
+/// The results are only recorded, it is up to other checks such as DMARC to act on them.
+/// To have them in the `Authentication-Results` header, set up `AuthResultsHeader` as well.
+#[derive(Clone, Debug)]
+pub struct Dkim {
+    resolver: Arc<dyn KeyResolver>,
+    spool_limit: usize,
+    pending: Arc<Mutex<HashMap<String, Weak<Mutex<DkimVerifier>>>>>,
+}
+
+impl Default for Dkim {
+    fn default() -> Self {
+        Self {
+            resolver: Arc::new(DnsRecords::default()),
+            spool_limit: Self::DEFAULT_SPOOL_LIMIT,
+            pending: Default::default(),
+        }
+    }
+}
+
+impl Dkim {
+    /// Hold back at most 50 MiB of mail data by default
+    pub const DEFAULT_SPOOL_LIMIT: usize = 50 * 1024 * 1024;
+    /// Fetch the keys with the given resolver rather than from the DNS
+    pub fn with_resolver(mut self, resolver: impl KeyResolver + 'static) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+    /// Hold back at most `limit` bytes of mail data, larger mail is refused
+    pub fn with_spool_limit(mut self, limit: usize) -> Self {
+        self.spool_limit = limit;
+        self
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for Dkim {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for Dkim {
+    fn check_body<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, CheckBodyResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let verifier = self
+                .pending
+                .lock()
+                .expect("DKIM lock")
+                .remove(session.transaction.id.as_str())
+                .and_then(|verifier| verifier.upgrade());
+            let verifier = match verifier {
+                Some(verifier) => std::mem::take(&mut *verifier.lock().expect("DKIM lock")),
+                None => {
+                    warn!(
+                        "DKIM did not see the mail data of {}",
+                        session.transaction.id
+                    );
+                    return CheckBodyResult::Accepted;
+                }
+            };
+            for result in verifier.verify(self.resolver.as_ref()).await {
+                debug!("DKIM result for {}: {:?}", session.transaction.id, result);
+                session
+                    .transaction
+                    .auth_results
+                    .push(AuthResult::from(&result));
+            }
+            CheckBodyResult::Accepted
+        })
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        // hold the mail until it is verified and hash it as it arrives
+        if session.transaction.spool.is_none() {
+            session.transaction.spool = Some(MailSpool::with_limit(self.spool_limit));
+        }
+        let verifier = Arc::new(Mutex::new(DkimVerifier::default()));
+        {
+            let mut pending = self.pending.lock().expect("DKIM lock");
+            // forget the abandoned transactions
+            pending.retain(|_, verifier| verifier.strong_count() != 0);
+            pending.insert(session.transaction.id.clone(), Arc::downgrade(&verifier));
+        }
+        let inner = session.transaction.sink.take();
+        session.transaction.sink = Some(Box::pin(DkimSink { inner, verifier }));
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+/// Passes the mail data on to the inner sink and to the verifier
+struct DkimSink {
+    inner: Option<Pin<Box<dyn MailDataSink>>>,
+    verifier: Arc<Mutex<DkimVerifier>>,
+}
+
+impl io::Write for DkimSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = match self.inner.as_mut() {
+            Some(inner) => inner.as_mut().poll_write(cx, buf),
+            None => Poll::Ready(Ok(buf.len())),
+        };
+        if let Poll::Ready(Ok(len)) = res {
+            self.verifier.lock().expect("DKIM lock").update(&buf[..len]);
+        }
+        res
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.as_mut().poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.as_mut().poll_close(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::tests::{keys, MESSAGE};
+    use async_std::io::WriteExt;
+
+    #[test]
+    fn records_results_on_transaction() {
+        async_std::task::block_on(async move {
+            let sut = Dkim::default().with_resolver(keys());
+            let mut session = SmtpSession::default();
+            session.transaction.id = "t1".to_owned();
+            sut.start_mail(&mut session).await;
+            assert!(session.transaction.spool.is_some());
+            let sink = session.transaction.sink.as_mut().expect("sink");
+            sink.write_all(MESSAGE.as_bytes()).await.expect("write");
+
+            let res = sut.check_body(&mut session).await;
+
+            assert_eq!(res, CheckBodyResult::Accepted);
+            let results: Vec<String> = session
+                .transaction
+                .auth_results
+                .to_vec()
+                .iter()
+                .map(|r| r.to_string())
+                .collect();
+            assert_eq!(
+                results,
+                vec![
+                    "dkim=pass header.d=football.example.com header.i=@football.example.com header.s=brisbane header.b=/gCrinpc",
+                    "dkim=pass header.d=football.example.com header.i=@football.example.com header.s=test header.b=F45dVWDf"
+                ]
+            );
+        })
+    }
+}
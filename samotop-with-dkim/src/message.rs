@@ -0,0 +1,115 @@
+use crate::canonicalization::BodyCanonicalizer;
+use crate::signature::without_signature_data;
+use crate::{Canonicalization, DkimSignature};
+use ring::digest;
+use std::collections::HashMap;
+
+/// Find the end of the header section in the data searched from the given position.
+/// It is the position after the CRLF of the last header field.
+pub(crate) fn header_end(data: &[u8], from: usize) -> Option<usize> {
+    match data.starts_with(b"\r\n") {
+        // no header fields at all
+        true => Some(0),
+        false => data[from..]
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|pos| from + pos + 2),
+    }
+}
+
+/// Split the header section into raw header fields including the folded lines
+pub(crate) fn header_fields(header: &[u8]) -> Vec<Vec<u8>> {
+    let mut fields: Vec<Vec<u8>> = vec![];
+    for line in header.split_inclusive(|b| *b == b'\n') {
+        match (line.first(), fields.last_mut()) {
+            (Some(b' '), Some(field)) | (Some(b'\t'), Some(field)) => field.extend_from_slice(line),
+            _ => fields.push(line.to_vec()),
+        }
+    }
+    fields
+}
+
+pub(crate) fn field_name(field: &[u8]) -> String {
+    let colon = field.iter().position(|b| *b == b':').unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..colon]).trim().to_owned()
+}
+
+pub(crate) fn field_value(field: &[u8]) -> String {
+    match field.iter().position(|b| *b == b':') {
+        Some(colon) => String::from_utf8_lossy(&field[colon + 1..]).into_owned(),
+        None => String::new(),
+    }
+}
+
+/// Canonicalizes and hashes the body for one signature
+pub(crate) struct BodyHasher {
+    canonicalizer: BodyCanonicalizer,
+    digest: digest::Context,
+    length: usize,
+    limit: Option<usize>,
+}
+
+impl BodyHasher {
+    pub fn new(method: Canonicalization, limit: Option<usize>) -> Self {
+        BodyHasher {
+            canonicalizer: BodyCanonicalizer::new(method),
+            digest: digest::Context::new(&digest::SHA256),
+            length: 0,
+            limit,
+        }
+    }
+    pub fn update(&mut self, data: &[u8]) {
+        let BodyHasher {
+            canonicalizer,
+            digest,
+            length,
+            limit,
+        } = self;
+        canonicalizer.update(data, &mut |data| hash(digest, length, *limit, data));
+    }
+    /// The body hash and the count of body bytes hashed
+    pub fn finish(mut self) -> (Vec<u8>, usize) {
+        let BodyHasher {
+            canonicalizer,
+            digest,
+            length,
+            limit,
+        } = &mut self;
+        canonicalizer.finish(&mut |data| hash(digest, length, *limit, data));
+        (self.digest.finish().as_ref().to_vec(), self.length)
+    }
+}
+
+fn hash(digest: &mut digest::Context, length: &mut usize, limit: Option<usize>, data: &[u8]) {
+    let take = match limit {
+        Some(limit) => std::cmp::min(limit.saturating_sub(*length), data.len()),
+        None => data.len(),
+    };
+    digest.update(&data[..take]);
+    *length += take;
+}
+
+/// The canonical signed header fields followed by the signature field without b=
+pub(crate) fn signed_data(fields: &[Vec<u8>], sig: &DkimSignature, raw: &[u8]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut used: HashMap<&str, usize> = HashMap::new();
+    for name in sig.headers.iter() {
+        // take the instances bottom up
+        let skip = used.entry(name.as_str()).or_default();
+        let field = fields
+            .iter()
+            .rev()
+            .filter(|field| field_name(field).eq_ignore_ascii_case(name))
+            .nth(*skip);
+        *skip += 1;
+        if let Some(field) = field {
+            data.extend(sig.header_canonicalization.header(field));
+        }
+    }
+    let mut signature = sig
+        .header_canonicalization
+        .header(without_signature_data(raw).as_slice());
+    signature.truncate(signature.len() - 2);
+    data.extend(signature);
+    data
+}
@@ -0,0 +1,378 @@
+use crate::message::*;
+use crate::{Canonicalization, DkimAlgorithm, DkimSignature};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RSA_PKCS1_SHA256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header fields signed by default if present in the message
+pub const DEFAULT_SIGNED_HEADERS: &[&str] = &[
+    "from",
+    "reply-to",
+    "subject",
+    "date",
+    "to",
+    "cc",
+    "message-id",
+    "in-reply-to",
+    "references",
+    "mime-version",
+    "content-type",
+    "content-transfer-encoding",
+];
+
+/// A private key to sign the mail with
+#[derive(Debug)]
+pub enum DkimPrivateKey {
+    Rsa(RsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl DkimPrivateKey {
+    /// RSA key from a PKCS#8 document in DER
+    pub fn rsa_from_pkcs8(der: &[u8]) -> Result<Self, String> {
+        RsaKeyPair::from_pkcs8(der)
+            .map(DkimPrivateKey::Rsa)
+            .map_err(|e| format!("invalid RSA key: {}", e))
+    }
+    /// RSA key from a PKCS#1 RSAPrivateKey in DER
+    pub fn rsa_from_der(der: &[u8]) -> Result<Self, String> {
+        RsaKeyPair::from_der(der)
+            .map(DkimPrivateKey::Rsa)
+            .map_err(|e| format!("invalid RSA key: {}", e))
+    }
+    /// Ed25519 key from a PKCS#8 document in DER
+    pub fn ed25519_from_pkcs8(der: &[u8]) -> Result<Self, String> {
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+            .map(DkimPrivateKey::Ed25519)
+            .map_err(|e| format!("invalid Ed25519 key: {}", e))
+    }
+    /// Ed25519 key from the 32 byte private key seed
+    pub fn ed25519_from_seed(seed: &[u8]) -> Result<Self, String> {
+        Ed25519KeyPair::from_seed_unchecked(seed)
+            .map(DkimPrivateKey::Ed25519)
+            .map_err(|e| format!("invalid Ed25519 key: {}", e))
+    }
+    pub fn algorithm(&self) -> DkimAlgorithm {
+        match self {
+            DkimPrivateKey::Rsa(_) => DkimAlgorithm::RsaSha256,
+            DkimPrivateKey::Ed25519(_) => DkimAlgorithm::Ed25519Sha256,
+        }
+    }
+    /// The key record to publish at `<selector>._domainkey.<domain>`
+    pub fn public_key_record(&self) -> String {
+        match self {
+            DkimPrivateKey::Rsa(key) => format!(
+                "v=DKIM1; k=rsa; p={}",
+                base64::encode(rsa_spki(key.public_key().as_ref()))
+            ),
+            DkimPrivateKey::Ed25519(key) => format!(
+                "v=DKIM1; k=ed25519; p={}",
+                base64::encode(key.public_key().as_ref())
+            ),
+        }
+    }
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            DkimPrivateKey::Rsa(key) => {
+                let mut signature = vec![0; key.public_modulus_len()];
+                key.sign(
+                    &RSA_PKCS1_SHA256,
+                    &SystemRandom::new(),
+                    data,
+                    &mut signature,
+                )
+                .map_err(|e| format!("RSA signing failed: {}", e))?;
+                Ok(signature)
+            }
+            DkimPrivateKey::Ed25519(key) => {
+                // Ed25519 signs the SHA-256 hash of the data (RFC 8463)
+                let hash = ring::digest::digest(&ring::digest::SHA256, data);
+                Ok(key.sign(hash.as_ref()).as_ref().to_vec())
+            }
+        }
+    }
+}
+
+/// Wrap a PKCS#1 RSAPublicKey in a SubjectPublicKeyInfo
+fn rsa_spki(key: &[u8]) -> Vec<u8> {
+    // SEQUENCE { OID 1.2.840.113549.1.1.1 rsaEncryption, NULL }
+    const ALGORITHM: &[u8] = &[
+        0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+    ];
+    let mut bits = vec![0];
+    bits.extend_from_slice(key);
+    let mut spki = ALGORITHM.to_vec();
+    spki.extend(der_item(0x03, bits.as_slice()));
+    der_item(0x30, spki.as_slice())
+}
+
+fn der_item(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut item = vec![tag];
+    match content.len() {
+        len if len < 0x80 => item.push(len as u8),
+        len => {
+            let bytes: Vec<u8> = len
+                .to_be_bytes()
+                .iter()
+                .copied()
+                .skip_while(|b| *b == 0)
+                .collect();
+            item.push(0x80 | bytes.len() as u8);
+            item.extend(bytes);
+        }
+    }
+    item.extend_from_slice(content);
+    item
+}
+
+/// Signs outgoing mail with DKIM (RFC 6376)
+#[derive(Debug)]
+pub struct DkimSigner {
+    domain: String,
+    selector: String,
+    key: DkimPrivateKey,
+    headers: Vec<String>,
+    header_canonicalization: Canonicalization,
+    body_canonicalization: Canonicalization,
+    identity: Option<String>,
+    expiration: Option<Duration>,
+}
+
+impl DkimSigner {
+    /// Sign as the given domain with the key published under the selector.
+    /// By default, relaxed canonicalization is used for both header and body.
+    pub fn new(
+        domain: impl Into<String>,
+        selector: impl Into<String>,
+        key: DkimPrivateKey,
+    ) -> Self {
+        Self {
+            domain: domain.into(),
+            selector: selector.into(),
+            key,
+            headers: DEFAULT_SIGNED_HEADERS
+                .iter()
+                .map(|h| (*h).to_owned())
+                .collect(),
+            header_canonicalization: Canonicalization::Relaxed,
+            body_canonicalization: Canonicalization::Relaxed,
+            identity: None,
+            expiration: None,
+        }
+    }
+    /// Sign these header fields if present. From is always signed.
+    pub fn with_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.headers = headers
+            .into_iter()
+            .map(|h| h.as_ref().trim().to_ascii_lowercase())
+            .collect();
+        self
+    }
+    pub fn with_canonicalization(
+        mut self,
+        header: Canonicalization,
+        body: Canonicalization,
+    ) -> Self {
+        self.header_canonicalization = header;
+        self.body_canonicalization = body;
+        self
+    }
+    /// The i= agent or user identity, it must be within the signing domain
+    pub fn with_identity(mut self, identity: impl Into<String>) -> Self {
+        self.identity = Some(identity.into());
+        self
+    }
+    /// Let the signature expire after the given time
+    pub fn with_expiration(mut self, expiration: Duration) -> Self {
+        self.expiration = Some(expiration);
+        self
+    }
+    /// Sign the complete message.
+    ///
+    /// Returns the `DKIM-Signature` header field including the final CRLF
+    /// to be prepended to the message.
+    pub fn sign(&self, message: &[u8]) -> Result<String, String> {
+        let end = header_end(message, 0).unwrap_or(message.len());
+        let (header, body) = message.split_at(end);
+        let body = body.strip_prefix(b"\r\n").unwrap_or(body);
+        let fields = header_fields(header);
+
+        let mut hasher = BodyHasher::new(self.body_canonicalization, None);
+        hasher.update(body);
+        let (body_hash, _) = hasher.finish();
+
+        let mut headers = vec![];
+        if !self.headers.iter().any(|h| h == "from") {
+            headers.push("from");
+        }
+        headers.extend(self.headers.iter().map(String::as_str));
+        let headers: Vec<&str> = headers
+            .into_iter()
+            .flat_map(|name| {
+                // sign every instance present
+                let count = fields
+                    .iter()
+                    .filter(|field| field_name(field).eq_ignore_ascii_case(name))
+                    .count();
+                std::iter::repeat_n(name, count)
+            })
+            .collect();
+        if !headers.contains(&"from") {
+            return Err("the message has no From header".to_owned());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs())
+            .unwrap_or_default();
+        let mut tags = vec![
+            "v=1".to_owned(),
+            format!("a={}", self.key.algorithm().name()),
+            format!(
+                "c={}/{}",
+                self.header_canonicalization.name(),
+                self.body_canonicalization.name()
+            ),
+            format!("d={}", self.domain),
+            format!("s={}", self.selector),
+            format!("t={}", timestamp),
+        ];
+        if let Some(expiration) = self.expiration {
+            tags.push(format!("x={}", timestamp + expiration.as_secs()));
+        }
+        if let Some(ref identity) = self.identity {
+            tags.push(format!("i={}", identity));
+        }
+        tags.push(format!("h={}", headers.join(":")));
+        tags.push(format!("bh={}", base64::encode(body_hash)));
+        tags.push("b=".to_owned());
+
+        // the signature covers the folded header so fold it now
+        let mut field = "DKIM-Signature:".to_owned();
+        let mut line = field.len();
+        for tag in tags {
+            if line + tag.len() + 2 > 78 {
+                field.push_str("\r\n\t");
+                line = 1;
+            } else {
+                field.push(' ');
+                line += 1;
+            }
+            line += tag.len() + 1;
+            field.push_str(tag.as_str());
+            field.push(';');
+        }
+        // no semicolon after b=
+        field.pop();
+
+        let sig = DkimSignature::parse(&field["DKIM-Signature:".len()..])?;
+        let data = signed_data(fields.as_slice(), &sig, field.as_bytes());
+        let signature = base64::encode(self.key.sign(data.as_slice())?);
+
+        let mut line = line - 1;
+        for chunk in signature.as_bytes().chunks(64) {
+            if line + chunk.len() > 78 {
+                field.push_str("\r\n\t");
+                line = 1;
+            }
+            line += chunk.len();
+            field.push_str(String::from_utf8_lossy(chunk).as_ref());
+        }
+        field.push_str("\r\n");
+        Ok(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DkimStatus, DkimVerifier, StaticRecords};
+
+    const MESSAGE: &str = "From: Joe <joe@example.com>\r\n\
+        To: Suzie <suzie@example.net>\r\n\
+        Subject: Is dinner ready?\r\n\
+        \r\n\
+        Hi.\r\n\
+        \r\n\
+        We lost the game.  Are you hungry yet?\r\n\
+        \r\n\
+        Joe.\r\n";
+
+    /// A throwaway 2048 bit RSA key in PKCS#8
+    const RSA_KEY: &str = concat!(
+        "MIIEvQIBADANBgkqhkiG9w0BAQEFAASCBKcwggSjAgEAAoIBAQCw5w3Q/I94KTod7X7DoFJU3xxtSEo9vg/6",
+        "erwQEQrbh4BuP0b8qRE24QqvO2RnERpmmUE3UW9hJYywqvoYPWP03d3UOqqAgmc7uwmfD7j8ADZKWvyaWoCU",
+        "teplSNkaMUoJLvQxq9bomggHv/sk94os/eudtdKhQ+TiLBEsu7y5s/8ToNgO6R128XeZaUwVUiTKVqFeBCQI",
+        "BYNZI2FhuEXLFNGRX2VpF+h5Th2a3qroydyEEpTZywHFeaZ4WkrBNs1rnVNxEDP7XyrmlsF6zFqrnyjtdvgp",
+        "oV0xp2MNBFHlOKxuDR7X3kP0cqut935w1DDYSHdLvAep0eZcPQZ9CPnJAgMBAAECggEAGU2TPldBtjA/bdRw",
+        "k061OAqJqF/0rownAO7ZQ3sMkIX7KsnGP5F3MuBRhOr723ooypAVMfJl+gFC9GogR6NQTtAFULvrc2IznemM",
+        "4MGA9hqZqV0GUJWtWpgCpOYcl5CBRHsA1rH7lV2i1GjzZUA69WCEeRRuwj5R2L/w3PMldXI6oyHzDtX1uctZ",
+        "+r1YelN9lbWIoWC586Qqu0OsYo8Wu+f+4dsxnM4AqkCj+EWaLWy+1JyO6hfZ82tGWr0nP9DuOrJG9wyG3HQ4",
+        "EXpgLD4zfEQX7iy1fLYaqyLPTKrfmc6C9WAuQTGMwPALjNyXeGaX1VXdh8SLJ5x/+9VDLhsfWQKBgQDU3rA0",
+        "OrW0ExKqI7gr1Mtf/gft+ON2OwmDnBxiPKEwLVzKjCy6dRlRxBktdqZvWMlT65eC3fdPJcJaqwwfxFLR+xyz",
+        "62cIhYWnfpuCMQmQC5NGVgyOVs6wMwE0fuVkI9vlnjDJY6k4bC56/5tOrHb9thPjlLWGYi5q5ytU9FRCVwKB",
+        "gQDUvshhY/w7Img7BfWjyLFfxkTOMColdhUBHpDra8gf23kjpRDterIi6INddtDFjpyE2C7xr5y/6pC9vLbt",
+        "g8uC9bRpTb6jNqA0G4CjAX1SxypNeU2VbOotzrUwsxfgIva6JLUulQnluPb//ZvPEGRnMAhB2dDYAsX1P7oO",
+        "g1hQ3wKBgGvKf+tSt2QCLHdfMwlu7bUkC5vjZjseVgkA6haMoQqmTdB6/sNEPJf89srgxBKqIPiP2d74yxyX",
+        "AD2tU6xaW53czIIG99uG0VEpeVGJx0/brK33MNB7AJqUmU2d3EbDKbyyR/4Sq0PY9AhwmpJSHscGhje9u9Xa",
+        "raqQeI7CUlBtAoGBAMuAByrpul9SZUN4SuS1V73WaQCWH+qfji348ATRn6ehh7np7kxLXlR39xiTS9Z9uQh1",
+        "xknXgugtVvC3sH9331bQ8xb7kAyNdycA28aQ6TEiZDuhee4iNctFfBouZfs3Vqzt6gW5+LKugE6UZ9LkyjXO",
+        "Q0fCM7w8wuD6aV3yk6sfAoGARTq1sEJcnhKERfs4ePOZLMiAXgYg9z6e3FwANdqcGTbM+21lqHP0+PAUFIAK",
+        "DEQPQVv29vsrnvQ6h0kXny/rUMQxGMc8+IcPdlHEoUzvBpxL2il6OiRMBZMhMn//C2xaeS/bdMHFYhNQZUlM",
+        "vlKAM6b9pp/+AvEM3mXFVpp3T0w=",
+    );
+
+    fn verify(signer: &DkimSigner, message: &str) -> Vec<DkimStatus> {
+        let keys =
+            StaticRecords::default().with_key("sel", "example.com", signer.key.public_key_record());
+        let mut verifier = DkimVerifier::default();
+        verifier.update(signer.sign(message.as_bytes()).expect("sign").as_bytes());
+        verifier.update(message.as_bytes());
+        async_std::task::block_on(verifier.verify(&keys))
+            .into_iter()
+            .map(|r| r.status)
+            .collect()
+    }
+
+    fn ed25519() -> DkimPrivateKey {
+        DkimPrivateKey::ed25519_from_seed(&[7; 32]).expect("key")
+    }
+
+    #[test]
+    fn sign_ed25519() {
+        let sut = DkimSigner::new("example.com", "sel", ed25519());
+        assert_eq!(verify(&sut, MESSAGE), vec![DkimStatus::Pass]);
+    }
+
+    #[test]
+    fn sign_rsa() {
+        let der = base64::decode(RSA_KEY).expect("base64");
+        let key = DkimPrivateKey::rsa_from_pkcs8(der.as_slice()).expect("key");
+        let sut =
+            DkimSigner::new("example.com", "sel", key).with_expiration(Duration::from_secs(3600));
+        assert_eq!(verify(&sut, MESSAGE), vec![DkimStatus::Pass]);
+    }
+
+    #[test]
+    fn sign_simple_with_folded_header() {
+        let sut = DkimSigner::new("example.com", "sel", ed25519())
+            .with_canonicalization(Canonicalization::Simple, Canonicalization::Simple)
+            .with_headers(["subject", "to"])
+            .with_identity("joe@mail.example.com");
+        let message = format!("Subject: dinner\r\n  is ready\r\n{}", MESSAGE);
+        assert_eq!(verify(&sut, message.as_str()), vec![DkimStatus::Pass]);
+    }
+
+    #[test]
+    fn refuse_message_without_from() {
+        let sut = DkimSigner::new("example.com", "sel", ed25519());
+        assert_eq!(
+            sut.sign(b"To: x@y\r\n\r\nHi\r\n"),
+            Err("the message has no From header".to_owned())
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::Canonicalization;
+
+/// Signing algorithms of the `a=` tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DkimAlgorithm {
+    /// RSA PKCS#1 v1.5 over SHA-256 (RFC 6376)
+    RsaSha256,
+    /// Ed25519 over SHA-256 (RFC 8463)
+    Ed25519Sha256,
+}
+
+impl DkimAlgorithm {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "rsa-sha256" => Some(DkimAlgorithm::RsaSha256),
+            "ed25519-sha256" => Some(DkimAlgorithm::Ed25519Sha256),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            DkimAlgorithm::RsaSha256 => "rsa-sha256",
+            DkimAlgorithm::Ed25519Sha256 => "ed25519-sha256",
+        }
+    }
+}
+
+/// A parsed `DKIM-Signature` header value (RFC 6376 section 3.5)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DkimSignature {
+    /// a= signing algorithm
+    pub algorithm: DkimAlgorithm,
+    /// b= the signature data
+    pub signature: Vec<u8>,
+    /// bh= hash of the canonicalized body
+    pub body_hash: Vec<u8>,
+    /// c= header canonicalization
+    pub header_canonicalization: Canonicalization,
+    /// c= body canonicalization
+    pub body_canonicalization: Canonicalization,
+    /// d= the signing domain
+    pub domain: String,
+    /// h= lower case names of the signed header fields
+    pub headers: Vec<String>,
+    /// i= the agent or user identity
+    pub identity: Option<String>,
+    /// l= count of body bytes signed
+    pub body_length: Option<usize>,
+    /// s= the selector of the key
+    pub selector: String,
+    /// t= signature timestamp
+    pub timestamp: Option<u64>,
+    /// x= signature expiration
+    pub expiration: Option<u64>,
+}
+
+impl DkimSignature {
+    /// Parse and validate the header value
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let tags = parse_tags(value)?;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let required = |name: &str| tag(name).ok_or_else(|| format!("missing {}= tag", name));
+        let base64 = |name: &str| {
+            base64::decode(required(name)?).map_err(|e| format!("invalid {}= tag: {}", name, e))
+        };
+        let number = |name: &str| match tag(name) {
+            None => Ok(None),
+            Some(value) => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|e| format!("invalid {}= tag: {}", name, e)),
+        };
+
+        if required("v")? != "1" {
+            return Err("unsupported version".to_owned());
+        }
+        let algorithm = DkimAlgorithm::parse(required("a")?)
+            .ok_or_else(|| format!("unsupported algorithm {}", required("a").unwrap_or("")))?;
+        let (header_canonicalization, body_canonicalization) = match tag("c") {
+            None => (Canonicalization::Simple, Canonicalization::Simple),
+            Some(c) => Canonicalization::parse_pair(c)
+                .ok_or_else(|| format!("unsupported canonicalization {}", c))?,
+        };
+        if let Some(q) = tag("q") {
+            if !q
+                .split(':')
+                .any(|q| q.trim().eq_ignore_ascii_case("dns/txt"))
+            {
+                return Err(format!("unsupported query method {}", q));
+            }
+        }
+        let domain = required("d")?.trim_end_matches('.').to_ascii_lowercase();
+        let headers: Vec<String> = required("h")?
+            .split(':')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        if !headers.iter().any(|h| h == "from") {
+            return Err("From header is not signed".to_owned());
+        }
+        let identity = tag("i").map(str::to_owned);
+        if let Some(ref identity) = identity {
+            let host = identity.rsplit('@').next().unwrap_or_default();
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            if host != domain && !host.ends_with(format!(".{}", domain).as_str()) {
+                return Err("identity is not within the signing domain".to_owned());
+            }
+        }
+        let signature = DkimSignature {
+            algorithm,
+            signature: base64("b")?,
+            body_hash: base64("bh")?,
+            header_canonicalization,
+            body_canonicalization,
+            domain,
+            headers,
+            identity,
+            body_length: number("l")?.map(|l| l as usize),
+            selector: required("s")?.to_ascii_lowercase(),
+            timestamp: number("t")?,
+            expiration: number("x")?,
+        };
+        if let (Some(t), Some(x)) = (signature.timestamp, signature.expiration) {
+            if x < t {
+                return Err("signature expires before it was made".to_owned());
+            }
+        }
+        Ok(signature)
+    }
+}
+
+/// Parse a tag=value list, whitespace is removed from the values
+pub(crate) fn parse_tags(value: &str) -> Result<Vec<(String, String)>, String> {
+    let mut tags: Vec<(String, String)> = vec![];
+    for spec in value.split(';') {
+        if spec.trim().is_empty() {
+            continue;
+        }
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid tag {:?}", spec.trim()))?;
+        let name = name.trim().to_owned();
+        if tags.iter().any(|(n, _)| *n == name) {
+            return Err(format!("duplicate tag {}", name));
+        }
+        let value = value.split_whitespace().collect::<String>();
+        tags.push((name, value));
+    }
+    Ok(tags)
+}
+
+/// Empty the value of the `b=` tag in a raw DKIM-Signature header field
+pub(crate) fn without_signature_data(raw: &[u8]) -> Vec<u8> {
+    let colon = match raw.iter().position(|b| *b == b':') {
+        Some(colon) => colon + 1,
+        None => return raw.to_vec(),
+    };
+    let mut output = raw[..colon].to_vec();
+    let mut tags = raw[colon..].split(|b| *b == b';').peekable();
+    while let Some(spec) = tags.next() {
+        let name_end = spec.iter().position(|b| *b == b'=').unwrap_or(spec.len());
+        let name: Vec<u8> = spec[..name_end]
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        if name == b"b" && name_end < spec.len() {
+            output.extend_from_slice(&spec[..=name_end]);
+            if spec.ends_with(b"\r\n") && tags.peek().is_none() {
+                output.extend_from_slice(b"\r\n");
+            }
+        } else {
+            output.extend_from_slice(spec);
+        }
+        if tags.peek().is_some() {
+            output.push(b';');
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signature() {
+        let sut = DkimSignature::parse(
+            "v=1; a=rsa-sha256; c=relaxed; d=Example.com; s=sel;\r\n\t\
+             h=From : To; bh=YWJj; b=ZG Vm\r\n\tZw==; l=42",
+        )
+        .expect("valid");
+        assert_eq!(sut.algorithm, DkimAlgorithm::RsaSha256);
+        assert_eq!(sut.header_canonicalization, Canonicalization::Relaxed);
+        assert_eq!(sut.body_canonicalization, Canonicalization::Simple);
+        assert_eq!(sut.domain, "example.com");
+        assert_eq!(sut.headers, vec!["from", "to"]);
+        assert_eq!(sut.body_hash, b"abc");
+        assert_eq!(sut.signature, b"defg");
+        assert_eq!(sut.body_length, Some(42));
+    }
+
+    #[test]
+    fn refuse_unsigned_from() {
+        let res = DkimSignature::parse("v=1; a=rsa-sha256; d=a.b; s=s; h=to; bh=YWJj; b=YWJj");
+        assert_eq!(res, Err("From header is not signed".to_owned()));
+    }
+
+    #[test]
+    fn remove_signature_data() {
+        let raw = b"DKIM-Signature: v=1; bh=YWJj; b=ZGVm\r\n Zw==; d=a.b\r\n";
+        assert_eq!(
+            String::from_utf8_lossy(without_signature_data(raw).as_slice()),
+            "DKIM-Signature: v=1; bh=YWJj; b=; d=a.b\r\n"
+        );
+        let raw = b"DKIM-Signature: v=1; bh=YWJj; b=ZGVm\r\n Zw==\r\n";
+        assert_eq!(
+            String::from_utf8_lossy(without_signature_data(raw).as_slice()),
+            "DKIM-Signature: v=1; bh=YWJj; b=\r\n"
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use crate::message::*;
+use crate::signature::parse_tags;
+use crate::{DkimAlgorithm, DkimKey, DkimSignature, KeyResolver, LookupError};
+use samotop_core::{common::*, mail::AuthResult};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Most signatures verified per message
+const MAX_SIGNATURES: usize = 8;
+/// Largest header section searched for signatures
+const MAX_HEADER_SIZE: usize = 1024 * 1024;
+
+/// The outcome of a DKIM signature verification (RFC 8601 section 2.7.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DkimStatus {
+    /// The message was not signed
+    None,
+    /// The signature is valid
+    Pass,
+    /// The signature or the body hash does not match
+    Fail,
+    /// The signature cannot be verified, such as when the key is missing
+    PermError,
+    /// The signature could not be verified now, such as when DNS fails
+    TempError,
+}
+
+impl DkimStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            DkimStatus::None => "none",
+            DkimStatus::Pass => "pass",
+            DkimStatus::Fail => "fail",
+            DkimStatus::PermError => "permerror",
+            DkimStatus::TempError => "temperror",
+        }
+    }
+}
+
+/// The verification result of one signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DkimResult {
+    pub status: DkimStatus,
+    /// d= the signing domain
+    pub domain: String,
+    /// s= the key selector
+    pub selector: String,
+    /// i= the signing identity
+    pub identity: Option<String>,
+    /// The start of b= to tell the signatures apart
+    pub signature_prefix: String,
+    pub reason: Option<String>,
+}
+
+impl DkimResult {
+    fn none() -> Self {
+        DkimResult {
+            status: DkimStatus::None,
+            domain: String::new(),
+            selector: String::new(),
+            identity: None,
+            signature_prefix: String::new(),
+            reason: None,
+        }
+    }
+}
+
+impl From<&DkimResult> for AuthResult {
+    fn from(result: &DkimResult) -> Self {
+        let mut auth = AuthResult::new("dkim", result.status.name());
+        if let Some(ref reason) = result.reason {
+            auth = auth.with_reason(reason.as_str());
+        }
+        if !result.domain.is_empty() {
+            auth = auth.with_property("header.d", result.domain.as_str());
+        }
+        if let Some(ref identity) = result.identity {
+            auth = auth.with_property("header.i", identity.as_str());
+        }
+        if !result.selector.is_empty() {
+            auth = auth.with_property("header.s", result.selector.as_str());
+        }
+        if !result.signature_prefix.is_empty() {
+            auth = auth.with_property("header.b", result.signature_prefix.as_str());
+        }
+        auth
+    }
+}
+
+/// Streaming DKIM verification of one message.
+///
+/// Feed the message data with `update()` as they arrive. The header section is kept
+/// to find the signatures and the signed headers, the body is only hashed on the go.
+/// Once the message is complete, `verify()` fetches the keys and checks the signatures.
+#[derive(Default)]
+pub struct DkimVerifier {
+    header: Vec<u8>,
+    in_body: bool,
+    fields: Vec<Vec<u8>>,
+    signatures: Vec<PendingSignature>,
+}
+
+struct PendingSignature {
+    raw: Vec<u8>,
+    parsed: std::result::Result<DkimSignature, String>,
+    body: Option<BodyHasher>,
+}
+
+impl DkimVerifier {
+    /// Process the next chunk of the message
+    pub fn update(&mut self, data: &[u8]) {
+        if self.in_body {
+            self.update_body(data);
+            return;
+        }
+        let searched = self.header.len().saturating_sub(3);
+        self.header.extend_from_slice(data);
+        let end = match header_end(self.header.as_slice(), searched) {
+            Some(end) => end,
+            None if self.header.len() > MAX_HEADER_SIZE => {
+                warn!(
+                    "DKIM gives up on a header section over {} bytes",
+                    MAX_HEADER_SIZE
+                );
+                self.header = vec![];
+                self.in_body = true;
+                return;
+            }
+            None => return,
+        };
+        let body = self.header.split_off(end + 2);
+        self.header.truncate(end);
+        self.parse_header();
+        self.update_body(body.as_slice());
+    }
+    /// Fetch the keys and verify the signatures of the complete message
+    pub async fn verify(mut self, resolver: &dyn KeyResolver) -> Vec<DkimResult> {
+        if !self.in_body {
+            // no body, just the header section
+            self.header.extend_from_slice(b"\r\n");
+            self.parse_header();
+        }
+        if self.signatures.is_empty() {
+            return vec![DkimResult::none()];
+        }
+        let mut results = vec![];
+        let signatures = std::mem::take(&mut self.signatures);
+        for signature in signatures {
+            results.push(self.verify_one(signature, resolver).await);
+        }
+        results
+    }
+    fn parse_header(&mut self) {
+        self.in_body = true;
+        self.fields = header_fields(std::mem::take(&mut self.header).as_slice());
+        for field in self.fields.iter() {
+            if self.signatures.len() >= MAX_SIGNATURES {
+                warn!("DKIM verifies only the first {} signatures", MAX_SIGNATURES);
+                break;
+            }
+            if !field_name(field).eq_ignore_ascii_case("dkim-signature") {
+                continue;
+            }
+            let parsed = DkimSignature::parse(field_value(field).as_str());
+            let body = parsed
+                .as_ref()
+                .ok()
+                .map(|sig| BodyHasher::new(sig.body_canonicalization, sig.body_length));
+            self.signatures.push(PendingSignature {
+                raw: field.clone(),
+                parsed,
+                body,
+            });
+        }
+    }
+    fn update_body(&mut self, data: &[u8]) {
+        for signature in self.signatures.iter_mut() {
+            if let Some(ref mut body) = signature.body {
+                body.update(data);
+            }
+        }
+    }
+    async fn verify_one(
+        &self,
+        signature: PendingSignature,
+        resolver: &dyn KeyResolver,
+    ) -> DkimResult {
+        let PendingSignature { raw, parsed, body } = signature;
+        let sig = match parsed {
+            Ok(sig) => sig,
+            Err(e) => {
+                // best effort to tell which signature it was
+                let tags = parse_tags(field_value(&raw).as_str()).unwrap_or_default();
+                let tag = |name: &str| {
+                    tags.iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, v)| v.clone())
+                        .unwrap_or_default()
+                };
+                return DkimResult {
+                    status: DkimStatus::PermError,
+                    domain: tag("d"),
+                    selector: tag("s"),
+                    identity: None,
+                    signature_prefix: tag("b").chars().take(8).collect(),
+                    reason: Some(e),
+                };
+            }
+        };
+        let result = |status: DkimStatus, reason: Option<&str>| DkimResult {
+            status,
+            domain: sig.domain.clone(),
+            selector: sig.selector.clone(),
+            identity: sig.identity.clone(),
+            signature_prefix: base64::encode(&sig.signature).chars().take(8).collect(),
+            reason: reason.map(str::to_owned),
+        };
+
+        if let Some(expiration) = sig.expiration {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            if expiration < now {
+                return result(DkimStatus::PermError, Some("signature expired"));
+            }
+        }
+
+        let (body_hash, length) = match body {
+            Some(body) => body.finish(),
+            None => return result(DkimStatus::PermError, Some("no body hash")),
+        };
+        if sig.body_length.map(|l| l > length).unwrap_or_default() {
+            return result(DkimStatus::PermError, Some("body is shorter than l="));
+        }
+        if body_hash != sig.body_hash {
+            return result(DkimStatus::Fail, Some("body hash did not verify"));
+        }
+
+        let records = match resolver
+            .lookup_key(sig.selector.as_str(), sig.domain.as_str())
+            .await
+        {
+            Ok(records) => records,
+            Err(LookupError::NotFound) => {
+                return result(DkimStatus::PermError, Some("no key for signature"))
+            }
+            Err(LookupError::Failed(e)) => {
+                warn!("DKIM key lookup failed: {}", e);
+                return result(DkimStatus::TempError, Some("key unavailable"));
+            }
+        };
+        let key = match records
+            .iter()
+            .map(|record| DkimKey::parse(record.as_str()))
+            .find(|key| key.is_ok())
+        {
+            Some(Ok(key)) => key,
+            _ => return result(DkimStatus::PermError, Some("invalid key record")),
+        };
+
+        let message = signed_data(&self.fields, &sig, raw.as_slice());
+        match key.verify(sig.algorithm, message.as_slice(), sig.signature.as_slice()) {
+            true => result(DkimStatus::Pass, None),
+            false if !is_compatible(sig.algorithm, &key) => {
+                result(DkimStatus::PermError, Some("inappropriate key algorithm"))
+            }
+            false => result(DkimStatus::Fail, Some("signature did not verify")),
+        }
+    }
+}
+
+impl fmt::Debug for DkimVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DkimVerifier")
+            .field("in_body", &self.in_body)
+            .field("fields", &self.fields.len())
+            .field("signatures", &self.signatures.len())
+            .finish()
+    }
+}
+
+fn is_compatible(algorithm: DkimAlgorithm, key: &DkimKey) -> bool {
+    matches!(
+        (algorithm, key.key_type),
+        (DkimAlgorithm::RsaSha256, crate::DkimKeyType::Rsa)
+            | (DkimAlgorithm::Ed25519Sha256, crate::DkimKeyType::Ed25519)
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::StaticRecords;
+
+    /// The example of RFC 8463 appendix A
+    pub(crate) const MESSAGE: &str = concat!(
+        "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed;\r\n",
+        " d=football.example.com; i=@football.example.com;\r\n",
+        " q=dns/txt; s=brisbane; t=1528637909; h=from : to :\r\n",
+        " subject : date : message-id : from : subject : date;\r\n",
+        " bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n",
+        " b=/gCrinpcQOoIfuHNQIbq4pgh9kyIK3AQUdt9OdqQehSwhEIug4D11Bus\r\n",
+        " Fa3bT3FY5OsU7ZbnKELq+eXdp1Q1Dw==\r\n",
+        "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed;\r\n",
+        " d=football.example.com; i=@football.example.com;\r\n",
+        " q=dns/txt; s=test; t=1528637909; h=from : to : subject :\r\n",
+        " date : message-id : from : subject : date;\r\n",
+        " bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n",
+        " b=F45dVWDfMbQDGHJFlXUNB2HKfbCeLRyhDXgFpEL8GwpsRe0IeIixNTe3\r\n",
+        " DhCVlUrSjV4BwcVcOF6+FF3Zo9Rpo1tFOeS9mPYQTnGdaSGsgeefOsk2Jz\r\n",
+        " dA+L10TeYt9BgDfQNZtKdN1WO//KgIqXP7OdEFE4LjFYNcUxZQ4FADY+8=\r\n",
+        "From: Joe SixPack <joe@football.example.com>\r\n",
+        "To: Suzie Q <suzie@shopping.example.net>\r\n",
+        "Subject: Is dinner ready?\r\n",
+        "Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n",
+        "Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n",
+        "\r\n",
+        "Hi.\r\n",
+        "\r\n",
+        "We lost the game.  Are you hungry yet?\r\n",
+        "\r\n",
+        "Joe.\r\n",
+    );
+
+    pub(crate) fn keys() -> StaticRecords {
+        StaticRecords::default()
+            .with_key(
+                "brisbane",
+                "football.example.com",
+                "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=",
+            )
+            .with_key(
+                "test",
+                "football.example.com",
+                concat!(
+                    "v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDkHlOQoBTzWRiGs5V6NpP3id",
+                    "Y6Wk08a5qhdR6wy5bdOKb2jLQiY/J16JYi0Qvx/byYzCNb3W91y3FutACDfzwQ/BC/e/8uBsCR+yz1Lx",
+                    "j+PL6lHvqMKrM3rG4hstT5QjvHO9PzoxZyVYLzBfO2EeC3Ip3G+2kryOTIKT+l/K4w3QIDAQAB"
+                ),
+            )
+    }
+
+    fn verify(chunks: &[&[u8]]) -> Vec<DkimResult> {
+        let mut sut = DkimVerifier::default();
+        for chunk in chunks {
+            sut.update(chunk);
+        }
+        async_std::task::block_on(sut.verify(&keys()))
+    }
+
+    #[test]
+    fn verify_rfc8463_example() {
+        let (a, b) = MESSAGE.as_bytes().split_at(700);
+        let results = verify(&[a, b]);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].status, DkimStatus::Pass, "{:?}", results[0]);
+        assert_eq!(results[0].selector, "brisbane");
+        assert_eq!(results[1].status, DkimStatus::Pass, "{:?}", results[1]);
+        assert_eq!(results[1].domain, "football.example.com");
+    }
+
+    #[test]
+    fn fail_modified_body() {
+        let message = MESSAGE.replace("hungry", "angry");
+        let results = verify(&[message.as_bytes()]);
+        assert_eq!(results[0].status, DkimStatus::Fail);
+        assert_eq!(
+            results[0].reason.as_deref(),
+            Some("body hash did not verify")
+        );
+    }
+
+    #[test]
+    fn fail_modified_header() {
+        let message = MESSAGE.replace("Is dinner ready?", "Is lunch ready?");
+        let results = verify(&[message.as_bytes()]);
+        assert_eq!(results[1].status, DkimStatus::Fail);
+        assert_eq!(
+            results[1].reason.as_deref(),
+            Some("signature did not verify")
+        );
+    }
+
+    #[test]
+    fn report_missing_key() {
+        let message = MESSAGE.replace("s=test", "s=gone");
+        let results = verify(&[message.as_bytes()]);
+        assert_eq!(results[1].status, DkimStatus::PermError);
+        assert_eq!(results[1].selector, "gone");
+    }
+
+    #[test]
+    fn report_unsigned_mail() {
+        let results = verify(&[b"From: joe@example.com\r\n\r\nHi.\r\n"]);
+        assert_eq!(results, vec![DkimResult::none()]);
+    }
+}
@@ -1,7 +1,7 @@
 use async_native_tls::TlsAcceptor;
 use async_native_tls::TlsConnector;
-use samotop_core::io::tls::TlsProvider;
-use samotop_core::io::tls::TlsUpgrade;
+use async_native_tls::TlsStream;
+use samotop_core::io::tls::{PeerCertificate, TlsInfo, TlsProvider, TlsUpgrade};
 use samotop_core::{common::*, io::tls::Io};
 use std::fmt;
 
@@ -37,24 +37,14 @@ impl TlsUpgrade for NativeTlsProvider<TlsAcceptor> {
         &self,
         io: Box<dyn Io>,
         _name: String,
-    ) -> S3Fut<std::io::Result<Box<dyn Io>>> {
+    ) -> S3Fut<std::io::Result<(Box<dyn Io>, TlsInfo)>> {
         let acceptor = self.inner.clone();
         let fut = async move {
             match acceptor.accept(io).await {
                 Ok(encrypted) => {
-                    // match encrypted.peer_certificate() {
-                    //     Err(e) => trace!("peer cert error: {:?}", e),
-                    //     Ok(None) => trace!("peer cert None."),
-                    //     Ok(Some(cert)) => {
-                    //         let cert = cert.to_der().unwrap();
-                    //         trace!("peer cert present: {:?}", cert.len());
-                    //         let mut f = std::fs::File::create("client.crt")?;
-                    //         use std::io::Write;
-                    //         f.write_all(cert.as_slice())?;
-                    //     }
-                    // }
+                    let info = tls_info(&encrypted, None);
                     let encrypted: Box<dyn Io> = Box::new(encrypted);
-                    Ok(encrypted)
+                    Ok((encrypted, info))
                 }
                 Err(e) => Err(std::io::Error::new(
                     std::io::ErrorKind::BrokenPipe,
@@ -89,13 +79,17 @@ impl TlsUpgrade for NativeTlsProvider<TlsConnector> {
         &self,
         stream: Box<dyn Io>,
         name: String,
-    ) -> S3Fut<std::io::Result<Box<dyn Io>>> {
+    ) -> S3Fut<std::io::Result<(Box<dyn Io>, TlsInfo)>> {
         let connector = self.inner.clone();
         Box::pin(async move {
-            match connector.connect(name, stream).await {
+            match connector.connect(name.as_str(), stream).await {
                 Ok(s) => {
+                    let mut info = tls_info(&s, Some(name));
+                    // native-tls verifies the server certificate and name unless
+                    // the connector was built with danger_accept_invalid_certs/hostnames
+                    info.peer_verified = true;
                     let s: Box<dyn Io> = Box::new(s);
-                    Ok(s)
+                    Ok((s, info))
                 }
                 Err(e) => Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, e)),
             }
@@ -108,3 +102,32 @@ impl fmt::Debug for NativeTlsProvider<TlsConnector> {
         f.debug_struct("NativeTlsProvider<TlsAcceptor>").finish()
     }
 }
+
+/// Collect details of an established native-tls session.
+/// native-tls has no API for the negotiated protocol and cipher suite, so they stay unknown.
+fn tls_info<S>(stream: &TlsStream<S>, server_name: Option<String>) -> TlsInfo
+where
+    S: io::Read + io::Write + Unpin,
+{
+    let peer_certificates = match stream.peer_certificate() {
+        Ok(Some(cert)) => match cert.to_der() {
+            Ok(der) => vec![PeerCertificate::from_der(der)],
+            Err(e) => {
+                log::warn!("peer certificate could not be encoded: {}", e);
+                vec![]
+            }
+        },
+        Ok(None) => vec![],
+        Err(e) => {
+            log::warn!("peer certificate error: {}", e);
+            vec![]
+        }
+    };
+    TlsInfo {
+        protocol: None,
+        cipher_suite: None,
+        server_name,
+        peer_certificates,
+        peer_verified: false,
+    }
+}
@@ -1,7 +1,9 @@
-use async_tls::{TlsAcceptor, TlsConnector};
+// futures-rustls rather than async-tls gives access to the negotiated rustls session
+pub use futures_rustls::{rustls, TlsAcceptor, TlsConnector};
+use rustls::Session;
 use samotop_core::{
     common::*,
-    io::tls::{Io, TlsProvider, TlsUpgrade},
+    io::tls::{Io, PeerCertificate, TlsInfo, TlsProvider, TlsUpgrade},
 };
 use std::fmt;
 
@@ -29,13 +31,18 @@ impl TlsUpgrade for RustlsProvider<TlsAcceptor> {
         &self,
         io: Box<dyn Io>,
         _name: String,
-    ) -> S3Fut<std::io::Result<Box<dyn Io>>> {
+    ) -> S3Fut<std::io::Result<(Box<dyn Io>, TlsInfo)>> {
         let fut = self.inner.accept(io);
         Box::pin(async move {
             match fut.await {
                 Ok(encrypted) => {
+                    let (_, session) = encrypted.get_ref();
+                    let mut info = tls_info(session);
+                    info.server_name = session.get_sni_hostname().map(String::from);
+                    // client certificates are only reported if the verifier accepted them
+                    info.peer_verified = !info.peer_certificates.is_empty();
                     let encrypted: Box<dyn Io> = Box::new(encrypted);
-                    Ok(encrypted)
+                    Ok((encrypted, info))
                 }
                 Err(e) => Err(e),
             }
@@ -44,13 +51,26 @@ impl TlsUpgrade for RustlsProvider<TlsAcceptor> {
 }
 
 impl TlsUpgrade for RustlsProvider<TlsConnector> {
-    fn upgrade_to_tls(&self, io: Box<dyn Io>, name: String) -> S3Fut<std::io::Result<Box<dyn Io>>> {
-        let fut = self.inner.connect(name.as_str(), io);
+    fn upgrade_to_tls(
+        &self,
+        io: Box<dyn Io>,
+        name: String,
+    ) -> S3Fut<std::io::Result<(Box<dyn Io>, TlsInfo)>> {
+        let fut = webpki::DNSNameRef::try_from_ascii_str(name.as_str())
+            .map(|domain| self.inner.connect(domain, io))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
         Box::pin(async move {
-            match fut.await {
+            match fut?.await {
                 Ok(encrypted) => {
+                    let (_, session) = encrypted.get_ref();
+                    let mut info = tls_info(session);
+                    info.server_name = Some(name);
+                    // rustls does not complete the handshake unless the server certificate
+                    // is valid for the name - the verifier can only be replaced
+                    // with the dangerous_configuration feature which we do not enable
+                    info.peer_verified = true;
                     let encrypted: Box<dyn Io> = Box::new(encrypted);
-                    Ok(encrypted)
+                    Ok((encrypted, info))
                 }
                 Err(e) => Err(e),
             }
@@ -58,6 +78,58 @@ impl TlsUpgrade for RustlsProvider<TlsConnector> {
     }
 }
 
+/// Rustls server configuration that requests client certificates
+/// and verifies them against the CA certificates in the given PEM bundle.
+///
+/// If `required` is false, clients without a certificate are accepted as well,
+/// which is what you want for a public facing SMTP server.
+/// Server certificate must be set before use, e.g. with `set_single_cert()`.
+pub fn client_auth_config(
+    ca_bundle: &mut dyn std::io::BufRead,
+    required: bool,
+) -> std::io::Result<rustls::ServerConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    match roots.add_pem_file(ca_bundle) {
+        Ok((valid, _)) if valid != 0 => {}
+        Ok((_, invalid)) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("No valid CA certificates found, {} invalid", invalid),
+            ))
+        }
+        Err(()) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "CA bundle is not a valid PEM file",
+            ))
+        }
+    }
+    let verifier = if required {
+        rustls::AllowAnyAuthenticatedClient::new(roots)
+    } else {
+        rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+    };
+    Ok(rustls::ServerConfig::new(verifier))
+}
+
+/// Collect details of an established rustls session
+fn tls_info(session: &dyn Session) -> TlsInfo {
+    TlsInfo {
+        protocol: session.get_protocol_version().map(|v| format!("{:?}", v)),
+        cipher_suite: session
+            .get_negotiated_ciphersuite()
+            .map(|s| format!("{:?}", s.suite)),
+        server_name: None,
+        peer_certificates: session
+            .get_peer_certificates()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|cert| PeerCertificate::from_der(cert.0))
+            .collect(),
+        peer_verified: false,
+    }
+}
+
 impl TlsProvider for RustlsProvider<TlsAcceptor> {
     fn get_tls_upgrade(&self) -> Option<Box<dyn TlsUpgrade>> {
         Some(Box::new(self.clone()))
@@ -0,0 +1,362 @@
+use crate::lookup::resolver_or_default;
+use samotop_core::{
+    common::*,
+    dns::{DnsError, DnsResolver},
+    mail::{
+        AcceptsGuard, AddRecipientResult, AuthResult, MailGuard, MailSetup, OpenSessionFailure,
+        OpenSessionResult, Recipient, StartMailResult,
+    },
+    smtp::SmtpSession,
+};
+use std::net::{IpAddr, SocketAddr};
+
+/// Words commonly found in the generic names ISPs give to dynamic end user addresses
+pub const GENERIC_NAME_HINTS: &[&str] = &[
+    "dynamic",
+    "dyn",
+    "dhcp",
+    "pool",
+    "ppp",
+    "dsl",
+    "adsl",
+    "dialup",
+    "dial",
+    "cable",
+    "cpe",
+    "broadband",
+    "client",
+    "unassigned",
+];
+
+/// Forward-confirmed reverse DNS (FCrDNS) check of the peer IP when the connection opens.
+///
+/// The PTR names of the peer IP are resolved back to addresses and the first name
+/// that resolves to the peer IP is stored in `SmtpSession::verified_peer_name`.
+/// The outcome is recorded as an `iprev` result in `SmtpSession::auth_results`.
+/// Each outcome has a configurable `FcrDnsAction`, all are accepted by default.
+#[derive(Clone, Default)]
+pub struct FcrDns {
+    resolver: Option<Arc<dyn DnsResolver>>,
+    no_ptr: FcrDnsAction,
+    mismatch: FcrDnsAction,
+    generic: FcrDnsAction,
+}
+
+/// What to do with a client failing the FCrDNS check
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FcrDnsAction {
+    /// Carry on with the session
+    #[default]
+    Accept,
+    /// Refuse the session with 554
+    Reject,
+    /// Refuse the session with 421 so that the client tries again later
+    TempFail,
+}
+
+/// The result of the FCrDNS check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FcrDnsResult {
+    /// The peer IP has no PTR record
+    NoPtr,
+    /// None of the PTR names resolve back to the peer IP
+    Mismatch(Vec<String>),
+    /// The PTR name resolves back to the peer IP
+    Verified(String),
+    /// DNS lookup failed, we do not know
+    Unknown,
+}
+
+impl FcrDns {
+    /// Use the given resolver instead of the shared default one
+    pub fn with_resolver(mut self, resolver: impl DnsResolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    /// What to do with peers that have no PTR record
+    pub fn on_no_ptr(mut self, action: FcrDnsAction) -> Self {
+        self.no_ptr = action;
+        self
+    }
+    /// What to do with peers whose PTR names do not resolve back to the peer IP
+    pub fn on_mismatch(mut self, action: FcrDnsAction) -> Self {
+        self.mismatch = action;
+        self
+    }
+    /// What to do with peers with a generic dynamic-looking name such as "dsl-10-1-2-3.isp.net"
+    pub fn on_generic(mut self, action: FcrDnsAction) -> Self {
+        self.generic = action;
+        self
+    }
+    /// Perform the FCrDNS check of the given IP
+    pub async fn check(&self, ip: IpAddr) -> FcrDnsResult {
+        let resolver = resolver_or_default(&self.resolver);
+        let names = match resolver.lookup_ptr(ip).await {
+            Ok(names) if names.is_empty() => return FcrDnsResult::NoPtr,
+            Ok(names) => names,
+            Err(DnsError::NotFound) => return FcrDnsResult::NoPtr,
+            Err(e) => {
+                warn!("PTR lookup of {} failed: {}", ip, e);
+                return FcrDnsResult::Unknown;
+            }
+        };
+        for name in names.iter() {
+            let confirmed = match ip {
+                IpAddr::V4(ip) => resolver
+                    .lookup_a(name.as_str())
+                    .await
+                    .map(|ips| ips.contains(&ip))
+                    .unwrap_or_default(),
+                IpAddr::V6(ip) => resolver
+                    .lookup_aaaa(name.as_str())
+                    .await
+                    .map(|ips| ips.contains(&ip))
+                    .unwrap_or_default(),
+            };
+            if confirmed {
+                return FcrDnsResult::Verified(name.clone());
+            }
+        }
+        FcrDnsResult::Mismatch(names)
+    }
+    /// Does the name look like a generic name of a dynamic end user address?
+    pub fn is_generic(name: &str, ip: IpAddr) -> bool {
+        let name = name.to_ascii_lowercase();
+        let label = name.split('.').next().unwrap_or_default();
+        let words = label
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>();
+        if words.iter().any(|w| GENERIC_NAME_HINTS.contains(w)) {
+            return true;
+        }
+        // the IP address is encoded in the name, such as 10-1-2-3.isp.net
+        // or reversed, such as 3.2.1.10.in-addr.isp.net
+        let (separator, radix, parts): (fn(char) -> bool, u32, Vec<u32>) = match ip {
+            IpAddr::V4(ip) => (
+                |c| !c.is_ascii_digit(),
+                10,
+                ip.octets().iter().map(|o| *o as u32).collect(),
+            ),
+            IpAddr::V6(ip) => (
+                |c| !c.is_ascii_hexdigit(),
+                16,
+                ip.segments()
+                    .iter()
+                    .filter(|s| **s != 0)
+                    .map(|s| *s as u32)
+                    .collect(),
+            ),
+        };
+        let numbers = name
+            .split(separator)
+            .filter_map(|n| u32::from_str_radix(n, radix).ok())
+            .collect::<Vec<_>>();
+        // at least three parts of the address in a row
+        parts.windows(3).any(|run| {
+            numbers
+                .windows(3)
+                .any(|found| found == run || found.iter().rev().eq(run.iter()))
+        })
+    }
+    fn refuse(action: FcrDnsAction, description: String) -> OpenSessionResult {
+        match action {
+            FcrDnsAction::Accept => OpenSessionResult::Accepted,
+            FcrDnsAction::Reject => {
+                OpenSessionResult::Failed(OpenSessionFailure::NoService, description)
+            }
+            FcrDnsAction::TempFail => {
+                OpenSessionResult::Failed(OpenSessionFailure::FailedTemporarily, description)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FcrDns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FcrDns")
+            .field("resolver", &self.resolver.as_ref().map(|_| "*"))
+            .field("no_ptr", &self.no_ptr)
+            .field("mismatch", &self.mismatch)
+            .field("generic", &self.generic)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for FcrDns {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for FcrDns {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn open_session<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S2Fut<'f, OpenSessionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let peer = session.connection.peer_addr.as_str();
+            let ip = match peer.parse::<SocketAddr>() {
+                Ok(addr) => addr.ip(),
+                Err(_) => match peer.parse::<IpAddr>() {
+                    Ok(ip) => ip,
+                    // not an IP connection, such as a unix socket
+                    Err(_) => return OpenSessionResult::Accepted,
+                },
+            };
+            let result = self.check(ip).await;
+            // RFC 8601 iprev result for the Authentication-Results header
+            let iprev = match result {
+                FcrDnsResult::Verified(ref name) => {
+                    AuthResult::new("iprev", "pass").with_reason(name.as_str())
+                }
+                FcrDnsResult::NoPtr | FcrDnsResult::Mismatch(_) => AuthResult::new("iprev", "fail"),
+                FcrDnsResult::Unknown => AuthResult::new("iprev", "temperror"),
+            };
+            session
+                .auth_results
+                .push(iprev.with_property("policy.iprev", ip.to_string()));
+            match result {
+                FcrDnsResult::Verified(name) => {
+                    let generic = Self::is_generic(name.as_str(), ip);
+                    session.verified_peer_name = Some(name.clone());
+                    match generic {
+                        true => Self::refuse(
+                            self.generic,
+                            format!("{} has a generic name {}", ip, name),
+                        ),
+                        false => OpenSessionResult::Accepted,
+                    }
+                }
+                FcrDnsResult::NoPtr => {
+                    Self::refuse(self.no_ptr, format!("{} has no PTR record", ip))
+                }
+                FcrDnsResult::Mismatch(names) => Self::refuse(
+                    self.mismatch,
+                    format!("{} PTR names {:?} do not resolve back", ip, names),
+                ),
+                FcrDnsResult::Unknown => {
+                    // do not fail permanently on DNS trouble
+                    let action = match (self.no_ptr, self.mismatch, self.generic) {
+                        (FcrDnsAction::Accept, FcrDnsAction::Accept, FcrDnsAction::Accept) => {
+                            FcrDnsAction::Accept
+                        }
+                        _ => FcrDnsAction::TempFail,
+                    };
+                    Self::refuse(action, format!("{} could not be verified", ip))
+                }
+            }
+        })
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samotop_core::dns::StaticResolver;
+    use std::net::Ipv4Addr;
+
+    fn dns() -> StaticResolver {
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let forged = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        StaticResolver::default()
+            .with_ptr(ip, "mx.example.org.")
+            .with_ip("mx.example.org", ip)
+            .with_ptr(forged, "forged.example.org.")
+    }
+
+    fn open(sut: &FcrDns, peer: &str) -> (OpenSessionResult, SmtpSession) {
+        let mut session = SmtpSession::default();
+        session.connection.peer_addr = peer.to_owned();
+        let res = async_std::task::block_on(sut.open_session(&mut session));
+        (res, session)
+    }
+
+    #[test]
+    fn verified_name_is_stored() {
+        let sut = FcrDns::default()
+            .with_resolver(dns())
+            .on_no_ptr(FcrDnsAction::Reject)
+            .on_mismatch(FcrDnsAction::Reject);
+        let (res, session) = open(&sut, "192.0.2.1:2525");
+        assert_eq!(res, OpenSessionResult::Accepted);
+        assert_eq!(
+            session.verified_peer_name,
+            Some("mx.example.org".to_owned())
+        );
+        assert_eq!(
+            session.auth_results[0].to_string(),
+            "iprev=pass reason=\"mx.example.org\" policy.iprev=192.0.2.1"
+        );
+    }
+
+    #[test]
+    fn missing_ptr_is_rejected() {
+        let sut = FcrDns::default()
+            .with_resolver(dns())
+            .on_no_ptr(FcrDnsAction::Reject);
+        let (res, session) = open(&sut, "192.0.2.3:2525");
+        assert!(matches!(
+            res,
+            OpenSessionResult::Failed(OpenSessionFailure::NoService, _)
+        ));
+        assert_eq!(session.verified_peer_name, None);
+        assert_eq!(
+            session.auth_results[0].to_string(),
+            "iprev=fail policy.iprev=192.0.2.3"
+        );
+    }
+
+    #[test]
+    fn mismatch_is_tempfailed() {
+        let sut = FcrDns::default()
+            .with_resolver(dns())
+            .on_mismatch(FcrDnsAction::TempFail);
+        let (res, _) = open(&sut, "192.0.2.2:2525");
+        assert!(matches!(
+            res,
+            OpenSessionResult::Failed(OpenSessionFailure::FailedTemporarily, _)
+        ));
+    }
+
+    #[test]
+    fn generic_names_are_recognized() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3));
+        assert!(FcrDns::is_generic("10-1-2-3.isp.net", ip));
+        assert!(FcrDns::is_generic("host3.2.1.10.in-addr.isp.net", ip));
+        assert!(FcrDns::is_generic("dsl.isp.net", ip));
+        assert!(!FcrDns::is_generic("mx1.example.org", ip));
+        let ip = "203.1.1.1".parse().expect("ip");
+        assert!(!FcrDns::is_generic("mx1.example.org", ip));
+        assert!(!FcrDns::is_generic("mx1-203.example.org", ip));
+        assert!(FcrDns::is_generic("203-001-001-001.isp.net", ip));
+    }
+}
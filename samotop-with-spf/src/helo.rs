@@ -0,0 +1,369 @@
+use crate::lookup::resolver_or_default;
+use samotop_core::{
+    common::*,
+    dns::{DnsError, DnsResolver},
+    mail::{
+        AcceptsGuard, AddRecipientResult, CheckHeloFailure, CheckHeloResult, MailGuard, MailSetup,
+        Recipient, StartMailResult,
+    },
+    smtp::{command::SmtpHelo, SmtpHost, SmtpSession},
+};
+use std::net::{IpAddr, SocketAddr};
+
+/// Validation of the host name the client greets with in HELO/EHLO/LHLO.
+///
+/// Each rule has a configurable `HeloAction`, all are accepted by default:
+/// * the host must be a fully qualified domain name or a well-formed address literal
+/// * an address literal must match the peer IP
+/// * the host must not be our own name or address
+/// * the domain name must resolve
+#[derive(Clone, Default)]
+pub struct HeloCheck {
+    resolver: Option<Arc<dyn DnsResolver>>,
+    own_names: Vec<String>,
+    invalid: HeloAction,
+    literal_mismatch: HeloAction,
+    own_name: HeloAction,
+    unresolvable: HeloAction,
+}
+
+/// What to do with a client failing a HELO rule
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeloAction {
+    /// Carry on with the session
+    #[default]
+    Accept,
+    /// Refuse the greeting with 501 for invalid names and 550 otherwise
+    Reject,
+    /// Carry on, but note the problem in `SmtpSession::tags`
+    Tag,
+}
+
+/// Problems found with the HELO host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeloProblem {
+    /// Neither a fully qualified domain name nor a well-formed address literal
+    Invalid,
+    /// The address literal does not match the peer IP
+    LiteralMismatch,
+    /// The client pretends to be us
+    OwnName,
+    /// The domain name does not resolve
+    Unresolvable,
+}
+
+impl fmt::Display for HeloProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HeloProblem::Invalid => "helo-invalid",
+            HeloProblem::LiteralMismatch => "helo-literal-mismatch",
+            HeloProblem::OwnName => "helo-own-name",
+            HeloProblem::Unresolvable => "helo-unresolvable",
+        })
+    }
+}
+
+impl HeloCheck {
+    /// Use the given resolver instead of the shared default one
+    pub fn with_resolver(mut self, resolver: impl DnsResolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    /// Consider the given name our own in addition to the service name
+    pub fn with_own_name(mut self, name: impl AsRef<str>) -> Self {
+        self.own_names.push(name.as_ref().to_ascii_lowercase());
+        self
+    }
+    /// What to do if the host is neither an FQDN nor a well-formed address literal
+    pub fn on_invalid(mut self, action: HeloAction) -> Self {
+        self.invalid = action;
+        self
+    }
+    /// What to do if the address literal does not match the peer IP
+    pub fn on_literal_mismatch(mut self, action: HeloAction) -> Self {
+        self.literal_mismatch = action;
+        self
+    }
+    /// What to do if the client greets with our own name or address
+    pub fn on_own_name(mut self, action: HeloAction) -> Self {
+        self.own_name = action;
+        self
+    }
+    /// What to do if the domain name does not resolve
+    pub fn on_unresolvable(mut self, action: HeloAction) -> Self {
+        self.unresolvable = action;
+        self
+    }
+    /// Is it a fully qualified domain name?
+    pub fn is_fqdn(name: &str) -> bool {
+        let name = name.strip_suffix('.').unwrap_or(name);
+        let labels = name.split('.').collect::<Vec<_>>();
+        labels.len() >= 2
+            && labels.iter().all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            && labels
+                .last()
+                .map(|tld| !tld.chars().all(|c| c.is_ascii_digit()))
+                .unwrap_or_default()
+    }
+    /// Check the rules that do not need DNS
+    pub fn check_local(&self, session: &SmtpSession, host: &SmtpHost) -> Vec<HeloProblem> {
+        let peer = parse_ip(session.connection.peer_addr.as_str());
+        let local = parse_ip(session.connection.local_addr.as_str());
+        let literal = match host {
+            SmtpHost::Domain(name) => {
+                if !Self::is_fqdn(name) {
+                    return vec![HeloProblem::Invalid];
+                }
+                let name = name.trim_end_matches('.').to_ascii_lowercase();
+                if name == session.service_name.to_ascii_lowercase()
+                    || self.own_names.contains(&name)
+                {
+                    return vec![HeloProblem::OwnName];
+                }
+                return vec![];
+            }
+            SmtpHost::Ipv4(ip) => IpAddr::V4(*ip),
+            SmtpHost::Ipv6(ip) => IpAddr::V6(*ip),
+            SmtpHost::Invalid { .. } => return vec![HeloProblem::Invalid],
+            // a general address literal cannot match the peer IP
+            SmtpHost::Other { .. } => return vec![HeloProblem::LiteralMismatch],
+        };
+        let mut problems = vec![];
+        if local.is_some() && local == Some(literal) {
+            problems.push(HeloProblem::OwnName);
+        }
+        if peer.is_some() && !same_ip(peer, literal) {
+            problems.push(HeloProblem::LiteralMismatch);
+        }
+        problems
+    }
+    /// Does the domain name resolve? None if DNS failed.
+    pub async fn resolves(&self, name: &str) -> Option<bool> {
+        let resolver = resolver_or_default(&self.resolver);
+        match resolver.lookup_a(name).await {
+            Ok(ips) if !ips.is_empty() => return Some(true),
+            Ok(_) | Err(DnsError::NotFound) => {}
+            Err(e) => {
+                warn!("A lookup of {} failed: {}", name, e);
+                return None;
+            }
+        }
+        match resolver.lookup_aaaa(name).await {
+            Ok(ips) => Some(!ips.is_empty()),
+            Err(DnsError::NotFound) => Some(false),
+            Err(e) => {
+                warn!("AAAA lookup of {} failed: {}", name, e);
+                None
+            }
+        }
+    }
+    fn action(&self, problem: HeloProblem) -> HeloAction {
+        match problem {
+            HeloProblem::Invalid => self.invalid,
+            HeloProblem::LiteralMismatch => self.literal_mismatch,
+            HeloProblem::OwnName => self.own_name,
+            HeloProblem::Unresolvable => self.unresolvable,
+        }
+    }
+    /// Apply the configured actions to the problems found
+    fn verdict(
+        &self,
+        session: &mut SmtpSession,
+        host: &SmtpHost,
+        problems: Vec<HeloProblem>,
+    ) -> CheckHeloResult {
+        for problem in problems {
+            match self.action(problem) {
+                HeloAction::Accept => {}
+                HeloAction::Tag => session.tags.push(format!("{} {}", problem, host)),
+                HeloAction::Reject => {
+                    let failure = match problem {
+                        HeloProblem::Invalid => CheckHeloFailure::InvalidName,
+                        _ => CheckHeloFailure::Rejected,
+                    };
+                    return CheckHeloResult::Failed(failure, format!("{} {}", problem, host));
+                }
+            }
+        }
+        CheckHeloResult::Accepted
+    }
+}
+
+pub(crate) fn parse_ip(addr: &str) -> Option<IpAddr> {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => Some(addr.ip()),
+        Err(_) => addr.parse::<IpAddr>().ok(),
+    }
+}
+
+fn same_ip(peer: Option<IpAddr>, literal: IpAddr) -> bool {
+    match (peer, literal) {
+        (Some(IpAddr::V6(peer)), IpAddr::V4(literal)) => peer.to_ipv4_mapped() == Some(literal),
+        (peer, literal) => peer == Some(literal),
+    }
+}
+
+impl fmt::Debug for HeloCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeloCheck")
+            .field("resolver", &self.resolver.as_ref().map(|_| "*"))
+            .field("own_names", &self.own_names)
+            .field("invalid", &self.invalid)
+            .field("literal_mismatch", &self.literal_mismatch)
+            .field("own_name", &self.own_name)
+            .field("unresolvable", &self.unresolvable)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for HeloCheck {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for HeloCheck {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn check_helo<'a, 's, 'h, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        helo: &'h SmtpHelo,
+    ) -> S2Fut<'f, CheckHeloResult>
+    where
+        'a: 'f,
+        's: 'f,
+        'h: 'f,
+    {
+        Box::pin(async move {
+            let mut problems = self.check_local(session, &helo.host);
+            if let (SmtpHost::Domain(name), true) = (&helo.host, problems.is_empty()) {
+                if self.unresolvable != HeloAction::Accept {
+                    match self.resolves(name).await {
+                        Some(true) => {}
+                        Some(false) => problems.push(HeloProblem::Unresolvable),
+                        None if self.unresolvable == HeloAction::Reject => {
+                            return CheckHeloResult::Failed(
+                                CheckHeloFailure::FailedTemporarily,
+                                format!("{} could not be resolved", helo.host),
+                            )
+                        }
+                        None => {}
+                    }
+                }
+            }
+            self.verdict(session, &helo.host, problems)
+        })
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samotop_core::dns::StaticResolver;
+    use std::net::Ipv4Addr;
+
+    fn check(sut: &HeloCheck, host: SmtpHost) -> (CheckHeloResult, SmtpSession) {
+        let mut session = SmtpSession {
+            service_name: "mx.example.org".to_owned(),
+            ..Default::default()
+        };
+        session.connection.peer_addr = "192.0.2.1:2525".to_owned();
+        session.connection.local_addr = "192.0.2.25:25".to_owned();
+        let helo = SmtpHelo {
+            verb: "EHLO".to_owned(),
+            host,
+        };
+        let res = async_std::task::block_on(sut.check_helo(&mut session, &helo));
+        (res, session)
+    }
+
+    #[test]
+    fn fqdn_is_recognized() {
+        assert!(HeloCheck::is_fqdn("mail.example.org."));
+        assert!(!HeloCheck::is_fqdn("localhost"));
+        assert!(!HeloCheck::is_fqdn("-bad.example.org"));
+        assert!(!HeloCheck::is_fqdn("192.0.2.1"));
+    }
+
+    #[test]
+    fn invalid_name_is_rejected_with_501() {
+        let sut = HeloCheck::default().on_invalid(HeloAction::Reject);
+        let (res, _) = check(&sut, SmtpHost::Domain("localhost".to_owned()));
+        assert!(matches!(
+            res,
+            CheckHeloResult::Failed(CheckHeloFailure::InvalidName, _)
+        ));
+    }
+
+    #[test]
+    fn own_name_is_rejected() {
+        let sut = HeloCheck::default().on_own_name(HeloAction::Reject);
+        let (res, _) = check(&sut, SmtpHost::Domain("MX.example.org".to_owned()));
+        assert!(matches!(
+            res,
+            CheckHeloResult::Failed(CheckHeloFailure::Rejected, _)
+        ));
+        let (res, _) = check(&sut, SmtpHost::Ipv4(Ipv4Addr::new(192, 0, 2, 25)));
+        assert!(matches!(
+            res,
+            CheckHeloResult::Failed(CheckHeloFailure::Rejected, _)
+        ));
+    }
+
+    #[test]
+    fn literal_mismatch_is_tagged() {
+        let sut = HeloCheck::default().on_literal_mismatch(HeloAction::Tag);
+        let (res, session) = check(&sut, SmtpHost::Ipv4(Ipv4Addr::new(192, 0, 2, 2)));
+        assert_eq!(res, CheckHeloResult::Accepted);
+        assert_eq!(session.tags, vec!["helo-literal-mismatch [192.0.2.2]"]);
+
+        let (res, session) = check(&sut, SmtpHost::Ipv4(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(res, CheckHeloResult::Accepted);
+        assert!(session.tags.is_empty());
+    }
+
+    #[test]
+    fn unresolvable_name_is_rejected() {
+        let dns = StaticResolver::default()
+            .with_ip("relay.example.com", IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let sut = HeloCheck::default()
+            .with_resolver(dns)
+            .on_unresolvable(HeloAction::Reject);
+        let (res, _) = check(&sut, SmtpHost::Domain("relay.example.com".to_owned()));
+        assert_eq!(res, CheckHeloResult::Accepted);
+        let (res, _) = check(&sut, SmtpHost::Domain("bot.example.com".to_owned()));
+        assert!(matches!(
+            res,
+            CheckHeloResult::Failed(CheckHeloFailure::Rejected, _)
+        ));
+    }
+}
@@ -1,16 +1,29 @@
 #[macro_use]
 extern crate log;
 
+mod fcrdns;
+mod helo;
 mod lookup;
+mod srs;
 
+pub use self::fcrdns::*;
+pub use self::helo::*;
+pub use self::srs::*;
+
+use self::helo::parse_ip;
 use self::lookup::*;
 use samotop_core::{
     common::*,
-    mail::{AcceptsDispatch, DispatchError, DispatchResult, MailDispatch, MailSetup},
-    smtp::{SmtpPath, SmtpSession},
+    dns::DnsResolver,
+    mail::{
+        AcceptsGuard, AddRecipientResult, AuthResult, MailGuard, MailSetup, Recipient,
+        StartMailFailure, StartMailResult,
+    },
+    smtp::{SmtpPath, SmtpReply, SmtpSession},
 };
+use std::net::IpAddr;
 pub use viaspf::Config;
-use viaspf::{evaluate_sender, SpfResult};
+use viaspf::{evaluate_sender, DomainName, Sender, SpfResult};
 
 /// enables checking for SPF records
 #[derive(Clone, Debug)]
@@ -21,97 +34,390 @@ impl Spf {
     pub fn with_config(self, config: Config) -> SpfWithConfig {
         SpfWithConfig {
             config: Arc::new(config),
+            resolver: None,
+            fail: SpfAction::Reject,
+            softfail: SpfAction::Tag,
+            temperror: SpfAction::TempFail,
+            permerror: SpfAction::Tag,
+            none: SpfAction::Accept,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// What to do with the mail for an SPF result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfAction {
+    /// Carry on with the mail
+    Accept,
+    /// Refuse the sender with 550
+    Reject,
+    /// Refuse the sender with 451 so that the client tries again later
+    TempFail,
+    /// Carry on, but flag the result with `policy.spf=tag` in the Authentication-Results
+    /// of the mail and note it in `Transaction::tags`
+    Tag,
+}
+
+/// SPF check of the sender when the mail transaction starts (RFC 7208).
+///
+/// The MAIL FROM identity is checked, or the HELO identity if the sender is null.
+/// The result is recorded in `session.transaction.auth_results` and the results
+/// fail, softfail, temperror, permerror and none each have a configurable `SpfAction`.
+/// By default, fail is rejected, temperror is tempfailed, softfail and permerror are tagged.
+#[derive(Clone)]
 pub struct SpfWithConfig {
     config: Arc<Config>,
+    resolver: Option<Arc<dyn DnsResolver>>,
+    fail: SpfAction,
+    softfail: SpfAction,
+    temperror: SpfAction,
+    permerror: SpfAction,
+    none: SpfAction,
+}
+
+impl SpfWithConfig {
+    /// Use the given resolver instead of the shared default one
+    pub fn with_resolver(mut self, resolver: impl DnsResolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    /// What to do if the client is not authorized to send for the domain
+    pub fn on_fail(mut self, action: SpfAction) -> Self {
+        self.fail = action;
+        self
+    }
+    /// What to do if the client is probably not authorized to send for the domain
+    pub fn on_softfail(mut self, action: SpfAction) -> Self {
+        self.softfail = action;
+        self
+    }
+    /// What to do if the check failed on a transient error, such as a DNS timeout
+    pub fn on_temperror(mut self, action: SpfAction) -> Self {
+        self.temperror = action;
+        self
+    }
+    /// What to do if the SPF record of the domain is broken
+    pub fn on_permerror(mut self, action: SpfAction) -> Self {
+        self.permerror = action;
+        self
+    }
+    /// What to do if the domain publishes no SPF record
+    pub fn on_none(mut self, action: SpfAction) -> Self {
+        self.none = action;
+        self
+    }
+    /// Check that the IP may send mail for the sender address.
+    /// The HELO domain is checked instead if the sender is empty.
+    pub async fn check(&self, ip: IpAddr, sender: &str, helo: &str) -> SpfResult {
+        let lookup = DnsLookup(resolver_or_default(&self.resolver));
+        self.check_with(&lookup, ip, sender, helo).await
+    }
+    async fn check_with(
+        &self,
+        resolver: &DnsLookup,
+        ip: IpAddr,
+        sender: &str,
+        helo: &str,
+    ) -> SpfResult {
+        let helo_domain = DomainName::new(helo).ok();
+        let sender = match sender.is_empty() {
+            true => Sender::from_domain(helo),
+            false => Sender::from_address(sender),
+        };
+        match sender {
+            Ok(sender) => {
+                let evaluation =
+                    evaluate_sender(resolver, &self.config, ip, &sender, helo_domain.as_ref());
+                match async_std::future::timeout(self.config.timeout(), evaluation).await {
+                    Ok(evaluation) => evaluation.spf_result,
+                    Err(_) => {
+                        warn!("SPF evaluation for {} timed out", ip);
+                        SpfResult::Temperror
+                    }
+                }
+            }
+            // there is no domain to check, such as with an address literal in HELO
+            Err(_) => SpfResult::None,
+        }
+    }
+    fn action(&self, result: &SpfResult) -> SpfAction {
+        match result {
+            SpfResult::Fail(_) => self.fail,
+            SpfResult::Softfail => self.softfail,
+            SpfResult::Temperror => self.temperror,
+            SpfResult::Permerror => self.permerror,
+            SpfResult::None => self.none,
+            SpfResult::Neutral | SpfResult::Pass => SpfAction::Accept,
+        }
+    }
 }
 
-impl<T: AcceptsDispatch> MailSetup<T> for SpfWithConfig {
+impl fmt::Debug for SpfWithConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpfWithConfig")
+            .field("config", &self.config)
+            .field("resolver", &self.resolver.as_ref().map(|_| "*"))
+            .field("fail", &self.fail)
+            .field("softfail", &self.softfail)
+            .field("temperror", &self.temperror)
+            .field("permerror", &self.permerror)
+            .field("none", &self.none)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for SpfWithConfig {
     fn setup(self, config: &mut T) {
-        config.add_last_dispatch(self)
+        config.add_last_guard(self)
     }
 }
-impl<T: AcceptsDispatch> MailSetup<T> for Spf {
+impl<T: AcceptsGuard> MailSetup<T> for Spf {
     fn setup(self, config: &mut T) {
-        config.add_last_dispatch(Spf.with_config(Config::default()))
+        config.add_last_guard(Spf.with_config(Config::default()))
     }
 }
 
-impl MailDispatch for SpfWithConfig {
-    fn open_mail_body<'a, 's, 'f>(
-        &'a self,
-        session: &'s mut SmtpSession,
-    ) -> S1Fut<'f, DispatchResult>
+impl MailGuard for SpfWithConfig {
+    fn is_check(&self) -> bool {
+        true
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
     where
         'a: 'f,
         's: 'f,
     {
-        let peer_addr = match session.connection.peer_addr.as_str().parse() {
-            Err(_) => std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
-            Ok(ip) => ip,
-        };
-        let peer_name = session.peer_name.clone().unwrap_or_default();
-        let sender = match session.transaction.mail.as_ref().map(|m| m.sender()) {
-            None | Some(SmtpPath::Null) | Some(SmtpPath::Postmaster) => String::new(),
-            Some(SmtpPath::Mailbox { host, .. }) => host.domain(),
-        };
-        let fut = async move {
+        Box::pin(async move {
+            let ip = match parse_ip(session.connection.peer_addr.as_str()) {
+                Some(ip) => ip,
+                // not an IP connection, such as a unix socket
+                None => return StartMailResult::Accepted,
+            };
+            let helo = session.peer_name.clone().unwrap_or_default();
+            let (sender, domain) = match session.transaction.mail.as_ref().map(|m| m.sender()) {
+                None | Some(SmtpPath::Null) | Some(SmtpPath::Postmaster) => {
+                    (String::new(), String::new())
+                }
+                Some(path @ SmtpPath::Mailbox { host, .. }) => (path.address(), host.domain()),
+            };
             // TODO: improve privacy - a) encrypt DNS, b) do DNS servers need to know who is receiving mail from whom?
-            let resolver = match new_resolver().await {
-                Err(e) => {
-                    error!("Could not crerate resolver! {:?}", e);
-                    return Err(DispatchError::Temporary);
+            // lookup futures are not Sync, running them in a task of their own
+            let spf = self.clone();
+            let (address, host) = (sender.clone(), helo.clone());
+            let result =
+                async_std::task::spawn(async move { spf.check(ip, &address, &host).await }).await;
+
+            let action = self.action(&result);
+            let auth = match result {
+                SpfResult::Fail(ref explanation) => {
+                    AuthResult::new("spf", "fail").with_reason(explanation.to_string())
                 }
-                Ok(resolver) => resolver,
+                ref result => AuthResult::new("spf", result.to_string()),
+            };
+            // DMARC checks the alignment of the sender domain
+            let auth = match sender.is_empty() {
+                true => auth.with_property("smtp.helo", helo.as_str()),
+                false => auth.with_property("smtp.mailfrom", domain.as_str()),
+            };
+            session.transaction.auth_results.push(match action {
+                SpfAction::Tag => auth.with_property("policy.spf", "tag"),
+                _ => auth,
+            });
+
+            let identity = match sender.is_empty() {
+                true => helo,
+                false => sender,
             };
-            let evaluation = evaluate_sender(
-                &resolver,
-                &self.config,
-                peer_addr,
-                &sender.parse().map_err(|e| {
-                    error!("Could not parse sender {:?}, {}", sender, e);
-                    DispatchError::Temporary
-                })?,
-                Some(&peer_name.parse().map_err(|e| {
-                    error!("Could not parse peer domain {:?}, {}", peer_name, e);
-                    DispatchError::Temporary
-                })?),
-            )
-            .await;
-            match evaluation.spf_result {
-                SpfResult::Fail(explanation) => {
-                    info!("mail rejected due to SPF fail: {}", explanation);
-                    Err(DispatchError::Permanent)
+            match action {
+                SpfAction::Reject => {
+                    let text = match result {
+                        SpfResult::Fail(ref explanation) if !explanation.to_string().is_empty() => {
+                            format!("5.7.23 SPF fail for {}: {}", identity, explanation)
+                        }
+                        SpfResult::Temperror | SpfResult::Permerror => {
+                            format!("5.7.24 SPF {} for {}", result, identity)
+                        }
+                        _ => format!("5.7.23 SPF {} for {}", result, identity),
+                    };
+                    StartMailResult::Failed(
+                        StartMailFailure::Custom(SmtpReply::Custom(550, single_line(text))),
+                        format!("SPF {} for {} from {}", result, identity, ip),
+                    )
                 }
-                result => {
+                SpfAction::TempFail => StartMailResult::Failed(
+                    StartMailFailure::Custom(SmtpReply::Custom(
+                        451,
+                        format!("4.7.24 SPF {} for {}, try again later", result, identity),
+                    )),
+                    format!("SPF {} for {} from {}", result, identity, ip),
+                ),
+                action => {
                     debug!("mail OK with SPF result: {}", result);
-                    session
-                        .transaction
-                        .extra_headers
-                        .push_str(format!("X-Samotop-SPF: {}\r\n", result).as_str());
-                    Ok(())
+                    if action == SpfAction::Tag {
+                        session
+                            .transaction
+                            .tags
+                            .push(format!("spf-{} {}", result, identity));
+                    }
+                    StartMailResult::Accepted
                 }
             }
-        };
+        })
+    }
 
-        Box::pin(fut)
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
     }
 }
 
+/// The explanation comes from the DNS and must not break the reply
+fn single_line(text: String) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use samotop_core::{
+        dns::{DnsRecordType, StaticResolver},
+        mail::StartMailFailure,
+        smtp::{command::SmtpMail, SmtpHost},
+    };
+
+    fn dns() -> StaticResolver {
+        StaticResolver::default()
+            .with_txt("example.com", "v=spf1 ip4:192.0.2.1 -all")
+            .with_txt("example.net", "v=spf1 ip4:192.0.2.1 ~all")
+            .with_txt("mail.example.org", "v=spf1 a -all")
+            .with_ip("mail.example.org", "192.0.2.2".parse().expect("ip"))
+            .with_failure("example.info", DnsRecordType::Txt)
+    }
+
+    fn start(sut: &SpfWithConfig, peer: &str, sender: SmtpPath) -> (StartMailResult, SmtpSession) {
+        let mut session = SmtpSession {
+            peer_name: Some("mail.example.org".to_owned()),
+            ..Default::default()
+        };
+        session.connection.peer_addr = peer.to_owned();
+        session.transaction.mail = Some(SmtpMail::Mail(sender, vec![]));
+        let res = async_std::task::block_on(sut.start_mail(&mut session));
+        (res, session)
+    }
+
+    fn mailbox(name: &str, domain: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        }
+    }
+
+    #[test]
+    fn pass_is_accepted() {
+        let sut = Spf.with_config(Config::default()).with_resolver(dns());
+        let (res, session) = start(&sut, "192.0.2.1:2525", mailbox("joe", "example.com"));
+        assert_eq!(res, StartMailResult::Accepted);
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "spf=pass smtp.mailfrom=example.com"
+        );
+    }
+
+    #[test]
+    fn fail_is_rejected_at_mail() {
+        let sut = Spf.with_config(Config::default()).with_resolver(dns());
+        let (res, _) = start(&sut, "192.0.2.9:2525", mailbox("joe", "example.com"));
+        match res {
+            StartMailResult::Failed(StartMailFailure::Custom(reply), _) => {
+                assert_eq!(reply.code(), 550)
+            }
+            otherwise => panic!("Expected rejection, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn softfail_is_tagged_or_tempfailed() {
+        let sut = Spf.with_config(Config::default()).with_resolver(dns());
+        let (res, session) = start(&sut, "192.0.2.9:2525", mailbox("joe", "example.net"));
+        assert_eq!(res, StartMailResult::Accepted);
+        assert_eq!(
+            session.transaction.tags,
+            vec!["spf-softfail joe@example.net"]
+        );
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "spf=softfail smtp.mailfrom=example.net policy.spf=tag"
+        );
+        assert!(session.tags.is_empty());
+
+        let sut = sut.on_softfail(SpfAction::TempFail);
+        let (res, _) = start(&sut, "192.0.2.9:2525", mailbox("joe", "example.net"));
+        match res {
+            StartMailResult::Failed(StartMailFailure::Custom(reply), _) => {
+                assert_eq!(reply.code(), 451)
+            }
+            otherwise => panic!("Expected temporary failure, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn temperror_is_tempfailed() {
+        let sut = Spf.with_config(Config::default()).with_resolver(dns());
+        let (res, session) = start(&sut, "192.0.2.1:2525", mailbox("joe", "example.info"));
+        match res {
+            StartMailResult::Failed(StartMailFailure::Custom(reply), _) => {
+                assert_eq!(reply.code(), 451)
+            }
+            otherwise => panic!("Expected temporary failure, got {:?}", otherwise),
+        }
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "spf=temperror smtp.mailfrom=example.info"
+        );
+    }
+
+    #[test]
+    fn none_can_be_rejected() {
+        let sut = Spf
+            .with_config(Config::default())
+            .with_resolver(dns())
+            .on_none(SpfAction::Reject);
+        let (res, session) = start(&sut, "192.0.2.1:2525", mailbox("joe", "example.biz"));
+        assert!(matches!(res, StartMailResult::Failed(_, _)));
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "spf=none smtp.mailfrom=example.biz"
+        );
+    }
+
+    #[test]
+    fn null_sender_is_checked_by_helo() {
+        let sut = Spf.with_config(Config::default()).with_resolver(dns());
+        let (res, session) = start(&sut, "192.0.2.2:2525", SmtpPath::Null);
+        assert_eq!(res, StartMailResult::Accepted);
+        assert_eq!(
+            session.transaction.auth_results.to_vec()[0].to_string(),
+            "spf=pass smtp.helo=mail.example.org"
+        );
+
+        let (res, _) = start(&sut, "192.0.2.9:2525", SmtpPath::Null);
+        assert!(matches!(res, StartMailResult::Failed(_, _)));
+    }
 
     #[test]
     fn default_mail_fut_is_sync() {
         let mut sess = SmtpSession::default();
         let cfg = Config::default();
         let sut = Spf.with_config(cfg);
-        let fut = sut.open_mail_body(&mut sess);
+        let fut = sut.start_mail(&mut sess);
         is_send(fut);
     }
 
@@ -0,0 +1,482 @@
+use samotop_core::{
+    common::*,
+    mail::{
+        AcceptsGuard, AddRecipientFailure, AddRecipientResult, MailGuard, MailSetup, Recipient,
+        StartMailResult,
+    },
+    smtp::{SmtpHost, SmtpPath, SmtpSession},
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sender Rewriting Scheme, so that forwarded mail passes SPF at the next hop.
+///
+/// When a recipient is not local and the sender is not local either,
+/// the envelope sender is rewritten to an SRS0 address at our domain,
+/// such as `SRS0=HHHH=TT=example.org=user@forwarder.example.net`.
+/// A sender that is an SRS0 address already becomes an SRS1 address pointing at the previous hop.
+/// The rewrite applies to the whole transaction, local recipients included.
+///
+/// Bounces to SRS addresses at our domain are decoded back to the original sender.
+/// The hash must match one of the secrets and the timestamp must not be older than `max_age`,
+/// otherwise the recipient is rejected so that we do not become a backscatter relay.
+///
+/// Set it up after any recipient mapping, such as `Mapper`.
+#[derive(Clone)]
+pub struct Srs {
+    domain: String,
+    secrets: Vec<Vec<u8>>,
+    local_domains: Vec<String>,
+    max_age: u32,
+}
+
+/// Why an SRS address could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SrsError {
+    /// The address is not an SRS address
+    NotSrs,
+    /// The address looks like SRS but the parts are missing
+    Malformed,
+    /// The hash does not match, the address was forged
+    BadHash,
+    /// The address is older than allowed
+    Expired,
+}
+
+impl fmt::Display for SrsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SrsError::NotSrs => "not an SRS address",
+            SrsError::Malformed => "malformed SRS address",
+            SrsError::BadHash => "invalid SRS hash",
+            SrsError::Expired => "expired SRS address",
+        })
+    }
+}
+
+impl std::error::Error for SrsError {}
+
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const HASH_LENGTH: usize = 4;
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+/// The timestamp has two base32 characters of days
+const TIMESTAMP_PERIOD: u32 = 1024;
+
+impl Srs {
+    /// Rewrite senders to our domain, signing the addresses with the secret
+    pub fn new(secret: impl AsRef<[u8]>, domain: impl Into<String>) -> Self {
+        let domain = domain.into().to_ascii_lowercase();
+        Self {
+            secrets: vec![secret.as_ref().to_vec()],
+            local_domains: vec![domain.clone()],
+            domain,
+            max_age: 21,
+        }
+    }
+    /// Also accept bounces signed with this secret, such as a previous one after rotation
+    pub fn with_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.secrets.push(secret.as_ref().to_vec());
+        self
+    }
+    /// Mail for and from this domain is not forwarded, so it is not rewritten
+    pub fn with_local_domain(mut self, domain: impl Into<String>) -> Self {
+        self.local_domains.push(domain.into().to_ascii_lowercase());
+        self
+    }
+    /// How many days a bounce may come back after the mail was forwarded
+    pub fn with_max_age(mut self, days: u32) -> Self {
+        self.max_age = days.min(TIMESTAMP_PERIOD - 1);
+        self
+    }
+    /// Is the domain one of ours?
+    pub fn is_local(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        self.local_domains
+            .iter()
+            .any(|local| local.eq_ignore_ascii_case(domain))
+    }
+
+    /// The SRS address to use as the envelope sender of the forwarded mail
+    pub fn forward(&self, local: &str, domain: &str) -> String {
+        self.forward_at(local, domain, today())
+    }
+    fn forward_at(&self, local: &str, domain: &str, day: u32) -> String {
+        if let Some(rest) = srs_tail(local, "SRS0") {
+            // the previous hop rewrote it already, point back at the hop
+            let hash = self.hash(&[domain, rest]);
+            format!("SRS1={}={}={}@{}", hash, domain, rest, self.domain)
+        } else if let Some(tail) = srs_tail(local, "SRS1") {
+            // keep the first hop so that the bounce does not travel the whole chain
+            if let [_, hop, rest] = tail[1..].splitn(3, '=').collect::<Vec<_>>()[..] {
+                let hash = self.hash(&[hop, rest]);
+                return format!("SRS1={}={}={}@{}", hash, hop, rest, self.domain);
+            }
+            self.srs0(local, domain, day)
+        } else {
+            self.srs0(local, domain, day)
+        }
+    }
+    fn srs0(&self, local: &str, domain: &str, day: u32) -> String {
+        let timestamp = timestamp(day);
+        let hash = self.hash(&[timestamp.as_str(), domain, local]);
+        format!(
+            "SRS0={}={}={}={}@{}",
+            hash, timestamp, domain, local, self.domain
+        )
+    }
+
+    /// The original address the SRS local part stands for
+    pub fn reverse(&self, local: &str) -> std::result::Result<(String, String), SrsError> {
+        self.reverse_at(local, today())
+    }
+    fn reverse_at(&self, local: &str, day: u32) -> std::result::Result<(String, String), SrsError> {
+        if let Some(tail) = srs_tail(local, "SRS0") {
+            match tail[1..].splitn(4, '=').collect::<Vec<_>>()[..] {
+                [hash, timestamp, domain, local] if !domain.is_empty() && !local.is_empty() => {
+                    self.verify(hash, &[timestamp, domain, local])?;
+                    let age = age(timestamp, day).ok_or(SrsError::Malformed)?;
+                    if age > self.max_age {
+                        return Err(SrsError::Expired);
+                    }
+                    Ok((local.to_owned(), domain.to_owned()))
+                }
+                _ => Err(SrsError::Malformed),
+            }
+        } else if let Some(tail) = srs_tail(local, "SRS1") {
+            match tail[1..].splitn(3, '=').collect::<Vec<_>>()[..] {
+                [hash, hop, rest] if !hop.is_empty() && !rest.is_empty() => {
+                    self.verify(hash, &[hop, rest])?;
+                    Ok((format!("SRS0{}", rest), hop.to_owned()))
+                }
+                _ => Err(SrsError::Malformed),
+            }
+        } else {
+            Err(SrsError::NotSrs)
+        }
+    }
+
+    fn hash(&self, parts: &[&str]) -> String {
+        hash(self.secrets[0].as_slice(), parts)
+    }
+    fn verify(&self, hash: &str, parts: &[&str]) -> std::result::Result<(), SrsError> {
+        if self
+            .secrets
+            .iter()
+            .any(|secret| self::hash(secret, parts).eq_ignore_ascii_case(hash))
+        {
+            Ok(())
+        } else {
+            Err(SrsError::BadHash)
+        }
+    }
+}
+
+/// The rest of the local part after the SRS tag, starting with the separator
+fn srs_tail<'l>(local: &'l str, tag: &str) -> Option<&'l str> {
+    let prefix = local.get(..tag.len())?;
+    let tail = &local[tag.len()..];
+    if prefix.eq_ignore_ascii_case(tag) && tail.starts_with(['=', '+', '-'].as_ref()) {
+        Some(tail)
+    } else {
+        None
+    }
+}
+
+fn hash(secret: &[u8], parts: &[&str]) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let mut context = ring::hmac::Context::with_key(&key);
+    for part in parts {
+        context.update(part.to_ascii_lowercase().as_bytes());
+    }
+    let mut hash = base64::encode(context.sign().as_ref());
+    hash.truncate(HASH_LENGTH);
+    hash
+}
+
+fn today() -> u32 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    (seconds / SECONDS_PER_DAY % TIMESTAMP_PERIOD as u64) as u32
+}
+
+fn timestamp(day: u32) -> String {
+    let day = day % TIMESTAMP_PERIOD;
+    [BASE32[(day >> 5) as usize & 31], BASE32[day as usize & 31]]
+        .iter()
+        .map(|c| *c as char)
+        .collect()
+}
+
+/// Days since the timestamp, it wraps around every 1024 days
+fn age(timestamp: &str, day: u32) -> Option<u32> {
+    let mut then = 0;
+    for c in timestamp.to_ascii_uppercase().bytes() {
+        then = then << 5 | BASE32.iter().position(|b| *b == c)? as u32;
+    }
+    if timestamp.len() != 2 {
+        return None;
+    }
+    Some((day % TIMESTAMP_PERIOD + TIMESTAMP_PERIOD - then) % TIMESTAMP_PERIOD)
+}
+
+impl fmt::Debug for Srs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Srs")
+            .field("domain", &self.domain)
+            .field("local_domains", &self.local_domains)
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for Srs {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for Srs {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        mut rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let (name, host) = match &rcpt.address {
+            SmtpPath::Mailbox {
+                name,
+                host: SmtpHost::Domain(host),
+                ..
+            } => (name.clone(), host.clone()),
+            _ => return Box::pin(ready(AddRecipientResult::Inconclusive(rcpt))),
+        };
+
+        if self.is_local(host.as_str()) {
+            match self.reverse(name.as_str()) {
+                Ok((name, host)) => {
+                    debug!(
+                        "Decoded SRS recipient {} into {}@{}",
+                        rcpt.address, name, host
+                    );
+                    rcpt.address = SmtpPath::Mailbox {
+                        name,
+                        host: SmtpHost::Domain(host),
+                        relays: vec![],
+                    };
+                }
+                Err(SrsError::NotSrs) => {}
+                Err(e) => {
+                    let reason = format!("{} {}", e, rcpt.address);
+                    return Box::pin(ready(AddRecipientResult::Failed(
+                        AddRecipientFailure::RejectedPermanently,
+                        reason,
+                    )));
+                }
+            }
+            return Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)));
+        }
+
+        // forwarding to a foreign domain
+        if let Some(mail) = session.transaction.mail.as_mut() {
+            let sender = mail.sender_mut();
+            let rewritten = match &*sender {
+                SmtpPath::Mailbox {
+                    name,
+                    host: SmtpHost::Domain(domain),
+                    ..
+                } if !self.is_local(domain.as_str()) => {
+                    Some(self.forward(name.as_str(), domain.as_str()))
+                }
+                _ => None,
+            };
+            if let Some(address) = rewritten {
+                debug!("Rewriting sender {} to SRS <{}>", sender, address);
+                let name = &address[..address.rfind('@').unwrap_or(address.len())];
+                *sender = SmtpPath::Mailbox {
+                    name: name.to_owned(),
+                    host: SmtpHost::Domain(self.domain.clone()),
+                    relays: vec![],
+                };
+            }
+        }
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samotop_core::smtp::command::SmtpMail;
+
+    fn sut() -> Srs {
+        Srs::new("secret", "forwarder.example.net").with_local_domain("example.net")
+    }
+
+    fn mailbox(name: &str, domain: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        }
+    }
+
+    #[test]
+    fn srs0_roundtrip() {
+        let sut = sut();
+        let address = sut.forward_at("user", "example.org", 100);
+        let (local, domain) = address.split_at(address.rfind('@').expect("@"));
+        assert!(local.starts_with("SRS0="), "{}", address);
+        assert!(local.ends_with("=DE=example.org=user"), "{}", address);
+        assert_eq!(domain, "@forwarder.example.net");
+        assert_eq!(
+            sut.reverse_at(local, 110),
+            Ok(("user".to_owned(), "example.org".to_owned()))
+        );
+        // case may get lost on the way
+        assert_eq!(
+            sut.reverse_at(local.to_ascii_lowercase().as_str(), 110),
+            Ok(("user".to_owned(), "example.org".to_owned()))
+        );
+    }
+
+    #[test]
+    fn srs0_is_validated() {
+        let sut = sut();
+        let address = sut.forward_at("user", "example.org", 1020);
+        let local = &address[..address.rfind('@').expect("@")];
+        assert_eq!(sut.reverse_at(local, 1050), Err(SrsError::Expired));
+        assert!(sut.reverse_at(local, 3).is_ok(), "the timestamp wraps");
+        let forged = local.replacen("example.org", "example.com", 1);
+        assert_eq!(sut.reverse_at(&forged, 1021), Err(SrsError::BadHash));
+        assert_eq!(
+            Srs::new("other", "forwarder.example.net").reverse_at(local, 1021),
+            Err(SrsError::BadHash)
+        );
+        assert!(Srs::new("other", "forwarder.example.net")
+            .with_secret("secret")
+            .reverse_at(local, 1021)
+            .is_ok());
+        assert_eq!(sut.reverse_at("SRS0=abcd", 1021), Err(SrsError::Malformed));
+        assert_eq!(sut.reverse_at("srsuser", 1021), Err(SrsError::NotSrs));
+    }
+
+    #[test]
+    fn srs1_points_at_the_first_hop() {
+        let first = Srs::new("first", "first.example.com");
+        let address = first.forward_at("user", "example.org", 100);
+        let srs0 = &address[..address.rfind('@').expect("@")];
+
+        let sut = sut();
+        let srs1 = sut.forward_at(srs0, "first.example.com", 101);
+        let local = &srs1[..srs1.rfind('@').expect("@")];
+        assert!(
+            local.starts_with("SRS1=")
+                && local.ends_with(&format!("=first.example.com={}", &srs0[4..])),
+            "{}",
+            srs1
+        );
+        assert_eq!(
+            sut.reverse_at(local, 300),
+            Ok((srs0.to_owned(), "first.example.com".to_owned()))
+        );
+
+        // another forwarder keeps the first hop
+        let third = Srs::new("third", "third.example.com");
+        let again = third.forward_at(local, "forwarder.example.net", 102);
+        let again = &again[..again.rfind('@').expect("@")];
+        assert!(again.contains("=first.example.com=="), "{}", again);
+        assert_eq!(
+            third.reverse_at(again, 102),
+            Ok((srs0.to_owned(), "first.example.com".to_owned()))
+        );
+    }
+
+    #[test]
+    fn forwarded_mail_sender_is_rewritten() {
+        let sut = sut();
+        let mut session = SmtpSession::default();
+        session.transaction.mail = Some(SmtpMail::Mail(mailbox("user", "example.org"), vec![]));
+
+        let local = Recipient::new(mailbox("me", "example.net"));
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, local));
+        assert!(matches!(res, AddRecipientResult::Inconclusive(_)));
+        let sender = session.transaction.mail.as_ref().expect("mail").sender();
+        assert_eq!(sender, &mailbox("user", "example.org"));
+
+        let foreign = Recipient::new(mailbox("me", "example.com"));
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, foreign));
+        match res {
+            AddRecipientResult::Inconclusive(rcpt) => {
+                assert_eq!(rcpt.address, mailbox("me", "example.com"))
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        let sender = session
+            .transaction
+            .mail
+            .as_ref()
+            .expect("mail")
+            .sender()
+            .address();
+        assert!(sender.starts_with("SRS0="), "{}", sender);
+        assert!(
+            sender.ends_with("=example.org=user@forwarder.example.net"),
+            "{}",
+            sender
+        );
+
+        // another foreign recipient does not rewrite it again
+        let foreign = Recipient::new(mailbox("you", "example.com"));
+        async_std::task::block_on(sut.add_recipient(&mut session, foreign));
+        let again = session.transaction.mail.as_ref().expect("mail").sender();
+        assert_eq!(again.address(), sender);
+    }
+
+    #[test]
+    fn bounce_is_decoded_or_rejected() {
+        let sut = sut();
+        let address = sut.forward("user", "example.org");
+        let local = &address[..address.rfind('@').expect("@")];
+        let mut session = SmtpSession::default();
+        session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
+
+        let bounce = Recipient::new(mailbox(local, "forwarder.example.net"));
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, bounce));
+        match res {
+            AddRecipientResult::Inconclusive(rcpt) => {
+                assert_eq!(rcpt.address, mailbox("user", "example.org"))
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert_eq!(
+            session.transaction.mail.as_ref().expect("mail").sender(),
+            &SmtpPath::Null
+        );
+
+        let forged = Recipient::new(mailbox(
+            "SRS0=AAAA=AA=example.org=user",
+            "forwarder.example.net",
+        ));
+        let res = async_std::task::block_on(sut.add_recipient(&mut session, forged));
+        assert!(
+            matches!(
+                res,
+                AddRecipientResult::Failed(AddRecipientFailure::RejectedPermanently, _)
+            ),
+            "{:?}",
+            res
+        );
+    }
+}
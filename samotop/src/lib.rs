@@ -40,23 +40,37 @@ The [samotop crate is published on crates.io](https://crates.io/crates/samotop).
 - [x] Integration: LMTP socket - can deliver to LDA over unix or network sockets using LMTP
 - [x] Integration: LMTP child process - can deliver to LDA using LMTP protocol over io with a child process
 - [x] LDA: Can process LMTP session (LHLO + delivery status per rcpt)
-- [x] Antispam: Reject mails failing SPF checks - through `viaspf` crate, now async
+- [x] Integration: Recipient verification callout to the LDA - `RecipientCallout`
+- [x] Antispam: Reject mails failing SPF checks at MAIL FROM with configurable actions, HELO checked for null senders - through `viaspf` crate, now async
+- [x] Antispam: DKIM signature verification of incoming mail - `Dkim`
+- [x] Antispam: DMARC policy evaluation with aggregate report data - `Dmarc`
+- [x] MTA: DKIM signing of outgoing mail - `DkimSigningTransport` in samotop-delivery
+- [x] MTA: Sender Rewriting Scheme (SRS0/SRS1) for forwarded mail, bounces decoded and validated - `Srs`
+- [x] MTA: Aliases and virtual domains from sendmail/postfix maps, one-to-many, catch-all, loop detection, reload on change - `Aliases`
+- [x] MDA: Sub-addressing - `user+tag` delivered to `user`, tag and original address kept - `SubAddressing`, per recipient `Delivered-To`, `X-Original-To` and tag folders - `MailDir`
+- [x] Antispam: Authentication-Results and Received-SPF headers, forged ones removed - `AuthResultsHeader`
 - [x] Antispam: Strict SMTP - require CRLF
-- [x] Antispam: Strict SMTP - reject session if client sends mail before banner - `Prudence`
+- [x] Antispam: Strict SMTP - delay the banner, reject session if client sends mail before banner, "220-" pre-greeting - `Prudence`
 - [x] Anti-abuse: Command timeout - `Impatience`
+- [x] Anti-abuse: Limit recipients, transactions and bad commands, tarpitting - `Prudence`
+- [x] Anti-abuse: Slowloris protection - session and DATA duration, DATA throughput - `Prudence`
+- [x] Anti-abuse: Open relay prevention - local domains, trusted networks and authenticated clients - `RelayPolicy`
+- [x] Privacy: Refuse unencrypted session - `RequireTls`
+- [x] Antispam: reverse lookup - forward-confirmed reverse DNS with `FcrDns`
+- [x] Antispam: HELO validation - `HeloCheck`
+- [x] Antispam: sender address verification - MX check and callout with `SenderCallout`
+- [x] DNS: shared resolver with a TTL cache for all checks, injectable with `with_resolver`, `StaticResolver` for offline tests - `DnsResolver`
+- [x] Antispam: whitelist and blacklist - `AccessList`
+- [x] Antispam: white/black/grey list with UI - user decides new contact handling - `Contacts` API for the UI
 - [x] Extensibility: Modular and composable service - `Builder` + `Configuration` + `MailSetup` => `Service`
 
 ## To do
 
 - [ ] Accounts: Self service account subscription through SMTP/IMAP
 - [ ] MTA: Queue and queue manager, relay mail to another MTA
-- [ ] Antispam: whitelist and blacklist
 - [ ] Antispam: greylisting
-- [ ] Antispam: white/black/grey list with UI - user decides new contact handling
 - [ ] Antispam: is it encrypted?
-- [ ] Antispam: reverse lookup
 - [ ] Antispam: DANE (DNSSEC) with UI - user verifies signatures
-- [ ] Privacy: Refuse unencrypted session
 - [ ] Privacy: Leave no trace, no logs, obfuscated file dates...
 
 # Installation
@@ -86,7 +100,7 @@ There are a few interesting provisions one could take away from Samotop:
 
 Running an SMTP server with STARTTLS support is a bit more involved
 regarding setting up the TLS configuration. The library includes a `TlsProvider`
-implementation for async-tls (rustls) and async-native-tls (native-tls).
+implementation for futures-rustls (rustls) and async-native-tls (native-tls).
 The samotop-server is a working reference for this TLS setup
 where you need to provide only the cert and key.
 You can also implement your own `TlsProvider` and plug it in.
@@ -201,6 +215,8 @@ pub mod mail;
 pub mod server;
 pub mod smtp;
 
+pub use samotop_core::dns;
+
 mod common {
     pub use samotop_core::common::*;
 
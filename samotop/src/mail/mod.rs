@@ -17,5 +17,8 @@ pub use samotop_delivery::prelude::*;
 #[cfg(feature = "spf")]
 pub use samotop_with_spf as spf;
 
+#[cfg(feature = "dkim")]
+pub use samotop_with_dkim as dkim;
+
 #[cfg(feature = "smime")]
 pub use samotop_smime as smime;
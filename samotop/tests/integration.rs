@@ -152,6 +152,128 @@ mod int_tests {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn prudent_holds_banner_from_early_talker() {
+        let read = Cursor::new("ehlo macca\r\n");
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + Prudence::default().with_banner_delay(Duration::from_millis(50));
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await
+            .expect("good handling");
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await.expect("response").as_slice()),
+        @r###""451 Requested action aborted: error in processing.\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+    }
+
+    #[async_std::test]
+    async fn prudent_sends_pre_greeting() -> Result<()> {
+        let read = DelayRead::new(100, Cursor::new("ehlo macca\r\n"));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + Prudence::default()
+                .with_banner_delay(Duration::from_millis(50))
+                .with_pre_greeting("prudic ESMTP");
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220-prudic ESMTP\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220 prudic service ready\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""250 prudic greets macca\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_catches_bot_after_pre_greeting() -> Result<()> {
+        let read = Cursor::new("ehlo macca\r\n");
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + Prudence::default()
+                .with_banner_delay(Duration::from_millis(50))
+                .with_pre_greeting("prudic ESMTP");
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220-prudic ESMTP\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""451 Requested action aborted: error in processing.\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_exempts_peers_from_banner_delay() -> Result<()> {
+        let read = Cursor::new("ehlo macca\r\n");
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + Prudence::default()
+                .with_banner_delay(Duration::from_secs(10))
+                .with_pre_greeting("prudic ESMTP")
+                .with_banner_delay_exemption("10.0.0.0".parse()?, 8);
+
+        service
+            .build()
+            .handle(
+                Ok(io),
+                ConnectionInfo::new("127.0.0.1:25".to_owned(), "10.1.2.3:4567".to_owned()),
+            )
+            .timeout(Duration::from_secs(1))
+            .await??;
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220 prudic service ready\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""250 prudic greets macca\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+
+        Ok(())
+    }
+
     #[async_std::test]
     async fn prudent_enforces_timeout() -> Result<()> {
         let read = Cursor::new("ehlo macca\r\n")
@@ -184,6 +306,232 @@ mod int_tests {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn prudent_limits_recipients_and_transactions() -> Result<()> {
+        let read = Cursor::new(concat!(
+            "ehlo macca\r\n",
+            "mail from:<>\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "rset\r\n",
+            "mail from:<>\r\n",
+        ));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + NullDispatch
+            + Prudence::default()
+                .with_max_recipients(1)
+                .with_max_transactions(1);
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        let mut replies = vec![];
+        while let Ok(reply) = writes.recv().await {
+            replies.push(String::from_utf8_lossy(reply.as_slice()).to_string());
+        }
+        assert_eq!(replies.len(), 7, "{:?}", replies);
+        assert_eq!(replies[4], "452 Too many recipients\r\n");
+        assert_eq!(
+            replies[6],
+            "421 prudic service not available, closing transmission channel\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_counts_every_mail_command() -> Result<()> {
+        let read = Cursor::new(concat!(
+            "ehlo macca\r\n",
+            "mail from:<>\r\n",
+            "mail from:<>\r\n",
+            "mail from:<>\r\n",
+        ));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + NullDispatch
+            + Prudence::default().with_max_transactions(2);
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        let mut replies = vec![];
+        while let Ok(reply) = writes.recv().await {
+            replies.push(String::from_utf8_lossy(reply.as_slice()).to_string());
+        }
+        assert_eq!(replies.len(), 5, "{:?}", replies);
+        assert!(replies[3].starts_with("250 "), "{:?}", replies);
+        assert_eq!(
+            replies[4],
+            "421 prudic service not available, closing transmission channel\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_limits_bad_commands() -> Result<()> {
+        let read = Cursor::new(concat!(
+            "ehlo macca\r\n",
+            "bugy command nonsense\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "quit\r\n",
+        ));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + Prudence::default()
+                .with_max_errors(2)
+                .with_error_delay(Duration::from_millis(10));
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220 prudic service ready\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""250 prudic greets macca\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""500 Syntax error, command unrecognized\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""503 Bad sequence of commands\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""421 prudic service not available, closing transmission channel\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_limits_session_duration() -> Result<()> {
+        let read =
+            Cursor::new("ehlo macca\r\n").chain(DelayRead::new(10000, Cursor::new("rset\r\n")));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + Prudence::default().with_max_session_duration(Duration::from_millis(50));
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220 prudic service ready\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""250 prudic greets macca\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""421 prudic service not available, closing transmission channel\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_enforces_data_throughput() -> Result<()> {
+        let read = Cursor::new(concat!(
+            "ehlo macca\r\n",
+            "mail from:<>\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "data\r\n",
+        ))
+        .chain(DelayRead::new(200, Cursor::new("Subject: slow\r\n")));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + NullDispatch
+            + Prudence::default().with_min_data_throughput(1000, Duration::from_millis(50));
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        let mut replies = vec![];
+        while let Ok(reply) = writes.recv().await {
+            replies.push(String::from_utf8_lossy(reply.as_slice()).to_string());
+        }
+        assert_eq!(replies.len(), 6, "{:?}", replies);
+        assert!(replies[4].starts_with("354 "));
+        assert_eq!(
+            replies[5],
+            "421 prudic service not available, closing transmission channel\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn prudent_limits_data_duration() -> Result<()> {
+        let read = Cursor::new(concat!(
+            "ehlo macca\r\n",
+            "mail from:<>\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "data\r\n",
+            "Subject: slow\r\n",
+        ))
+        .chain(DelayRead::new(10000, Cursor::new(".\r\n")));
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder
+            + Name::new("prudic")
+            + Esmtp.with(SmtpParser)
+            + NullDispatch
+            + Prudence::default().with_max_data_duration(Duration::from_millis(50));
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        let mut replies = vec![];
+        while let Ok(reply) = writes.recv().await {
+            replies.push(String::from_utf8_lossy(reply.as_slice()).to_string());
+        }
+        assert_eq!(replies.len(), 6, "{:?}", replies);
+        assert_eq!(
+            replies[5],
+            "421 prudic service not available, closing transmission channel\r\n"
+        );
+
+        Ok(())
+    }
+
     struct DelayRead<R> {
         delay: Option<Pin<Box<dyn Future<Output = ()> + Sync + Send>>>,
         inner: R,